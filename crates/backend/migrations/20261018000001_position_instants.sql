@@ -0,0 +1,17 @@
+-- Positions become a pair of instants so shifts can cross midnight. Existing rows which end at or
+-- before their start time are taken to finish the next day.
+ALTER TABLE positions
+    ADD COLUMN starts_at TIMESTAMPTZ,
+    ADD COLUMN ends_at TIMESTAMPTZ;
+
+UPDATE positions SET
+    starts_at = (date + start_time) AT TIME ZONE 'UTC',
+    ends_at = (date + end_time + CASE WHEN end_time <= start_time THEN INTERVAL '1 day' ELSE INTERVAL '0' END) AT TIME ZONE 'UTC';
+
+ALTER TABLE positions
+    ALTER COLUMN starts_at SET NOT NULL,
+    ALTER COLUMN ends_at SET NOT NULL,
+    DROP COLUMN date,
+    DROP COLUMN start_time,
+    DROP COLUMN end_time,
+    ADD CONSTRAINT positions_ends_after_start CHECK (ends_at > starts_at);
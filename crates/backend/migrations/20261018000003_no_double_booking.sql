@@ -0,0 +1,41 @@
+-- A user can't be assigned to two positions which overlap in time, unless an admin marks one of
+-- the assignments as an intentional overlap. Each assignment keeps a copy of its position's time
+-- range so an exclusion constraint can enforce this.
+CREATE EXTENSION IF NOT EXISTS btree_gist;
+
+ALTER TABLE scheduled_positions
+    ADD COLUMN allow_overlap BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN during TSTZRANGE;
+
+UPDATE scheduled_positions sp
+SET during = tstzrange(p.starts_at, p.ends_at)
+FROM positions p
+WHERE p.id = sp.position_id;
+
+ALTER TABLE scheduled_positions
+    ALTER COLUMN during SET NOT NULL,
+    ADD CONSTRAINT scheduled_positions_no_overlap
+        EXCLUDE USING gist (user_id WITH =, during WITH &&) WHERE (NOT allow_overlap);
+
+CREATE FUNCTION scheduled_positions_set_during() RETURNS trigger AS $$
+BEGIN
+    SELECT tstzrange(starts_at, ends_at) INTO NEW.during FROM positions WHERE id = NEW.position_id;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scheduled_positions_set_during
+BEFORE INSERT OR UPDATE ON scheduled_positions
+FOR EACH ROW EXECUTE FUNCTION scheduled_positions_set_during();
+
+CREATE FUNCTION positions_update_during() RETURNS trigger AS $$
+BEGIN
+    UPDATE scheduled_positions SET during = tstzrange(NEW.starts_at, NEW.ends_at)
+    WHERE position_id = NEW.id;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER positions_update_during
+AFTER UPDATE OF starts_at, ends_at ON positions
+FOR EACH ROW EXECUTE FUNCTION positions_update_during();
@@ -0,0 +1,23 @@
+-- Positions say how many people they need. Existing positions need one person but accept as many
+-- as are already assigned.
+ALTER TABLE positions
+    ADD COLUMN min_headcount BIGINT NOT NULL DEFAULT 1,
+    ADD COLUMN max_headcount BIGINT NOT NULL DEFAULT 1;
+
+UPDATE positions p
+SET max_headcount = GREATEST(1, (SELECT count(*) FROM scheduled_positions sp WHERE sp.position_id = p.id));
+
+ALTER TABLE positions
+    ADD CONSTRAINT positions_headcount CHECK (0 <= min_headcount AND min_headcount <= max_headcount);
+
+CREATE TABLE position_slots (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    position_id BIGINT NOT NULL REFERENCES positions,
+	role VARCHAR(64) NOT NULL,
+    min_headcount BIGINT NOT NULL DEFAULT 1,
+    max_headcount BIGINT NOT NULL DEFAULT 1,
+    UNIQUE (position_id, role),
+    CONSTRAINT position_slots_headcount CHECK (0 <= min_headcount AND min_headcount <= max_headcount)
+);
+
+ALTER TABLE scheduled_positions ADD COLUMN slot_id BIGINT REFERENCES position_slots;
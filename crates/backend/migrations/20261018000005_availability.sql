@@ -0,0 +1,19 @@
+CREATE TABLE availability_windows (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    weekday BIGINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    effective_from DATE,
+    effective_to DATE,
+    CHECK (effective_to >= effective_from)
+);
+
+CREATE TABLE unavailable_periods (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    reason VARCHAR(1024),
+    CHECK (ends_at > starts_at)
+);
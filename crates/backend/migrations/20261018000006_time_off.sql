@@ -0,0 +1,22 @@
+CREATE TABLE time_off_requests (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('vacation', 'sick', 'personal', 'other')),
+    starts_at TIMESTAMPTZ NOT NULL,
+    ends_at TIMESTAMPTZ NOT NULL,
+    reason VARCHAR(1024),
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'cancelled')),
+    CHECK (ends_at > starts_at)
+);
+
+CREATE TABLE time_off_events (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    request_id BIGINT NOT NULL REFERENCES time_off_requests,
+    actor_id BIGINT NOT NULL REFERENCES users,
+    status VARCHAR(16) NOT NULL,
+    note VARCHAR(1024),
+    created_at TIMESTAMPTZ NOT NULL
+);
+
+ALTER TABLE scheduled_positions ADD COLUMN needs_reassignment BOOLEAN NOT NULL DEFAULT false;
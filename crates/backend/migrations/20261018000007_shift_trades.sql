@@ -0,0 +1,21 @@
+ALTER TABLE teams ADD COLUMN trades_need_approval BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE shift_trades (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    assignment_id BIGINT NOT NULL REFERENCES scheduled_positions,
+    offered_by BIGINT NOT NULL REFERENCES users,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('give_away', 'swap')),
+    taken_by BIGINT REFERENCES users,
+    counter_assignment_id BIGINT REFERENCES scheduled_positions,
+    status VARCHAR(16) NOT NULL DEFAULT 'open'
+        CHECK (status IN ('open', 'pending', 'completed', 'declined', 'cancelled', 'reversed')),
+    CHECK (kind = 'swap' OR counter_assignment_id IS NULL)
+);
+
+CREATE TABLE shift_trade_events (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    trade_id BIGINT NOT NULL REFERENCES shift_trades,
+    actor_id BIGINT NOT NULL REFERENCES users,
+    status VARCHAR(16) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
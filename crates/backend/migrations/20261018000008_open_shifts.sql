@@ -0,0 +1,44 @@
+-- Assignments can't take a position, or the slot they fill, past its maximum headcount. The
+-- position is locked first so concurrent assignments to its last place queue up behind each other.
+CREATE FUNCTION scheduled_positions_check_headcount() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND NEW.position_id = OLD.position_id
+        AND NEW.slot_id IS NOT DISTINCT FROM OLD.slot_id THEN
+        RETURN NEW;
+    END IF;
+
+    PERFORM 1 FROM positions WHERE id = NEW.position_id FOR UPDATE;
+    IF (SELECT count(*) FROM scheduled_positions WHERE position_id = NEW.position_id AND id <> NEW.id)
+            >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+        OR (SELECT count(*) FROM scheduled_positions WHERE slot_id = NEW.slot_id AND id <> NEW.id)
+            >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id) THEN
+        RAISE EXCEPTION 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"'
+            USING ERRCODE = 'check_violation';
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scheduled_positions_check_headcount
+BEFORE INSERT OR UPDATE ON scheduled_positions
+FOR EACH ROW EXECUTE FUNCTION scheduled_positions_check_headcount();
+
+CREATE TABLE open_shifts (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    position_id BIGINT NOT NULL UNIQUE REFERENCES positions,
+    claim_mode VARCHAR(16) NOT NULL DEFAULT 'first_come'
+        CHECK (claim_mode IN ('first_come', 'approval')),
+    claim_deadline TIMESTAMPTZ,
+    published_by BIGINT NOT NULL REFERENCES users
+);
+
+CREATE TABLE shift_claims (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    open_shift_id BIGINT NOT NULL REFERENCES open_shifts,
+    user_id BIGINT NOT NULL REFERENCES users,
+    slot_id BIGINT REFERENCES position_slots,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'withdrawn')),
+    assignment_id BIGINT REFERENCES scheduled_positions,
+    created_at TIMESTAMPTZ NOT NULL
+);
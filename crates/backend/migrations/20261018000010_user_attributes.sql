@@ -0,0 +1,32 @@
+-- Typed facts about users, in place of the sketch in the base schema. Values are stored as text
+-- in a canonical form for their type's datatype.
+CREATE TABLE attribute_types (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    name VARCHAR(128) NOT NULL UNIQUE,
+    datatype VARCHAR(16) NOT NULL CHECK (datatype IN ('text', 'number', 'boolean', 'date', 'enum'))
+);
+
+CREATE TABLE attribute_choices (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    attribute_type_id BIGINT NOT NULL REFERENCES attribute_types,
+    value VARCHAR(256) NOT NULL,
+    UNIQUE (attribute_type_id, value)
+);
+
+CREATE TABLE user_attributes (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    attribute_type_id BIGINT NOT NULL REFERENCES attribute_types,
+    value VARCHAR(1024) NOT NULL,
+    UNIQUE (user_id, attribute_type_id)
+);
+
+-- Positions can require an attribute value.
+ALTER TABLE position_constraints
+    ADD COLUMN attribute_type_id BIGINT REFERENCES attribute_types,
+    ADD COLUMN attribute_value VARCHAR(1024),
+    DROP CONSTRAINT position_constraints_kind,
+    ADD CONSTRAINT position_constraints_kind
+        CHECK (kind IN ('minimum_age', 'different_person', 'attribute')),
+    ADD CONSTRAINT position_constraints_attribute
+        CHECK ((kind = 'attribute') = (attribute_type_id IS NOT NULL AND attribute_value IS NOT NULL));
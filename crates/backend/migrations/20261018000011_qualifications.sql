@@ -0,0 +1,22 @@
+CREATE TABLE qualifications (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    name VARCHAR(128) NOT NULL UNIQUE,
+    description VARCHAR(1024)
+);
+
+CREATE TABLE user_qualifications (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    qualification_id BIGINT NOT NULL REFERENCES qualifications,
+    issued_on DATE NOT NULL,
+    expires_on DATE,
+    evidence VARCHAR(1024),
+    CHECK (expires_on IS NULL OR expires_on >= issued_on)
+);
+
+CREATE TABLE position_qualifications (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    position_id BIGINT NOT NULL REFERENCES positions,
+    qualification_id BIGINT NOT NULL REFERENCES qualifications,
+    UNIQUE (position_id, qualification_id)
+);
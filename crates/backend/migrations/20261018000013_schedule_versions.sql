@@ -0,0 +1,37 @@
+-- Published schedules are snapshots of a team's assignments for a period. They're never changed
+-- afterwards; rolling back publishes an older snapshot again as a new version.
+CREATE TABLE schedule_versions (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    starts_on DATE NOT NULL,
+    ends_on DATE NOT NULL,
+    number BIGINT NOT NULL,
+    published_at TIMESTAMPTZ NOT NULL,
+    published_by BIGINT NOT NULL REFERENCES users,
+    rollback_of BIGINT REFERENCES schedule_versions,
+    UNIQUE (team_id, starts_on, ends_on, number),
+    CHECK (starts_on <= ends_on)
+);
+
+-- Positions aren't foreign keys, so they can still be deleted once published.
+CREATE TABLE version_assignments (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    version_id BIGINT NOT NULL REFERENCES schedule_versions,
+    position_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users,
+    slot_id BIGINT
+);
+
+CREATE FUNCTION schedule_versions_immutable() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'published schedule versions can''t be changed';
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER schedule_versions_immutable
+BEFORE UPDATE OR DELETE ON schedule_versions
+FOR EACH ROW EXECUTE FUNCTION schedule_versions_immutable();
+
+CREATE TRIGGER version_assignments_immutable
+BEFORE UPDATE OR DELETE ON version_assignments
+FOR EACH ROW EXECUTE FUNCTION schedule_versions_immutable();
@@ -0,0 +1,10 @@
+ALTER TABLE positions ADD COLUMN adults_only BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Rules can be limited to minors, and only flag breaches rather than block them. For adults_only
+-- the threshold is the age of an adult.
+ALTER TABLE labor_rules
+    ADD COLUMN under_age BIGINT CHECK (under_age > 0),
+    ADD COLUMN blocking BOOLEAN NOT NULL DEFAULT TRUE,
+    DROP CONSTRAINT labor_rules_kind_check,
+    ADD CONSTRAINT labor_rules_kind_check
+        CHECK (kind IN ('max_hours_per_day', 'max_hours_per_week', 'min_rest', 'max_consecutive_days', 'break_after', 'latest_end', 'adults_only'));
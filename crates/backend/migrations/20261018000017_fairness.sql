@@ -0,0 +1,19 @@
+-- What counts as an undesirable shift on each team. Nights run from night_start to night_end,
+-- past midnight if night_end is earlier, and are off if either is unset.
+CREATE TABLE fairness_settings (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    team_id BIGINT NOT NULL UNIQUE REFERENCES teams,
+    weekends BOOLEAN NOT NULL,
+    night_start TIME,
+    night_end TIME,
+    holidays BOOLEAN NOT NULL
+);
+
+-- How many minutes a week each member of a team is meant to work.
+CREATE TABLE hours_targets (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    user_id BIGINT NOT NULL REFERENCES users,
+    weekly_minutes BIGINT NOT NULL CHECK (weekly_minutes >= 0),
+    UNIQUE (team_id, user_id)
+);
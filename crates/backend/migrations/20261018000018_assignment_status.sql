@@ -0,0 +1,47 @@
+-- Assignments move through a lifecycle from proposed to completed. Declined and cancelled ones
+-- are kept for their history but no longer hold a place, so they don't count towards headcounts
+-- or double-booking.
+ALTER TABLE scheduled_positions
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'assigned'
+        CHECK (status IN ('proposed', 'assigned', 'confirmed', 'declined', 'cancelled', 'completed', 'no_show'));
+
+ALTER TABLE scheduled_positions
+    DROP CONSTRAINT scheduled_positions_no_overlap,
+    ADD CONSTRAINT scheduled_positions_no_overlap
+        EXCLUDE USING gist (user_id WITH =, during WITH &&)
+        WHERE (NOT allow_overlap AND status NOT IN ('declined', 'cancelled'));
+
+CREATE OR REPLACE FUNCTION scheduled_positions_check_headcount() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status IN ('declined', 'cancelled') THEN
+        RETURN NEW;
+    END IF;
+    IF TG_OP = 'UPDATE' AND NEW.position_id = OLD.position_id
+        AND NEW.slot_id IS NOT DISTINCT FROM OLD.slot_id
+        AND OLD.status NOT IN ('declined', 'cancelled') THEN
+        RETURN NEW;
+    END IF;
+
+    PERFORM 1 FROM positions WHERE id = NEW.position_id FOR UPDATE;
+    IF (SELECT count(*) FROM scheduled_positions
+            WHERE position_id = NEW.position_id AND id <> NEW.id
+                AND status NOT IN ('declined', 'cancelled'))
+            >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+        OR (SELECT count(*) FROM scheduled_positions
+            WHERE slot_id = NEW.slot_id AND id <> NEW.id
+                AND status NOT IN ('declined', 'cancelled'))
+            >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id) THEN
+        RAISE EXCEPTION 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"'
+            USING ERRCODE = 'check_violation';
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TABLE assignment_events (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    assignment_id BIGINT NOT NULL REFERENCES scheduled_positions,
+    actor_id BIGINT NOT NULL REFERENCES users,
+    status VARCHAR(16) NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,8 @@
+CREATE TABLE shift_preferences (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users,
+    weekday BIGINT NOT NULL CHECK (weekday BETWEEN 0 AND 6),
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    prefers BOOLEAN NOT NULL
+);
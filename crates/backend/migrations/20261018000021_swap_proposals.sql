@@ -0,0 +1,5 @@
+-- A swap is proposed to the user who offered the shift before it settles.
+ALTER TABLE shift_trades
+    DROP CONSTRAINT shift_trades_status_check,
+    ADD CONSTRAINT shift_trades_status_check
+        CHECK (status IN ('open', 'proposed', 'pending', 'completed', 'declined', 'cancelled', 'reversed'));
@@ -0,0 +1,39 @@
+-- An admin's permission to overlap covers the assignment it was given for, not later ones: an
+-- assignment without it can't overlap any other, overridden or not. The exclusion constraint
+-- still keeps assignments without an override apart, and this trigger checks them against
+-- overridden ones, which the constraint can't see.
+CREATE FUNCTION scheduled_positions_check_overrides() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status IN ('declined', 'cancelled') THEN
+        RETURN NEW;
+    END IF;
+
+    -- Assignments for the same user take turns, so none misses one which isn't committed yet.
+    PERFORM 1 FROM users WHERE id = NEW.user_id FOR UPDATE;
+    IF NEW.allow_overlap THEN
+        RETURN NEW;
+    END IF;
+    IF TG_OP = 'UPDATE' AND NEW.user_id = OLD.user_id AND NEW.position_id = OLD.position_id
+        AND NOT OLD.allow_overlap AND OLD.status NOT IN ('declined', 'cancelled') THEN
+        RETURN NEW;
+    END IF;
+
+    IF EXISTS (
+        SELECT 1 FROM scheduled_positions sp, positions q
+        WHERE q.id = NEW.position_id
+            AND sp.user_id = NEW.user_id
+            AND sp.id <> NEW.id
+            AND sp.allow_overlap
+            AND sp.status NOT IN ('declined', 'cancelled')
+            AND sp.during && tstzrange(q.starts_at, q.ends_at)
+    ) THEN
+        RAISE EXCEPTION 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"'
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'scheduled_positions_no_overlap';
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scheduled_positions_check_overrides
+BEFORE INSERT OR UPDATE OF user_id, position_id, allow_overlap, status ON scheduled_positions
+FOR EACH ROW EXECUTE FUNCTION scheduled_positions_check_overrides();
@@ -0,0 +1,30 @@
+-- Moving a position only rewrites its assignments' `during`, which the override check on
+-- scheduled_positions doesn't watch, so an assignment without an override could be moved onto
+-- an overridden one. Check the moved position's assignments here as well.
+CREATE FUNCTION positions_check_overrides() RETURNS trigger AS $$
+BEGIN
+    PERFORM 1 FROM users
+    WHERE id IN (SELECT user_id FROM scheduled_positions WHERE position_id = NEW.id)
+    FOR UPDATE;
+
+    IF EXISTS (
+        SELECT 1 FROM scheduled_positions a, scheduled_positions b
+        WHERE a.position_id = NEW.id
+            AND b.user_id = a.user_id
+            AND b.position_id <> NEW.id
+            AND NOT a.allow_overlap
+            AND b.allow_overlap
+            AND a.status NOT IN ('declined', 'cancelled')
+            AND b.status NOT IN ('declined', 'cancelled')
+            AND b.during && tstzrange(NEW.starts_at, NEW.ends_at)
+    ) THEN
+        RAISE EXCEPTION 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"'
+            USING ERRCODE = 'exclusion_violation', CONSTRAINT = 'scheduled_positions_no_overlap';
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER positions_check_overrides
+AFTER UPDATE OF starts_at, ends_at ON positions
+FOR EACH ROW EXECUTE FUNCTION positions_check_overrides();
@@ -0,0 +1,39 @@
+CREATE TABLE users (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	username VARCHAR(64) NOT NULL UNIQUE, 
+    lastname VARCHAR(64), 
+    firstname VARCHAR(64),
+	email VARCHAR(128), 
+	password_hash VARCHAR(128), 
+    date_of_birth DATE,
+	admin BOOLEAN NOT NULL DEFAULT false,
+	active BOOLEAN NOT NULL DEFAULT true
+);
+
+CREATE TABLE teams (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name VARCHAR(64) NOT NULL, 
+	description VARCHAR(1024)
+);
+
+CREATE TABLE team_members (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL references teams,
+	user_id BIGINT NOT NULL references users,
+    manager BOOLEAN NOT NULL DEFAULT false
+);
+
+CREATE TABLE positions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	team_id BIGINT NOT NULL REFERENCES teams, 
+	name VARCHAR(128) NOT NULL,
+    date DATE NOT NULL,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL
+);
+
+CREATE TABLE scheduled_positions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL REFERENCES positions, 
+    user_id BIGINT NOT NULL REFERENCES users
+);
@@ -0,0 +1,19 @@
+CREATE TABLE recurring_positions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	team_id BIGINT NOT NULL REFERENCES teams,
+	name VARCHAR(128) NOT NULL,
+    start_time TIME NOT NULL,
+    end_time TIME NOT NULL,
+    start_date DATE NOT NULL,
+    end_date DATE,
+    rrule VARCHAR(256) NOT NULL
+);
+
+CREATE TABLE recurrence_exceptions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recurring_position_id BIGINT NOT NULL REFERENCES recurring_positions,
+    date DATE NOT NULL,
+    UNIQUE (recurring_position_id, date)
+);
+
+ALTER TABLE positions ADD COLUMN recurring_position_id BIGINT REFERENCES recurring_positions;
@@ -0,0 +1,26 @@
+-- Positions become a pair of instants so shifts can cross midnight. Existing rows which end at or
+-- before their start time are taken to finish the next day. SQLite can't add constraints to an
+-- existing table and migrations can't rebuild a referenced one, so triggers enforce them.
+ALTER TABLE positions ADD COLUMN starts_at DATETIME;
+ALTER TABLE positions ADD COLUMN ends_at DATETIME;
+
+UPDATE positions SET
+    starts_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', date || ' ' || start_time),
+    ends_at = strftime('%Y-%m-%dT%H:%M:%S+00:00', date || ' ' || end_time,
+        CASE WHEN end_time <= start_time THEN '+1 day' ELSE '+0 days' END);
+
+ALTER TABLE positions DROP COLUMN date;
+ALTER TABLE positions DROP COLUMN start_time;
+ALTER TABLE positions DROP COLUMN end_time;
+
+CREATE TRIGGER positions_ends_after_start_insert BEFORE INSERT ON positions
+WHEN NEW.starts_at IS NULL OR NEW.ends_at IS NULL OR NEW.ends_at <= NEW.starts_at
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: positions_ends_after_start');
+END;
+
+CREATE TRIGGER positions_ends_after_start_update BEFORE UPDATE ON positions
+WHEN NEW.starts_at IS NULL OR NEW.ends_at IS NULL OR NEW.ends_at <= NEW.starts_at
+BEGIN
+    SELECT RAISE(ABORT, 'CHECK constraint failed: positions_ends_after_start');
+END;
@@ -0,0 +1,49 @@
+-- A user can't be assigned to two positions which overlap in time, unless an admin marks one of
+-- the assignments as an intentional overlap. SQLite has no exclusion constraints, so triggers
+-- check new and moved assignments, and positions whose times change.
+ALTER TABLE scheduled_positions ADD COLUMN allow_overlap BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TRIGGER scheduled_positions_no_overlap_insert BEFORE INSERT ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND EXISTS (
+    SELECT 1 FROM scheduled_positions sp
+    JOIN positions p ON p.id = sp.position_id
+    JOIN positions q ON q.id = NEW.position_id
+    WHERE sp.user_id = NEW.user_id
+        AND NOT sp.allow_overlap
+        AND p.starts_at < q.ends_at
+        AND q.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER scheduled_positions_no_overlap_update BEFORE UPDATE ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND EXISTS (
+    SELECT 1 FROM scheduled_positions sp
+    JOIN positions p ON p.id = sp.position_id
+    JOIN positions q ON q.id = NEW.position_id
+    WHERE sp.user_id = NEW.user_id
+        AND sp.id <> NEW.id
+        AND NOT sp.allow_overlap
+        AND p.starts_at < q.ends_at
+        AND q.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER positions_no_overlap_update BEFORE UPDATE OF starts_at, ends_at ON positions
+WHEN EXISTS (
+    SELECT 1 FROM scheduled_positions a
+    JOIN scheduled_positions b ON b.user_id = a.user_id AND b.id <> a.id
+    JOIN positions p ON p.id = b.position_id
+    WHERE a.position_id = NEW.id
+        AND p.id <> NEW.id
+        AND NOT a.allow_overlap
+        AND NOT b.allow_overlap
+        AND p.starts_at < NEW.ends_at
+        AND NEW.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
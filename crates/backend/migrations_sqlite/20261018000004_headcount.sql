@@ -0,0 +1,20 @@
+-- Positions say how many people they need. Existing positions need one person but accept as many
+-- as are already assigned.
+ALTER TABLE positions ADD COLUMN min_headcount BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE positions ADD COLUMN max_headcount BIGINT NOT NULL DEFAULT 1
+    CONSTRAINT positions_headcount CHECK (0 <= min_headcount AND min_headcount <= max_headcount);
+
+UPDATE positions
+SET max_headcount = max(1, (SELECT count(*) FROM scheduled_positions sp WHERE sp.position_id = positions.id));
+
+CREATE TABLE position_slots (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL REFERENCES positions,
+	role VARCHAR(64) NOT NULL,
+    min_headcount BIGINT NOT NULL DEFAULT 1,
+    max_headcount BIGINT NOT NULL DEFAULT 1,
+    UNIQUE (position_id, role),
+    CONSTRAINT position_slots_headcount CHECK (0 <= min_headcount AND min_headcount <= max_headcount)
+);
+
+ALTER TABLE scheduled_positions ADD COLUMN slot_id BIGINT REFERENCES position_slots;
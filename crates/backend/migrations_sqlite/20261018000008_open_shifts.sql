@@ -0,0 +1,41 @@
+-- Assignments can't take a position, or the slot they fill, past its maximum headcount. SQLite
+-- only has one writer at a time, so concurrent assignments can't both see a free place.
+CREATE TRIGGER scheduled_positions_headcount_insert BEFORE INSERT ON scheduled_positions
+WHEN (SELECT count(*) FROM scheduled_positions WHERE position_id = NEW.position_id)
+        >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+    OR (SELECT count(*) FROM scheduled_positions WHERE slot_id = NEW.slot_id)
+        >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id)
+BEGIN
+    SELECT RAISE(ABORT, 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"');
+END;
+
+CREATE TRIGGER scheduled_positions_headcount_update BEFORE UPDATE ON scheduled_positions
+WHEN (NEW.position_id <> OLD.position_id OR NEW.slot_id IS NOT OLD.slot_id) AND (
+    (SELECT count(*) FROM scheduled_positions WHERE position_id = NEW.position_id AND id <> NEW.id)
+        >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+    OR (SELECT count(*) FROM scheduled_positions WHERE slot_id = NEW.slot_id AND id <> NEW.id)
+        >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id)
+)
+BEGIN
+    SELECT RAISE(ABORT, 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"');
+END;
+
+CREATE TABLE open_shifts (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL UNIQUE REFERENCES positions,
+    claim_mode VARCHAR(16) NOT NULL DEFAULT 'first_come'
+        CHECK (claim_mode IN ('first_come', 'approval')),
+    claim_deadline DATETIME,
+    published_by BIGINT NOT NULL REFERENCES users
+);
+
+CREATE TABLE shift_claims (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    open_shift_id BIGINT NOT NULL REFERENCES open_shifts,
+    user_id BIGINT NOT NULL REFERENCES users,
+    slot_id BIGINT REFERENCES position_slots,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'denied', 'withdrawn')),
+    assignment_id BIGINT REFERENCES scheduled_positions,
+    created_at DATETIME NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Rules a position puts on who may work it, in place of the sketch in the base schema. Which of
+-- the settings are filled in depends on the kind of constraint.
+CREATE TABLE position_constraints (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL REFERENCES positions,
+    kind VARCHAR(32) NOT NULL,
+    min_age BIGINT,
+    other_position_id BIGINT REFERENCES positions,
+    CONSTRAINT position_constraints_kind CHECK (kind IN ('minimum_age', 'different_person')),
+    CONSTRAINT position_constraints_min_age
+        CHECK ((kind = 'minimum_age') = (min_age IS NOT NULL AND min_age >= 0)),
+    CONSTRAINT position_constraints_other_position
+        CHECK ((kind = 'different_person') = (other_position_id IS NOT NULL AND other_position_id <> position_id))
+);
@@ -0,0 +1,47 @@
+-- Typed facts about users, in place of the sketch in the base schema. Values are stored as text
+-- in a canonical form for their type's datatype.
+CREATE TABLE attribute_types (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(128) NOT NULL UNIQUE,
+    datatype VARCHAR(16) NOT NULL CHECK (datatype IN ('text', 'number', 'boolean', 'date', 'enum'))
+);
+
+CREATE TABLE attribute_choices (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    attribute_type_id BIGINT NOT NULL REFERENCES attribute_types,
+    value VARCHAR(256) NOT NULL,
+    UNIQUE (attribute_type_id, value)
+);
+
+CREATE TABLE user_attributes (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id BIGINT NOT NULL REFERENCES users,
+    attribute_type_id BIGINT NOT NULL REFERENCES attribute_types,
+    value VARCHAR(1024) NOT NULL,
+    UNIQUE (user_id, attribute_type_id)
+);
+
+-- Positions can require an attribute value. SQLite can't change a table's checks, so it's rebuilt.
+CREATE TABLE position_constraints_new (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL REFERENCES positions,
+    kind VARCHAR(32) NOT NULL,
+    min_age BIGINT,
+    other_position_id BIGINT REFERENCES positions,
+    attribute_type_id BIGINT REFERENCES attribute_types,
+    attribute_value VARCHAR(1024),
+    CONSTRAINT position_constraints_kind
+        CHECK (kind IN ('minimum_age', 'different_person', 'attribute')),
+    CONSTRAINT position_constraints_min_age
+        CHECK ((kind = 'minimum_age') = (min_age IS NOT NULL AND min_age >= 0)),
+    CONSTRAINT position_constraints_other_position
+        CHECK ((kind = 'different_person') = (other_position_id IS NOT NULL AND other_position_id <> position_id)),
+    CONSTRAINT position_constraints_attribute
+        CHECK ((kind = 'attribute') = (attribute_type_id IS NOT NULL AND attribute_value IS NOT NULL))
+);
+
+INSERT INTO position_constraints_new (id, position_id, kind, min_age, other_position_id)
+SELECT id, position_id, kind, min_age, other_position_id FROM position_constraints;
+
+DROP TABLE position_constraints;
+ALTER TABLE position_constraints_new RENAME TO position_constraints;
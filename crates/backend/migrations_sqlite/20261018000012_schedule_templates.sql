@@ -0,0 +1,31 @@
+CREATE TABLE holidays (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    date DATE NOT NULL,
+    name VARCHAR(128) NOT NULL,
+    UNIQUE (team_id, date)
+);
+
+CREATE TABLE schedule_templates (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    name VARCHAR(128) NOT NULL,
+    UNIQUE (team_id, name)
+);
+
+CREATE TABLE template_positions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    template_id BIGINT NOT NULL REFERENCES schedule_templates,
+    name VARCHAR(128) NOT NULL,
+    start_offset BIGINT NOT NULL,
+    end_offset BIGINT NOT NULL,
+    min_headcount BIGINT NOT NULL,
+    max_headcount BIGINT NOT NULL,
+    CHECK (end_offset > start_offset)
+);
+
+CREATE TABLE template_assignments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    template_position_id BIGINT NOT NULL REFERENCES template_positions,
+    user_id BIGINT NOT NULL REFERENCES users
+);
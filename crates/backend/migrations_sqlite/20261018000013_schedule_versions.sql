@@ -0,0 +1,43 @@
+-- Published schedules are snapshots of a team's assignments for a period. They're never changed
+-- afterwards; rolling back publishes an older snapshot again as a new version.
+CREATE TABLE schedule_versions (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    starts_on DATE NOT NULL,
+    ends_on DATE NOT NULL,
+    number BIGINT NOT NULL,
+    published_at DATETIME NOT NULL,
+    published_by BIGINT NOT NULL REFERENCES users,
+    rollback_of BIGINT REFERENCES schedule_versions,
+    UNIQUE (team_id, starts_on, ends_on, number),
+    CHECK (starts_on <= ends_on)
+);
+
+-- Positions aren't foreign keys, so they can still be deleted once published.
+CREATE TABLE version_assignments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    version_id BIGINT NOT NULL REFERENCES schedule_versions,
+    position_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL REFERENCES users,
+    slot_id BIGINT
+);
+
+CREATE TRIGGER schedule_versions_no_update BEFORE UPDATE ON schedule_versions
+BEGIN
+    SELECT RAISE(ABORT, 'published schedule versions can''t be changed');
+END;
+
+CREATE TRIGGER schedule_versions_no_delete BEFORE DELETE ON schedule_versions
+BEGIN
+    SELECT RAISE(ABORT, 'published schedule versions can''t be changed');
+END;
+
+CREATE TRIGGER version_assignments_no_update BEFORE UPDATE ON version_assignments
+BEGIN
+    SELECT RAISE(ABORT, 'published schedule versions can''t be changed');
+END;
+
+CREATE TRIGGER version_assignments_no_delete BEFORE DELETE ON version_assignments
+BEGIN
+    SELECT RAISE(ABORT, 'published schedule versions can''t be changed');
+END;
@@ -0,0 +1,10 @@
+-- Thresholds are in minutes, apart from max_consecutive_days which counts days.
+CREATE TABLE labor_rules (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    kind VARCHAR(32) NOT NULL
+        CHECK (kind IN ('max_hours_per_day', 'max_hours_per_week', 'min_rest', 'max_consecutive_days', 'break_after')),
+    threshold BIGINT NOT NULL CHECK (threshold > 0),
+    break_minutes BIGINT CHECK (break_minutes > 0),
+    CHECK ((kind = 'break_after') = (break_minutes IS NOT NULL))
+);
@@ -0,0 +1,21 @@
+ALTER TABLE positions ADD COLUMN adults_only BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Rules can be limited to minors, and only flag breaches rather than block them. For adults_only
+-- the threshold is the age of an adult. SQLite can't change a CHECK, so the table is rebuilt.
+CREATE TABLE labor_rules_new (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL REFERENCES teams,
+    kind VARCHAR(32) NOT NULL
+        CHECK (kind IN ('max_hours_per_day', 'max_hours_per_week', 'min_rest', 'max_consecutive_days', 'break_after', 'latest_end', 'adults_only')),
+    threshold BIGINT NOT NULL CHECK (threshold > 0),
+    break_minutes BIGINT CHECK (break_minutes > 0),
+    under_age BIGINT CHECK (under_age > 0),
+    blocking BOOLEAN NOT NULL DEFAULT TRUE,
+    CHECK ((kind = 'break_after') = (break_minutes IS NOT NULL))
+);
+
+INSERT INTO labor_rules_new (id, team_id, kind, threshold, break_minutes)
+SELECT id, team_id, kind, threshold, break_minutes FROM labor_rules;
+
+DROP TABLE labor_rules;
+ALTER TABLE labor_rules_new RENAME TO labor_rules;
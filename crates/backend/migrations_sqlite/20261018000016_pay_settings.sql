@@ -0,0 +1,9 @@
+-- Overtime thresholds are in minutes.
+CREATE TABLE pay_settings (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    team_id BIGINT NOT NULL UNIQUE REFERENCES teams,
+    period_start DATE NOT NULL,
+    period_days BIGINT NOT NULL CHECK (period_days > 0),
+    daily_overtime_after BIGINT CHECK (daily_overtime_after > 0),
+    weekly_overtime_after BIGINT CHECK (weekly_overtime_after > 0)
+);
@@ -0,0 +1,100 @@
+-- Assignments move through a lifecycle from proposed to completed. Declined and cancelled ones
+-- are kept for their history but no longer hold a place, so the triggers checking headcounts and
+-- double-booking leave them out.
+ALTER TABLE scheduled_positions
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'assigned'
+        CHECK (status IN ('proposed', 'assigned', 'confirmed', 'declined', 'cancelled', 'completed', 'no_show'));
+
+DROP TRIGGER scheduled_positions_no_overlap_insert;
+DROP TRIGGER scheduled_positions_no_overlap_update;
+DROP TRIGGER positions_no_overlap_update;
+DROP TRIGGER scheduled_positions_headcount_insert;
+DROP TRIGGER scheduled_positions_headcount_update;
+
+CREATE TRIGGER scheduled_positions_no_overlap_insert BEFORE INSERT ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND NEW.status NOT IN ('declined', 'cancelled') AND EXISTS (
+    SELECT 1 FROM scheduled_positions sp
+    JOIN positions p ON p.id = sp.position_id
+    JOIN positions q ON q.id = NEW.position_id
+    WHERE sp.user_id = NEW.user_id
+        AND NOT sp.allow_overlap
+        AND sp.status NOT IN ('declined', 'cancelled')
+        AND p.starts_at < q.ends_at
+        AND q.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER scheduled_positions_no_overlap_update BEFORE UPDATE ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND NEW.status NOT IN ('declined', 'cancelled') AND EXISTS (
+    SELECT 1 FROM scheduled_positions sp
+    JOIN positions p ON p.id = sp.position_id
+    JOIN positions q ON q.id = NEW.position_id
+    WHERE sp.user_id = NEW.user_id
+        AND sp.id <> NEW.id
+        AND NOT sp.allow_overlap
+        AND sp.status NOT IN ('declined', 'cancelled')
+        AND p.starts_at < q.ends_at
+        AND q.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER positions_no_overlap_update BEFORE UPDATE OF starts_at, ends_at ON positions
+WHEN EXISTS (
+    SELECT 1 FROM scheduled_positions a
+    JOIN scheduled_positions b ON b.user_id = a.user_id AND b.id <> a.id
+    JOIN positions p ON p.id = b.position_id
+    WHERE a.position_id = NEW.id
+        AND p.id <> NEW.id
+        AND NOT a.allow_overlap
+        AND NOT b.allow_overlap
+        AND a.status NOT IN ('declined', 'cancelled')
+        AND b.status NOT IN ('declined', 'cancelled')
+        AND p.starts_at < NEW.ends_at
+        AND NEW.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER scheduled_positions_headcount_insert BEFORE INSERT ON scheduled_positions
+WHEN NEW.status NOT IN ('declined', 'cancelled') AND (
+    (SELECT count(*) FROM scheduled_positions
+        WHERE position_id = NEW.position_id AND status NOT IN ('declined', 'cancelled'))
+        >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+    OR (SELECT count(*) FROM scheduled_positions
+        WHERE slot_id = NEW.slot_id AND status NOT IN ('declined', 'cancelled'))
+        >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id)
+)
+BEGIN
+    SELECT RAISE(ABORT, 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"');
+END;
+
+CREATE TRIGGER scheduled_positions_headcount_update BEFORE UPDATE ON scheduled_positions
+WHEN NEW.status NOT IN ('declined', 'cancelled')
+    AND (NEW.position_id <> OLD.position_id OR NEW.slot_id IS NOT OLD.slot_id
+        OR OLD.status IN ('declined', 'cancelled'))
+    AND (
+        (SELECT count(*) FROM scheduled_positions
+            WHERE position_id = NEW.position_id AND id <> NEW.id
+                AND status NOT IN ('declined', 'cancelled'))
+            >= (SELECT max_headcount FROM positions WHERE id = NEW.position_id)
+        OR (SELECT count(*) FROM scheduled_positions
+            WHERE slot_id = NEW.slot_id AND id <> NEW.id
+                AND status NOT IN ('declined', 'cancelled'))
+            >= (SELECT max_headcount FROM position_slots WHERE id = NEW.slot_id)
+    )
+BEGIN
+    SELECT RAISE(ABORT, 'new row for relation "scheduled_positions" violates check constraint "scheduled_positions_headcount"');
+END;
+
+CREATE TABLE assignment_events (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    assignment_id BIGINT NOT NULL REFERENCES scheduled_positions,
+    actor_id BIGINT NOT NULL REFERENCES users,
+    status VARCHAR(16) NOT NULL,
+    created_at DATETIME NOT NULL
+);
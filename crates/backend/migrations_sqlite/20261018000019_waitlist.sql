@@ -0,0 +1,13 @@
+-- People waiting for a place on a full position, in the order they joined.
+CREATE TABLE waitlist_spots (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    position_id BIGINT NOT NULL REFERENCES positions,
+    user_id BIGINT NOT NULL REFERENCES users,
+    slot_id BIGINT REFERENCES position_slots,
+    status VARCHAR(16) NOT NULL DEFAULT 'waiting'
+        CHECK (status IN ('waiting', 'offered', 'accepted', 'declined', 'expired', 'withdrawn')),
+    offer_expires_at DATETIME,
+    assignment_id BIGINT REFERENCES scheduled_positions,
+    created_at DATETIME NOT NULL,
+    CHECK (status <> 'offered' OR offer_expires_at IS NOT NULL)
+);
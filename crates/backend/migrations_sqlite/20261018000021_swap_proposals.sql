@@ -0,0 +1,32 @@
+-- A swap is proposed to the user who offered the shift before it settles. SQLite can't change a
+-- CHECK, so the table is rebuilt, along with its events so their rows keep a table to refer to.
+CREATE TABLE shift_trades_new (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    assignment_id BIGINT NOT NULL REFERENCES scheduled_positions,
+    offered_by BIGINT NOT NULL REFERENCES users,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('give_away', 'swap')),
+    taken_by BIGINT REFERENCES users,
+    counter_assignment_id BIGINT REFERENCES scheduled_positions,
+    status VARCHAR(16) NOT NULL DEFAULT 'open'
+        CHECK (status IN ('open', 'proposed', 'pending', 'completed', 'declined', 'cancelled', 'reversed')),
+    CHECK (kind = 'swap' OR counter_assignment_id IS NULL)
+);
+
+CREATE TABLE shift_trade_events_new (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+    trade_id BIGINT NOT NULL REFERENCES shift_trades_new,
+    actor_id BIGINT NOT NULL REFERENCES users,
+    status VARCHAR(16) NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+INSERT INTO shift_trades_new (id, assignment_id, offered_by, kind, taken_by, counter_assignment_id, status)
+SELECT id, assignment_id, offered_by, kind, taken_by, counter_assignment_id, status FROM shift_trades;
+
+INSERT INTO shift_trade_events_new (id, trade_id, actor_id, status, created_at)
+SELECT id, trade_id, actor_id, status, created_at FROM shift_trade_events;
+
+DROP TABLE shift_trade_events;
+DROP TABLE shift_trades;
+ALTER TABLE shift_trades_new RENAME TO shift_trades;
+ALTER TABLE shift_trade_events_new RENAME TO shift_trade_events;
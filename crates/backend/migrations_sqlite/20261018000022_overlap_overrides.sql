@@ -0,0 +1,38 @@
+-- An admin's permission to overlap covers the assignment it was given for, not later ones: an
+-- assignment without it can't overlap any other, overridden or not. Updates are only checked
+-- when they move the assignment or give up its override, so an assignment made before an
+-- overlapping override can still change status.
+DROP TRIGGER scheduled_positions_no_overlap_insert;
+DROP TRIGGER scheduled_positions_no_overlap_update;
+
+CREATE TRIGGER scheduled_positions_no_overlap_insert BEFORE INSERT ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND NEW.status NOT IN ('declined', 'cancelled') AND EXISTS (
+    SELECT 1 FROM scheduled_positions sp
+    JOIN positions p ON p.id = sp.position_id
+    JOIN positions q ON q.id = NEW.position_id
+    WHERE sp.user_id = NEW.user_id
+        AND sp.status NOT IN ('declined', 'cancelled')
+        AND p.starts_at < q.ends_at
+        AND q.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
+
+CREATE TRIGGER scheduled_positions_no_overlap_update BEFORE UPDATE ON scheduled_positions
+WHEN NOT NEW.allow_overlap AND NEW.status NOT IN ('declined', 'cancelled')
+    AND (NEW.user_id <> OLD.user_id OR NEW.position_id <> OLD.position_id
+        OR OLD.allow_overlap OR OLD.status IN ('declined', 'cancelled'))
+    AND EXISTS (
+        SELECT 1 FROM scheduled_positions sp
+        JOIN positions p ON p.id = sp.position_id
+        JOIN positions q ON q.id = NEW.position_id
+        WHERE sp.user_id = NEW.user_id
+            AND sp.id <> NEW.id
+            AND sp.status NOT IN ('declined', 'cancelled')
+            AND p.starts_at < q.ends_at
+            AND q.starts_at < p.ends_at
+    )
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
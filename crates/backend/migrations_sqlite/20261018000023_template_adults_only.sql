@@ -0,0 +1,3 @@
+-- Templates remember which positions only adults may work, and copy that onto the positions
+-- they create.
+ALTER TABLE template_positions ADD COLUMN adults_only BOOLEAN NOT NULL DEFAULT FALSE;
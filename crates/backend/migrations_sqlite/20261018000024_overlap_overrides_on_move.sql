@@ -0,0 +1,19 @@
+-- An assignment without an override can't be moved onto any other, overridden or not.
+DROP TRIGGER positions_no_overlap_update;
+
+CREATE TRIGGER positions_no_overlap_update BEFORE UPDATE OF starts_at, ends_at ON positions
+WHEN EXISTS (
+    SELECT 1 FROM scheduled_positions a
+    JOIN scheduled_positions b ON b.user_id = a.user_id AND b.id <> a.id
+    JOIN positions p ON p.id = b.position_id
+    WHERE a.position_id = NEW.id
+        AND p.id <> NEW.id
+        AND NOT a.allow_overlap
+        AND a.status NOT IN ('declined', 'cancelled')
+        AND b.status NOT IN ('declined', 'cancelled')
+        AND p.starts_at < NEW.ends_at
+        AND NEW.starts_at < p.ends_at
+)
+BEGIN
+    SELECT RAISE(ABORT, 'conflicting key value violates exclusion constraint "scheduled_positions_no_overlap"');
+END;
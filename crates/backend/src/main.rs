@@ -3,13 +3,16 @@ use actix_web::{
     // cookie::{Key, SameSite},
     middleware::Logger,
     middleware::NormalizePath,
-    web::{scope, Data},
+    web::Data,
     App,
     HttpServer,
 };
 // use actix_web_lab::web::spa;
 
+// Model functions only the commented out routes would call are marked `#[allow(dead_code)]`.
 mod models;
+mod rrule;
+mod timezone;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -37,6 +40,32 @@ async fn main() -> std::io::Result<()> {
 
     // models::initialize_admin(&pool).await.unwrap();
 
+    // `backend export` writes every assignment to stdout as JSON Lines instead of serving, and
+    // `backend export --published` only what has been published.
+    if std::env::args().nth(1).as_deref() == Some("export") {
+        let published = std::env::args().nth(2).as_deref() == Some("--published");
+        let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+        return match models::export_assignments(&pool, published, &mut out).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Lapsed waitlist offers are passed down the line every minute.
+    actix_web::rt::spawn(models::expire_offers_every(
+        pool.clone(),
+        std::time::Duration::from_secs(60),
+    ));
+
+    // Recurring positions are kept materialised a few months ahead, checked hourly.
+    actix_web::rt::spawn(models::materialise_every(
+        pool.clone(),
+        std::time::Duration::from_secs(60 * 60),
+    ));
+
     HttpServer::new(move || {
         App::new()
             .wrap(NormalizePath::trim())
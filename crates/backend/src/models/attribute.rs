@@ -0,0 +1,490 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::ScheduleError;
+use super::store::{Store, Transactional};
+use super::user::User;
+
+const NOT_ADMIN: &str = "only admins may manage user attributes";
+
+text_enum! {
+    #[derive(Default)]
+    pub enum Datatype {
+        #[default]
+        Text => "text",
+        Number => "number",
+        Boolean => "boolean",
+        /// A date written like `2022-11-07`.
+        Date => "date",
+        /// One of the type's `AttributeChoice`s.
+        Enum => "enum",
+    }
+}
+
+/// A kind of fact admins record about users, like "speaks Spanish" or "forklift licence".
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct AttributeType {
+    #[primary_key]
+    pub id: i64,
+    #[unique]
+    pub name: String,
+    pub datatype: Datatype,
+}
+
+/// One of the values allowed for an `enum` attribute type.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(attribute_type_id, value)]
+pub struct AttributeChoice {
+    #[primary_key]
+    pub id: i64,
+    #[references(AttributeType)]
+    pub attribute_type_id: i64,
+    pub value: String,
+}
+
+/// A user's value for an attribute type, stored as text in a canonical form for its datatype.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(user_id, attribute_type_id)]
+pub struct UserAttribute {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    #[references(AttributeType)]
+    pub attribute_type_id: i64,
+    pub value: String,
+}
+
+/// An attribute value read as its datatype, so numbers and dates compare properly.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Typed {
+    Text(String),
+    Number(f64),
+    Boolean(bool),
+    Date(NaiveDate),
+}
+
+impl Typed {
+    /// The value as it's stored.
+    pub fn canonical(&self) -> String {
+        match self {
+            Typed::Text(s) => s.clone(),
+            Typed::Number(n) => n.to_string(),
+            Typed::Boolean(b) => b.to_string(),
+            Typed::Date(d) => d.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+impl AttributeType {
+    /// Defines the type, along with its `choices` if it's an enum. Only admins may define types.
+    /// The type and its choices are saved together, so a bad choice leaves nothing behind.
+    #[allow(dead_code)]
+    pub async fn define<S>(
+        &self,
+        store: &S,
+        by: &User,
+        choices: &[&str],
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<AttributeChoice> + Transactional,
+    {
+        by.require_admin("only admins may define attribute types")?;
+        if (self.datatype == Datatype::Enum) == choices.is_empty() {
+            return Err(ScheduleError::Invalid(
+                "enum attribute types need choices, and only they have them",
+            ));
+        }
+
+        let tx = store.begin().await?;
+        let ty = tx.insert(self).await?;
+        for value in choices {
+            tx.create(&AttributeChoice {
+                id: 0,
+                attribute_type_id: ty.id,
+                value: value.to_string(),
+            })
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(ty)
+    }
+
+    pub async fn by_name<S: Store<Self>>(store: &S, name: &str) -> Result<Self, sqlx::Error> {
+        store
+            .find(&Filter::new().eq("name", name))
+            .await?
+            .pop()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Reads `raw` as this type, failing if it isn't a valid value.
+    pub async fn parse<S: Store<AttributeChoice>>(
+        &self,
+        store: &S,
+        raw: &str,
+    ) -> Result<Typed, ScheduleError> {
+        let raw = raw.trim();
+        match self.datatype {
+            Datatype::Text => Ok(Typed::Text(raw.to_string())),
+            Datatype::Number => raw
+                .parse()
+                .ok()
+                .filter(|n: &f64| n.is_finite())
+                .map(Typed::Number)
+                .ok_or(ScheduleError::Invalid("value must be a number")),
+            Datatype::Boolean => raw
+                .parse()
+                .map(Typed::Boolean)
+                .map_err(|_| ScheduleError::Invalid("value must be true or false")),
+            Datatype::Date => NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .map(Typed::Date)
+                .map_err(|_| ScheduleError::Invalid("value must be a date like 2022-11-07")),
+            Datatype::Enum => {
+                let allowed = Store::<AttributeChoice>::exists(
+                    store,
+                    &Filter::new()
+                        .eq("attribute_type_id", self.id)
+                        .eq("value", raw),
+                )
+                .await?;
+                if allowed {
+                    Ok(Typed::Text(raw.to_string()))
+                } else {
+                    Err(ScheduleError::Invalid("value must be one of the choices"))
+                }
+            }
+        }
+    }
+
+    /// Reads a value which was validated when it was stored.
+    pub fn read(&self, stored: &str) -> Typed {
+        match self.datatype {
+            Datatype::Text | Datatype::Enum => None,
+            Datatype::Number => stored.parse().ok().map(Typed::Number),
+            Datatype::Boolean => stored.parse().ok().map(Typed::Boolean),
+            Datatype::Date => NaiveDate::parse_from_str(stored, "%Y-%m-%d")
+                .ok()
+                .map(Typed::Date),
+        }
+        .unwrap_or_else(|| Typed::Text(stored.to_string()))
+    }
+}
+
+impl UserAttribute {
+    /// Sets the user's value for the attribute type. Only admins may do this, since position
+    /// constraints rely on attributes and users shouldn't vouch for themselves. The value is
+    /// checked against the type and stored in its canonical form.
+    #[allow(dead_code)]
+    pub async fn set<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<AttributeType> + Store<AttributeChoice>,
+    {
+        by.require_admin(NOT_ADMIN)?;
+        let ty: AttributeType = store.get(self.attribute_type_id).await?;
+        let value = ty.parse(store, &self.value).await?.canonical();
+
+        let attribute = UserAttribute {
+            value,
+            ..self.clone()
+        };
+        Ok(store
+            .upsert(&["user_id", "attribute_type_id"], &attribute)
+            .await?)
+    }
+
+    #[allow(dead_code)]
+    pub async fn remove<S: Store<Self>>(&self, store: &S, by: &User) -> Result<(), ScheduleError> {
+        by.require_admin(NOT_ADMIN)?;
+        let existing: Self = store.get(self.id).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+
+    /// The user's value for `attribute_type_id`, if they have one.
+    pub async fn of<S: Store<Self>>(
+        store: &S,
+        user_id: i64,
+        attribute_type_id: i64,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        Ok(store
+            .find(
+                &Filter::new()
+                    .eq("user_id", user_id)
+                    .eq("attribute_type_id", attribute_type_id),
+            )
+            .await?
+            .pop())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A test on a user's attribute, like `{"attribute": "languages", "op": "eq", "value":
+/// "spanish"}`. Values compare as the attribute's datatype.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttributeCondition {
+    pub attribute: String,
+    pub op: Comparison,
+    pub value: String,
+}
+
+impl AttributeCondition {
+    fn matches(&self, stored: &Typed, wanted: &Typed) -> bool {
+        let ordering = stored.partial_cmp(wanted);
+        match self.op {
+            Comparison::Eq => ordering == Some(Ordering::Equal),
+            Comparison::Ne => ordering != Some(Ordering::Equal),
+            Comparison::Lt => ordering == Some(Ordering::Less),
+            Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Gt => ordering == Some(Ordering::Greater),
+            Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// Users whose attributes meet every condition. Users without an attribute don't meet
+/// conditions on it.
+#[allow(dead_code)]
+pub async fn users_matching<S>(
+    store: &S,
+    conditions: &[AttributeCondition],
+) -> Result<Vec<User>, ScheduleError>
+where
+    S: Store<User> + Store<AttributeType> + Store<AttributeChoice> + Store<UserAttribute>,
+{
+    let mut matching: Option<BTreeSet<i64>> = None;
+    for condition in conditions {
+        let ty = AttributeType::by_name(store, &condition.attribute).await?;
+        let wanted = ty.parse(store, &condition.value).await?;
+        let attributes: Vec<UserAttribute> = store
+            .find(&Filter::new().eq("attribute_type_id", ty.id))
+            .await?;
+        let users: BTreeSet<i64> = attributes
+            .iter()
+            .filter(|a| condition.matches(&ty.read(&a.value), &wanted))
+            .map(|a| a.user_id)
+            .collect();
+        matching = Some(match matching {
+            Some(so_far) => so_far.intersection(&users).copied().collect(),
+            None => users,
+        });
+    }
+
+    let mut users = Vec::new();
+    match matching {
+        Some(ids) => {
+            for id in ids {
+                users.push(Store::<User>::get(store, id).await?);
+            }
+        }
+        None => users = store.find(&Filter::new()).await?,
+    }
+
+    Ok(users)
+}
+
+#[cfg(test)]
+mod attribute_tests {
+    use crate::models::attribute::{
+        users_matching, AttributeChoice, AttributeCondition, AttributeType, Comparison, Datatype,
+        UserAttribute,
+    };
+    use crate::models::db_test;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::ScheduleError;
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::test_support::{people, user};
+    use crate::models::user::User;
+    use anyhow::Result;
+
+    /// User 1 is an admin. Types are 1 "forklift" (boolean), 2 "language" (enum), 3 "height"
+    /// (number) and 4 "first aid" (date).
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        people(&store, 3, &[]).await?;
+        let mut admin = user(1);
+        admin.admin = true;
+        store.update(&admin).await?;
+        let types = [
+            ("forklift", Datatype::Boolean, vec![]),
+            ("language", Datatype::Enum, vec!["english", "spanish"]),
+            ("height", Datatype::Number, vec![]),
+            ("first aid", Datatype::Date, vec![]),
+        ];
+        for (name, datatype, choices) in types {
+            AttributeType {
+                id: 0,
+                name: name.into(),
+                datatype,
+            }
+            .define(&store, &admin, &choices)
+            .await?;
+        }
+
+        Ok(store)
+    }
+
+    fn attribute(user_id: i64, attribute_type_id: i64, value: &str) -> UserAttribute {
+        UserAttribute {
+            id: 0,
+            user_id,
+            attribute_type_id,
+            value: value.into(),
+        }
+    }
+
+    fn condition(attribute: &str, op: Comparison, value: &str) -> AttributeCondition {
+        AttributeCondition {
+            attribute: attribute.into(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_define_types() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        let ty = AttributeType {
+            id: 0,
+            name: "shoe size".into(),
+            datatype: Datatype::Number,
+        };
+        assert!(matches!(
+            ty.define(&store, &user(2), &[]).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assert!(matches!(
+            ty.define(&store, &admin, &["9"]).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_failed_define_leaves_nothing() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        // A repeated choice fails without leaving the type half defined.
+        let shift = AttributeType {
+            id: 0,
+            name: "shift".into(),
+            datatype: Datatype::Enum,
+        };
+        assert!(shift.define(&store, &admin, &["day", "day"]).await.is_err());
+        assert!(AttributeType::by_name(&store, "shift").await.is_err());
+        assert!(
+            !Store::<AttributeChoice>::exists(&store, &Filter::new().eq("value", "day")).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_values_are_validated() -> Result<()> {
+        let store = setup().await?;
+        let by: User = store.get(1).await?;
+        for (type_id, value) in [(1, "yes"), (2, "french"), (3, "tall"), (4, "1/2/2023")] {
+            assert!(matches!(
+                attribute(2, type_id, value).set(&store, &by).await,
+                Err(ScheduleError::Invalid(_))
+            ));
+        }
+        // Users can't set their own attributes, since constraints rely on them.
+        assert!(matches!(
+            attribute(2, 1, "true").set(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+
+        let height = attribute(2, 3, " 180.0 ").set(&store, &by).await?;
+        assert_eq!("180", height.value);
+        // Setting it again replaces the value.
+        let height = attribute(2, 3, "181").set(&store, &by).await?;
+        let stored: UserAttribute = store.get(height.id).await?;
+        assert_eq!("181", stored.value);
+
+        assert!(matches!(
+            height.remove(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        height.remove(&store, &by).await?;
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_users_matching() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        for (user_id, type_id, value) in [
+            (2, 1, "true"),
+            (2, 2, "spanish"),
+            (2, 3, "9"),
+            (3, 2, "spanish"),
+            (3, 3, "10"),
+            (3, 4, "2022-06-01"),
+        ] {
+            attribute(user_id, type_id, value)
+                .set(&store, &admin)
+                .await?;
+        }
+
+        let ids = |users: Vec<User>| users.iter().map(|u| u.id).collect::<Vec<_>>();
+        let spanish = condition("language", Comparison::Eq, "spanish");
+        assert_eq!(
+            vec![2, 3],
+            ids(users_matching(&store, std::slice::from_ref(&spanish)).await?)
+        );
+        // 10 is more than 9 as a number, even though it isn't as text.
+        let tall = condition("height", Comparison::Gt, "9");
+        assert_eq!(
+            vec![3],
+            ids(users_matching(&store, &[spanish, tall]).await?)
+        );
+        let forklift = condition("forklift", Comparison::Eq, "true");
+        let recent_first_aid = condition("first aid", Comparison::Ge, "2022-01-01");
+        assert!(users_matching(&store, &[forklift, recent_first_aid])
+            .await?
+            .is_empty());
+        assert_eq!(3, users_matching(&store, &[]).await?.len());
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users")]
+        async fn test_create_user_attributes(pool) -> Result<()> {
+            let ty = AttributeType {
+                id: 0,
+                name: "forklift".into(),
+                datatype: Datatype::Boolean,
+            };
+            let ty = ty.insert(&pool).await?;
+            assert!(ty.create(&pool).await.is_err());
+
+            let a = attribute(1, ty.id, "true").insert(&pool).await?;
+            assert_eq!(a, UserAttribute::get(&pool, a.id).await?);
+            // One value per user and type.
+            assert!(a.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
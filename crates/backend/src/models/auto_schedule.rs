@@ -0,0 +1,715 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use super::availability::{self, Availability, ShiftPreference};
+use super::fairness::{FairnessSetting, HoursTarget, Undesirability};
+use super::holiday::Holiday;
+use super::labor_rule::{self, LaborRule};
+use super::position_constraint;
+use super::position_slot::PositionSlot;
+use super::positions::Position;
+use super::qualification;
+use super::resource::Filter;
+use super::scheduled_position::{ScheduleError, ScheduleStore, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+
+/// What to plan, and the limits on it.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanRequest {
+    pub team_id: i64,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Plans only depend on the seed and the data, so the same request gives the same plan.
+    pub seed: u64,
+    /// The most anyone may work between `from` and `to`, including shifts they already have.
+    pub max_hours: Option<i64>,
+}
+
+/// Proposed assignments for the team's understaffed positions starting between `from` and `to`.
+/// Existing assignments are kept, so the plan only ever adds to them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub request: PlanRequest,
+    pub assignments: Vec<ScheduledPosition>,
+    pub unfilled: Vec<Unfilled>,
+}
+
+/// Places the plan couldn't fill, and why nobody on the team could take them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unfilled {
+    pub position_id: i64,
+    pub slot_id: Option<i64>,
+    pub places: i64,
+    pub excluded: Vec<Exclusion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub user_id: i64,
+    pub reason: Reason,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// Already working this position.
+    AlreadyAssigned,
+    /// Working, or planned to work, an overlapping position.
+    Conflict,
+    /// Marked unavailable for some of it.
+    Unavailable,
+    /// Has approved time off during it.
+    OnLeave,
+    /// It would take them past the plan's maximum hours.
+    MaxHours,
+    /// They don't meet the position's constraints.
+    Constraint,
+    /// They lack a qualification the position requires.
+    Unqualified,
+    /// It would break one of the team's working-time rules.
+    LaborRule,
+}
+
+/// The result of accepting a plan. Assignments which no longer fit, because the schedule changed
+/// since the plan was made, are skipped.
+#[derive(Debug, Clone, Serialize)]
+pub struct Applied {
+    pub scheduled: Vec<ScheduledPosition>,
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Skipped {
+    pub assignment: ScheduledPosition,
+    pub error: String,
+}
+
+/// How far back the planner looks at who's had the undesirable shifts.
+const FAIRNESS_WEEKS: i64 = 4;
+
+/// How a candidate ranks for a place, lowest first: undesirable shifts they've had, whether it's
+/// outside their availability, how little they'd like it, their hours against their target, a
+/// tie breaker and their index.
+type Rank = (usize, (bool, i64), i64, u64, usize);
+
+/// A place on a position, or on one of its slots, still needing people.
+struct Place {
+    position: Position,
+    slot_id: Option<i64>,
+    places: i64,
+    order: u64,
+}
+
+/// The team's working-time rules, and the zone its days are read in.
+struct Rules {
+    rules: Vec<LaborRule>,
+    tz: Tz,
+}
+
+/// Someone who could be assigned, with the positions they're working or planned to work and
+/// their hours target over the plan, if they have one.
+struct Candidate {
+    user_id: i64,
+    born: Option<NaiveDate>,
+    working: Vec<Position>,
+    target: Option<Duration>,
+}
+
+impl Candidate {
+    /// How long they work between `from` and `to`.
+    fn worked(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Duration {
+        self.working
+            .iter()
+            .filter(|p| from <= p.starts_at && p.starts_at < to)
+            .fold(Duration::zero(), |total, p| total + p.duration())
+    }
+
+    /// How much of their target they work between `from` and `to`, in ten thousandths, like
+    /// the fairness report's target ratio. Without a target they're measured against `usual`.
+    fn share(&self, from: DateTime<Utc>, to: DateTime<Utc>, usual: Duration) -> i64 {
+        let target = self.target.unwrap_or(usual).num_minutes().max(1);
+        self.worked(from, to).num_minutes() * 10_000 / target
+    }
+
+    /// How many undesirable shifts they work between `from` and `to`.
+    fn undesirable(
+        &self,
+        undesirability: &Undesirability,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> usize {
+        self.working
+            .iter()
+            .filter(|p| from <= p.starts_at && p.starts_at < to)
+            .filter(|p| undesirability.of(p).any())
+            .count()
+    }
+}
+
+impl PlanRequest {
+    /// Plans assignments greedily, filling the places with fewest eligible people first. Each
+    /// goes to whoever it suits best: for undesirable shifts, whoever has had fewest of them over
+    /// the four weeks before and during the plan; then someone within their weekly
+    /// availability; then whoever most prefers the shift; then whoever has worked least so far
+    /// against their hours target, so the plan corrects imbalances. Members without a target are
+    /// measured against the average one, and if nobody has one, hours are compared as they are.
+    /// Ties are broken by the seed.
+    #[allow(dead_code)]
+    pub async fn plan<S>(&self, store: &S) -> Result<Plan, ScheduleError>
+    where
+        S: ScheduleStore
+            + Store<TeamMember>
+            + Store<FairnessSetting>
+            + Store<Holiday>
+            + Store<HoursTarget>
+            + Store<ShiftPreference>,
+    {
+        if self.to <= self.from {
+            return Err(ScheduleError::Invalid("plans must end after they start"));
+        }
+        let mut rng = SplitMix64(self.seed);
+        let team: Team = store.get(self.team_id).await?;
+        let rules = Rules {
+            rules: LaborRule::for_team(store, self.team_id).await?,
+            tz: team.tz()?,
+        };
+        let since = self.from - Duration::weeks(FAIRNESS_WEEKS);
+        let undesirability = Undesirability::load(
+            store,
+            self.team_id,
+            since.with_timezone(&rules.tz).date_naive(),
+            self.to.with_timezone(&rules.tz).date_naive(),
+        )
+        .await?;
+
+        let members: Vec<TeamMember> = store
+            .find(&Filter::new().eq("team_id", self.team_id))
+            .await?;
+        let mut candidates = Vec::new();
+        for m in members {
+            let assignments: Vec<ScheduledPosition> = store
+                .find(&ScheduledPosition::active().eq("user_id", m.user_id))
+                .await?;
+            let mut working = Vec::new();
+            for sp in assignments {
+                working.push(Store::<Position>::get(store, sp.position_id).await?);
+            }
+            let user: User = store.get(m.user_id).await?;
+            let target: Option<HoursTarget> = store
+                .find(
+                    &Filter::new()
+                        .eq("team_id", self.team_id)
+                        .eq("user_id", m.user_id),
+                )
+                .await?
+                .into_iter()
+                .next();
+            candidates.push(Candidate {
+                user_id: m.user_id,
+                born: user.date_of_birth,
+                working,
+                target: target.filter(|t| t.weekly_minutes > 0).map(|t| {
+                    Duration::minutes(
+                        t.weekly_minutes * (self.to - self.from).num_minutes()
+                            / Duration::weeks(1).num_minutes(),
+                    )
+                }),
+            });
+        }
+        let targets: Vec<Duration> = candidates.iter().filter_map(|c| c.target).collect();
+        let usual = match targets.len() {
+            0 => Duration::zero(),
+            n => targets.iter().fold(Duration::zero(), |total, t| total + *t) / n as i32,
+        };
+
+        let positions: Vec<Position> = store
+            .find(
+                &Filter::new()
+                    .eq("team_id", self.team_id)
+                    .ge("starts_at", self.from)
+                    .lt("starts_at", self.to),
+            )
+            .await?;
+        let mut places = Vec::new();
+        // Whether each candidate could work each position, leaving aside the plan so far.
+        let mut exclusions = BTreeMap::new();
+        // For those who could, whether it's outside their availability and how much they'd
+        // like it.
+        let mut suits = BTreeMap::new();
+        for position in positions {
+            let staffing = position.staffing(store).await?;
+            let mut open = Vec::new();
+            if staffing.slots.is_empty() {
+                open.push((None, position.min_headcount - staffing.assigned));
+            }
+            for slot in &staffing.slots {
+                let needed: PositionSlot = store.get(slot.slot_id).await?;
+                open.push((Some(slot.slot_id), needed.min_headcount - slot.assigned));
+            }
+            open.retain(|(_, places)| *places > 0);
+            if open.is_empty() {
+                continue;
+            }
+
+            for c in &candidates {
+                let availability = availability::check(store, c.user_id, &position).await?;
+                let mut reason = match availability {
+                    Availability::Available | Availability::OutsideWindows => None,
+                    Availability::Unavailable(_) => Some(Reason::Unavailable),
+                    Availability::OnLeave(_) => Some(Reason::OnLeave),
+                };
+                let assignment = ScheduledPosition {
+                    position_id: position.id,
+                    user_id: c.user_id,
+                    ..Default::default()
+                };
+                if reason.is_none()
+                    && !position_constraint::evaluate(store, &assignment)
+                        .await?
+                        .is_empty()
+                {
+                    reason = Some(Reason::Constraint);
+                }
+                if reason.is_none()
+                    && !qualification::missing(store, c.user_id, &position)
+                        .await?
+                        .is_empty()
+                {
+                    reason = Some(Reason::Unqualified);
+                }
+                if reason.is_none() {
+                    let outside = availability == Availability::OutsideWindows;
+                    let preference = availability::preference(store, c.user_id, &position).await?;
+                    suits.insert((position.id, c.user_id), (outside, -preference));
+                }
+                exclusions.insert((position.id, c.user_id), reason);
+            }
+            for (slot_id, places_left) in open {
+                places.push(Place {
+                    position: position.clone(),
+                    slot_id,
+                    places: places_left,
+                    order: rng.next(),
+                });
+            }
+        }
+
+        let eligible = |place: &Place| {
+            candidates
+                .iter()
+                .filter(|c| exclusions[&(place.position.id, c.user_id)].is_none())
+                .count()
+        };
+        places.sort_by_key(|p| (eligible(p), p.position.starts_at, p.order));
+
+        let mut plan = Plan {
+            request: self.clone(),
+            assignments: Vec::new(),
+            unfilled: Vec::new(),
+        };
+        for place in places {
+            let undesirable = undesirability.of(&place.position).any();
+            let mut left = place.places;
+            while left > 0 {
+                let mut excluded = Vec::new();
+                let mut best: Option<Rank> = None;
+                for (i, c) in candidates.iter().enumerate() {
+                    let reason = exclusions[&(place.position.id, c.user_id)]
+                        .or_else(|| self.check(c, &place.position, &rules));
+                    if let Some(reason) = reason {
+                        excluded.push(Exclusion {
+                            user_id: c.user_id,
+                            reason,
+                        });
+                        continue;
+                    }
+                    let had = if undesirable {
+                        c.undesirable(&undesirability, since, self.to)
+                    } else {
+                        0
+                    };
+                    let key = (
+                        had,
+                        suits[&(place.position.id, c.user_id)],
+                        c.share(self.from, self.to, usual),
+                        rng.next(),
+                        i,
+                    );
+                    if best.is_none_or(|b| key < b) {
+                        best = Some(key);
+                    }
+                }
+
+                match best {
+                    Some((_, _, _, _, i)) => {
+                        candidates[i].working.push(place.position.clone());
+                        plan.assignments.push(ScheduledPosition {
+                            position_id: place.position.id,
+                            user_id: candidates[i].user_id,
+                            slot_id: place.slot_id,
+                            ..Default::default()
+                        });
+                        left -= 1;
+                    }
+                    None => {
+                        plan.unfilled.push(Unfilled {
+                            position_id: place.position.id,
+                            slot_id: place.slot_id,
+                            places: left,
+                            excluded,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        plan.assignments
+            .sort_by_key(|sp| (sp.position_id, sp.slot_id, sp.user_id));
+        plan.unfilled.sort_by_key(|u| (u.position_id, u.slot_id));
+
+        Ok(plan)
+    }
+
+    /// The constraints which depend on the plan so far.
+    fn check(&self, candidate: &Candidate, position: &Position, rules: &Rules) -> Option<Reason> {
+        if candidate.working.iter().any(|p| p.id == position.id) {
+            return Some(Reason::AlreadyAssigned);
+        }
+        if candidate.working.iter().any(|p| p.overlaps(position)) {
+            return Some(Reason::Conflict);
+        }
+        let hours = candidate.worked(self.from, self.to) + position.duration();
+        if self
+            .max_hours
+            .is_some_and(|max| hours > Duration::hours(max))
+        {
+            return Some(Reason::MaxHours);
+        }
+        let broken = labor_rule::breaches(
+            &rules.rules,
+            rules.tz,
+            candidate.user_id,
+            candidate.born,
+            &candidate.working,
+            position,
+        );
+        (!broken.is_empty()).then_some(Reason::LaborRule)
+    }
+}
+
+impl Plan {
+    /// Accepts the plan on behalf of `by`, who must manage the team. Each assignment goes
+    /// through the usual scheduling checks.
+    #[allow(dead_code)]
+    pub async fn apply<S>(&self, store: &S, by: &User) -> Result<Applied, ScheduleError>
+    where
+        S: ScheduleStore + Store<TeamMember>,
+    {
+        TeamMember::require_manager(
+            store,
+            self.request.team_id,
+            by,
+            "only managers of the team may accept a plan",
+        )
+        .await?;
+
+        let mut applied = Applied {
+            scheduled: Vec::new(),
+            skipped: Vec::new(),
+        };
+        for sp in &self.assignments {
+            let position: Position = store.get(sp.position_id).await?;
+            if position.team_id != self.request.team_id {
+                return Err(ScheduleError::Invalid(
+                    "plan includes another team's positions",
+                ));
+            }
+            match sp.schedule(store, by).await {
+                Ok(scheduled) => applied.scheduled.push(scheduled.assignment),
+                Err(ScheduleError::Database(e)) => return Err(e.into()),
+                Err(e) => applied.skipped.push(Skipped {
+                    assignment: sp.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        Ok(applied)
+    }
+}
+
+/// SplitMix64, a small and well mixed generator, so plans don't depend on the platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod auto_schedule_tests {
+    use crate::models::auto_schedule::{Exclusion, PlanRequest, Reason};
+    use crate::models::availability::{AvailabilityWindow, ShiftPreference, UnavailablePeriod};
+    use crate::models::fairness::HoursTarget;
+    use crate::models::positions::Position;
+    use crate::models::resource::Filter;
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    /// Users 1 and 2 work on team 1, which user 3 manages, and there's a day shift on each of
+    /// the 7th to the 10th.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, false), (1, 2, false), (1, 3, true)]).await?;
+        for d in 7..=10 {
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: at(d, 9),
+                    ends_at: at(d, 17),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(store)
+    }
+
+    fn week(seed: u64) -> PlanRequest {
+        PlanRequest {
+            team_id: 1,
+            from: at(7, 0),
+            to: at(14, 0),
+            seed,
+            max_hours: None,
+        }
+    }
+
+    fn staff(plan: &[ScheduledPosition]) -> Vec<(i64, i64)> {
+        plan.iter().map(|sp| (sp.position_id, sp.user_id)).collect()
+    }
+
+    #[actix_web::test]
+    async fn test_plan_shares_shifts() -> Result<()> {
+        let store = setup().await?;
+        let plan = week(1).plan(&store).await?;
+        assert_eq!(4, plan.assignments.len());
+        assert!(plan.unfilled.is_empty());
+
+        // Everyone gets the same hours, and the same seed gives the same plan.
+        let mut per_user = [0; 3];
+        for sp in &plan.assignments {
+            per_user[sp.user_id as usize - 1] += 1;
+        }
+        per_user.sort();
+        assert_eq!([1, 1, 2], per_user);
+        assert_eq!(plan, week(1).plan(&store).await?);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_plan_follows_hours_targets() -> Result<()> {
+        let store = setup().await?;
+        // User 1 is meant to work three times as much as user 2, and user 3 has no target.
+        for (user_id, hours) in [(1, 24), (2, 8)] {
+            store
+                .create(&HoursTarget {
+                    id: 0,
+                    team_id: 1,
+                    user_id,
+                    weekly_minutes: hours * 60,
+                })
+                .await?;
+        }
+
+        // Once everyone has a shift, user 1 is furthest from their target.
+        for seed in 1..=5 {
+            let plan = week(seed).plan(&store).await?;
+            let shifts = |user_id| {
+                plan.assignments
+                    .iter()
+                    .filter(|sp| sp.user_id == user_id)
+                    .count()
+            };
+            assert_eq!((2, 1, 1), (shifts(1), shifts(2), shifts(3)));
+        }
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_plan_respects_constraints() -> Result<()> {
+        let store = setup().await?;
+        // Everyone but user 1 is off on the 7th, and user 1 already works the 8th.
+        for user_id in [2, 3] {
+            store
+                .create(&UnavailablePeriod {
+                    user_id,
+                    starts_at: at(7, 0),
+                    ends_at: at(8, 0),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        store
+            .create(&ScheduledPosition {
+                position_id: 2,
+                user_id: 1,
+                ..Default::default()
+            })
+            .await?;
+
+        let plan = PlanRequest {
+            max_hours: Some(16),
+            ..week(7)
+        }
+        .plan(&store)
+        .await?;
+        assert!(staff(&plan.assignments).contains(&(1, 1)));
+        assert!(!staff(&plan.assignments).iter().any(|(p, _)| *p == 2));
+        assert_eq!(3, plan.assignments.len());
+
+        // With nobody free, the explanation says why.
+        let plan = PlanRequest {
+            max_hours: Some(8),
+            ..week(7)
+        };
+        store
+            .create(&UnavailablePeriod {
+                user_id: 1,
+                starts_at: at(7, 12),
+                ends_at: at(7, 13),
+                ..Default::default()
+            })
+            .await?;
+        let plan = plan.plan(&store).await?;
+        assert_eq!(1, plan.unfilled[0].position_id);
+        assert_eq!(
+            vec![
+                Exclusion {
+                    user_id: 1,
+                    reason: Reason::Unavailable
+                },
+                Exclusion {
+                    user_id: 2,
+                    reason: Reason::Unavailable
+                },
+                Exclusion {
+                    user_id: 3,
+                    reason: Reason::Unavailable
+                },
+            ],
+            plan.unfilled[0].excluded
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_plan_follows_preferences() -> Result<()> {
+        let store = setup().await?;
+        // User 1 is only available on Tuesdays and would like to work them, and user 2 would
+        // like to work Mondays.
+        let nine_to_five = (NaiveTime::from_hms(9, 0, 0), NaiveTime::from_hms(17, 0, 0));
+        store
+            .create(&AvailabilityWindow {
+                user_id: 1,
+                weekday: 1,
+                start_time: nine_to_five.0,
+                end_time: nine_to_five.1,
+                ..Default::default()
+            })
+            .await?;
+        for (user_id, weekday) in [(1, 1), (2, 0)] {
+            store
+                .create(&ShiftPreference {
+                    id: 0,
+                    user_id,
+                    weekday,
+                    start_time: nine_to_five.0,
+                    end_time: nine_to_five.1,
+                    prefers: true,
+                })
+                .await?;
+        }
+
+        let two_days = PlanRequest {
+            to: at(9, 0),
+            ..week(5)
+        };
+        let plan = two_days.plan(&store).await?;
+        assert_eq!(vec![(1, 2), (2, 1)], staff(&plan.assignments));
+
+        // Working outside their availability is a last resort, not ruled out.
+        for user_id in [2, 3] {
+            store
+                .create(&UnavailablePeriod {
+                    user_id,
+                    starts_at: at(7, 0),
+                    ends_at: at(8, 0),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        let plan = two_days.plan(&store).await?;
+        assert_eq!(vec![(1, 1), (2, 1)], staff(&plan.assignments));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_apply_plan() -> Result<()> {
+        let store = setup().await?;
+        let plan = week(3).plan(&store).await?;
+        assert!(matches!(
+            plan.apply(&store, &user(1)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+
+        // Someone was assigned the 7th since the plan was made.
+        let taken = plan
+            .assignments
+            .iter()
+            .find(|sp| sp.position_id == 1)
+            .unwrap();
+        let other = if taken.user_id == 1 { 2 } else { 1 };
+        store
+            .create(&ScheduledPosition {
+                position_id: 1,
+                user_id: other,
+                ..Default::default()
+            })
+            .await?;
+
+        let applied = plan.apply(&store, &user(3)).await?;
+        assert_eq!(3, applied.scheduled.len());
+        assert_eq!(1, applied.skipped.len());
+        assert_eq!(
+            4,
+            Store::<ScheduledPosition>::count(&store, &Filter::new()).await?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{Filter, Record, Resource};
+use super::scheduled_position::ScheduleError;
+use super::store::Store;
+use super::team::Team;
+use super::time_off::TimeOffRequest;
+use super::user::User;
+use crate::timezone::to_instant;
+
+/// A weekly window when a user can work, in the time zone of whichever team schedules them.
+/// `weekday` counts from Monday as 0. An `end_time` at or before `start_time` runs into the next
+/// day. The window only applies between `effective_from` and `effective_to`, when set.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct AvailabilityWindow {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub weekday: i64,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub effective_from: Option<NaiveDate>,
+    pub effective_to: Option<NaiveDate>,
+}
+
+/// A one-off period when a user can't work, regardless of their weekly availability.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct UnavailablePeriod {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+/// A weekly time a user would rather work, or would rather not when `prefers` is false. Times
+/// read like an `AvailabilityWindow`'s. Preferences don't stop anyone being scheduled; they
+/// only steer the automatic planner.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct ShiftPreference {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub weekday: i64,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub prefers: bool,
+}
+
+/// Whether a user can work a position.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Availability {
+    /// Within their weekly windows, or they haven't given any.
+    Available,
+    /// Outside their weekly windows.
+    OutsideWindows,
+    /// During these unavailable periods.
+    Unavailable(Vec<UnavailablePeriod>),
+    /// During this approved time off.
+    OnLeave(Vec<TimeOffRequest>),
+}
+
+/// Records belonging to a single user, who may maintain them along with admins.
+#[allow(dead_code)]
+#[async_trait::async_trait]
+pub trait Owned: Record {
+    fn owner(&self) -> i64;
+
+    /// Creates or updates this record on behalf of `by`.
+    async fn save<S: Store<Self>>(&self, store: &S, by: &User) -> Result<Self, ScheduleError> {
+        check_owner(self.owner(), by)?;
+        if self.id() == 0 {
+            return Ok(store.insert(self).await?);
+        }
+
+        let existing: Self = store.get(self.id()).await?;
+        check_owner(existing.owner(), by)?;
+        store.update(self).await?;
+        Ok(self.clone())
+    }
+
+    async fn remove<S: Store<Self>>(&self, store: &S, by: &User) -> Result<(), ScheduleError> {
+        let existing: Self = store.get(self.id()).await?;
+        check_owner(existing.owner(), by)?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+}
+
+fn check_owner(owner: i64, by: &User) -> Result<(), ScheduleError> {
+    if owner == by.id || by.admin {
+        Ok(())
+    } else {
+        Err(ScheduleError::Forbidden(
+            "users may only change their own availability",
+        ))
+    }
+}
+
+impl Owned for AvailabilityWindow {
+    fn owner(&self) -> i64 {
+        self.user_id
+    }
+}
+
+impl Owned for UnavailablePeriod {
+    fn owner(&self) -> i64 {
+        self.user_id
+    }
+}
+
+impl Owned for ShiftPreference {
+    fn owner(&self) -> i64 {
+        self.user_id
+    }
+}
+
+impl AvailabilityWindow {
+    fn applies_on(&self, date: NaiveDate) -> bool {
+        date.weekday().num_days_from_monday() as i64 == self.weekday
+            && self.effective_from.into_iter().all(|from| from <= date)
+            && self.effective_to.into_iter().all(|to| date <= to)
+    }
+
+    /// When the window starts and ends on `date`, reading its times in `tz`.
+    fn on(&self, date: NaiveDate, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        span(self.start_time, self.end_time, date, tz)
+    }
+}
+
+impl ShiftPreference {
+    /// Whether the preference covers any of `position`, reading its times in `tz`.
+    fn touches(&self, position: &Position, tz: Tz) -> bool {
+        let mut date = position.starts_at.with_timezone(&tz).date_naive() - Duration::days(1);
+        let last = position.ends_at.with_timezone(&tz).date_naive();
+        while date <= last {
+            if date.weekday().num_days_from_monday() as i64 == self.weekday {
+                let (start, end) = span(self.start_time, self.end_time, date, tz);
+                if start < position.ends_at && position.starts_at < end {
+                    return true;
+                }
+            }
+            date += Duration::days(1);
+        }
+        false
+    }
+}
+
+/// When a weekly time from `start_time` to `end_time` falls on `date`, reading its times in
+/// `tz`. An `end_time` at or before `start_time` is on the next day.
+fn span(
+    start_time: NaiveTime,
+    end_time: NaiveTime,
+    date: NaiveDate,
+    tz: Tz,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let mut end_date = date;
+    if end_time <= start_time {
+        end_date += Duration::days(1);
+    }
+    (
+        to_instant(tz, date.and_time(start_time)),
+        to_instant(tz, end_date.and_time(end_time)),
+    )
+}
+
+/// How much `user_id` wants to work `position`: one for each of their preferences for it, less
+/// one for each against it.
+pub async fn preference<S>(store: &S, user_id: i64, position: &Position) -> Result<i64, sqlx::Error>
+where
+    S: Store<Team> + Store<ShiftPreference>,
+{
+    let preferences: Vec<ShiftPreference> =
+        store.find(&Filter::new().eq("user_id", user_id)).await?;
+    if preferences.is_empty() {
+        return Ok(0);
+    }
+
+    let team: Team = store.get(position.team_id).await?;
+    let tz = team.tz()?;
+    Ok(preferences
+        .iter()
+        .filter(|p| p.touches(position, tz))
+        .map(|p| if p.prefers { 1 } else { -1 })
+        .sum())
+}
+
+/// Checks `position` against the availability `user_id` has recorded.
+pub async fn check<S>(
+    store: &S,
+    user_id: i64,
+    position: &Position,
+) -> Result<Availability, sqlx::Error>
+where
+    S: Store<Team> + Store<AvailabilityWindow> + Store<UnavailablePeriod> + Store<TimeOffRequest>,
+{
+    let time_off = TimeOffRequest::approved_during(store, position, user_id).await?;
+    if !time_off.is_empty() {
+        return Ok(Availability::OnLeave(time_off));
+    }
+
+    let unavailable: Vec<UnavailablePeriod> = store
+        .find(
+            &Filter::new()
+                .eq("user_id", user_id)
+                .lt("starts_at", position.ends_at)
+                .gt("ends_at", position.starts_at),
+        )
+        .await?;
+    if !unavailable.is_empty() {
+        return Ok(Availability::Unavailable(unavailable));
+    }
+
+    let windows: Vec<AvailabilityWindow> =
+        store.find(&Filter::new().eq("user_id", user_id)).await?;
+    if windows.is_empty() {
+        return Ok(Availability::Available);
+    }
+
+    let team: Team = store.get(position.team_id).await?;
+    let tz = team.tz()?;
+
+    // Every window occurrence which could touch the position, starting the day before in case
+    // one runs overnight.
+    let mut date = position.starts_at.with_timezone(&tz).date_naive() - Duration::days(1);
+    let last = position.ends_at.with_timezone(&tz).date_naive();
+    let mut spans = Vec::new();
+    while date <= last {
+        for w in windows.iter().filter(|w| w.applies_on(date)) {
+            spans.push(w.on(date, tz));
+        }
+        date += Duration::days(1);
+    }
+    spans.sort();
+
+    // Merge back to back windows, then see if one covers the whole position.
+    let mut covered_until = None;
+    for (start, end) in spans {
+        covered_until = match covered_until {
+            Some(until) if start <= until => Some(std::cmp::max(until, end)),
+            _ if start <= position.starts_at => Some(end),
+            _ => break,
+        };
+    }
+
+    Ok(match covered_until {
+        Some(until) if until >= position.ends_at => Availability::Available,
+        _ => Availability::OutsideWindows,
+    })
+}
+
+#[cfg(test)]
+mod availability_tests {
+    use crate::models::availability::{
+        check, Availability, AvailabilityWindow, Owned, ShiftPreference, UnavailablePeriod,
+    };
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::ScheduleError;
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::user::User;
+    use anyhow::Result;
+    use chrono::{DateTime, NaiveTime, TimeZone, Utc};
+
+    /// 7 Nov 2022 is a Monday.
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    fn shift(starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> Position {
+        Position {
+            team_id: 1,
+            starts_at,
+            ends_at,
+            ..Default::default()
+        }
+    }
+
+    fn window(weekday: i64, start: u32, end: u32) -> AvailabilityWindow {
+        AvailabilityWindow {
+            user_id: 1,
+            weekday,
+            start_time: NaiveTime::from_hms(start, 0, 0),
+            end_time: NaiveTime::from_hms(end, 0, 0),
+            ..Default::default()
+        }
+    }
+
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        let mut user = User::default();
+        user.id = 1;
+        store.create(&user).await?;
+        Ok(store)
+    }
+
+    #[actix_web::test]
+    async fn test_check_windows() -> Result<()> {
+        let store = setup().await?;
+        assert_eq!(
+            Availability::Available,
+            check(&store, 1, &shift(at(7, 9), at(7, 17))).await?
+        );
+
+        // Mondays 08:00-12:00 and 12:00-18:00, and Monday nights into Tuesday.
+        store.create(&window(0, 8, 12)).await?;
+        store.create(&window(0, 12, 18)).await?;
+        store.create(&window(0, 22, 6)).await?;
+
+        let cases = [
+            (shift(at(7, 9), at(7, 17)), Availability::Available),
+            (shift(at(7, 7), at(7, 17)), Availability::OutsideWindows),
+            (shift(at(7, 23), at(8, 5)), Availability::Available),
+            (shift(at(8, 9), at(8, 17)), Availability::OutsideWindows),
+        ];
+        for (position, expected) in cases {
+            assert_eq!(expected, check(&store, 1, &position).await?);
+        }
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_check_effective_dates() -> Result<()> {
+        let store = setup().await?;
+        let from_next_week = AvailabilityWindow {
+            effective_from: Some(at(14, 0).date_naive()),
+            ..window(0, 8, 18)
+        };
+        store.create(&from_next_week).await?;
+
+        assert_eq!(
+            Availability::OutsideWindows,
+            check(&store, 1, &shift(at(7, 9), at(7, 17))).await?
+        );
+        assert_eq!(
+            Availability::Available,
+            check(&store, 1, &shift(at(14, 9), at(14, 17))).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_check_unavailable() -> Result<()> {
+        let store = setup().await?;
+        let dentist = UnavailablePeriod {
+            user_id: 1,
+            starts_at: at(7, 14),
+            ends_at: at(7, 15),
+            reason: Some("Dentist".into()),
+            ..Default::default()
+        };
+        let dentist = store.insert(&dentist).await?;
+
+        assert_eq!(
+            Availability::Unavailable(vec![dentist]),
+            check(&store, 1, &shift(at(7, 9), at(7, 17))).await?
+        );
+        assert_eq!(
+            Availability::Available,
+            check(&store, 1, &shift(at(7, 15), at(7, 17))).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_maintain_own_availability() -> Result<()> {
+        let store = setup().await?;
+        let mut owner = User::default();
+        owner.id = 1;
+        let mut other = User::default();
+        other.id = 2;
+
+        let mut w = window(0, 8, 18).save(&store, &owner).await?;
+        assert!(matches!(
+            window(1, 8, 18).save(&store, &other).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+
+        w.end_time = NaiveTime::from_hms(20, 0, 0);
+        w.save(&store, &owner).await?;
+        let saved: AvailabilityWindow = store.get(w.id).await?;
+        assert_eq!(NaiveTime::from_hms(20, 0, 0), saved.end_time);
+
+        assert!(w.remove(&store, &other).await.is_err());
+        w.remove(&store, &owner).await?;
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users")]
+        async fn test_create_availability(pool) -> Result<()> {
+            let w = window(0, 8, 18).insert(&pool).await?;
+            assert_eq!(w, AvailabilityWindow::get(&pool, w.id).await?);
+
+            // The database rejects weekdays out of range and periods ending before they start.
+            assert!(window(7, 8, 18).create(&pool).await.is_err());
+            let backwards = UnavailablePeriod {
+                user_id: 1,
+                starts_at: at(7, 15),
+                ends_at: at(7, 14),
+                ..Default::default()
+            };
+            assert!(backwards.create(&pool).await.is_err());
+
+            let preference = ShiftPreference {
+                id: 0,
+                user_id: 1,
+                weekday: 4,
+                start_time: NaiveTime::from_hms(22, 0, 0),
+                end_time: NaiveTime::from_hms(6, 0, 0),
+                prefers: false,
+            };
+            let preference = preference.insert(&pool).await?;
+            assert_eq!(preference, ShiftPreference::get(&pool, preference.id).await?);
+
+            Ok(())
+        }
+    }
+}
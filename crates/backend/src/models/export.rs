@@ -0,0 +1,98 @@
+use std::io::Write;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlx::{Database, Pool};
+
+use super::resource::{Filter, Resource};
+use super::schedule_version::VersionAssignment;
+use super::scheduled_position::ScheduledPosition;
+use super::Db;
+
+/// Writes every row matching `filter` to `writer` as JSON Lines. Rows are streamed from the
+/// database one at a time so memory use stays flat regardless of table size. Returns the number
+/// of rows written.
+pub async fn export_json_lines<DB, R, W>(
+    pool: &Pool<DB>,
+    filter: &Filter,
+    writer: &mut W,
+) -> Result<u64>
+where
+    DB: Database,
+    R: Resource<DB> + Serialize,
+    W: Write,
+{
+    let mut rows = R::stream(pool, filter);
+    let mut count = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        serde_json::to_writer(&mut *writer, &row)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    Ok(count)
+}
+
+/// Writes every assignment to `writer` as JSON Lines, for `backend export`. Returns the number
+/// of rows written. The live assignments include managers' drafts, so anything shared beyond
+/// them should be exported with `published`, which writes the assignments of every published
+/// schedule version instead.
+pub async fn export_assignments<W: Write>(db: &Db, published: bool, writer: &mut W) -> Result<u64> {
+    let all = Filter::new();
+    match (db, published) {
+        (Db::Postgres(store), false) => {
+            export_json_lines::<_, ScheduledPosition, _>(store.pool(), &all, writer).await
+        }
+        (Db::Postgres(store), true) => {
+            export_json_lines::<_, VersionAssignment, _>(store.pool(), &all, writer).await
+        }
+        (Db::Sqlite(store), false) => {
+            export_json_lines::<_, ScheduledPosition, _>(store.pool(), &all, writer).await
+        }
+        (Db::Sqlite(store), true) => {
+            export_json_lines::<_, VersionAssignment, _>(store.pool(), &all, writer).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod export_tests {
+    use crate::models::db_test;
+    use crate::models::export::export_json_lines;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::ScheduledPosition;
+    use anyhow::Result;
+    use futures::TryStreamExt;
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_stream_all(pool) -> Result<()> {
+            let streamed: Vec<ScheduledPosition> =
+                ScheduledPosition::stream_all(&pool).try_collect().await?;
+
+            assert_eq!(ScheduledPosition::get_all(&pool).await?, streamed);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_export_json_lines(pool) -> Result<()> {
+            let mut out = Vec::new();
+            let filter = Filter::new().eq("user_id", 2);
+            let count =
+                export_json_lines::<_, ScheduledPosition, _>(&pool, &filter, &mut out).await?;
+
+            assert_eq!(1, count);
+            let line = String::from_utf8(out)?;
+            let sp: ScheduledPosition = serde_json::from_str(line.trim_end())?;
+            assert_eq!(ScheduledPosition::get_all(&pool).await?[1], sp);
+
+            Ok(())
+        }
+    }
+}
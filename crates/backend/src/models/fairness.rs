@@ -0,0 +1,531 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::holiday::Holiday;
+use super::positions::Position;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone::{midnight, to_instant};
+
+const NOT_MANAGER: &str = "only managers of the team may change how it shares out shifts";
+const NOT_MANAGER_REPORT: &str = "only managers of the team may see how it shares out shifts";
+
+/// Which shifts a team counts as undesirable when sharing them out. Nights run from
+/// `night_start` to `night_end` in the team's time zone, past midnight if `night_end` is
+/// earlier, and don't count if either is unset.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct FairnessSetting {
+    #[primary_key]
+    pub id: i64,
+    #[unique]
+    #[references(Team)]
+    pub team_id: i64,
+    pub weekends: bool,
+    pub night_start: Option<NaiveTime>,
+    pub night_end: Option<NaiveTime>,
+    pub holidays: bool,
+}
+
+impl Default for FairnessSetting {
+    /// Weekends, holidays and anything between 10pm and 6am.
+    fn default() -> Self {
+        FairnessSetting {
+            id: 0,
+            team_id: 0,
+            weekends: true,
+            night_start: Some(NaiveTime::from_hms(22, 0, 0)),
+            night_end: Some(NaiveTime::from_hms(6, 0, 0)),
+            holidays: true,
+        }
+    }
+}
+
+impl FairnessSetting {
+    /// Saves the team's settings, replacing any it had, on behalf of `by`, who must manage the
+    /// team.
+    #[allow(dead_code)]
+    pub async fn set<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        if self.night_start.is_some() && self.night_start == self.night_end {
+            return Err(ScheduleError::Invalid("nights can't end when they start"));
+        }
+        TeamMember::require_manager(store, self.team_id, by, NOT_MANAGER).await?;
+
+        Ok(store.upsert(&["team_id"], self).await?)
+    }
+
+    /// The team's settings, or the default if it hasn't set one.
+    pub async fn for_team<S: Store<Self>>(store: &S, team_id: i64) -> Result<Self, sqlx::Error> {
+        let settings = store
+            .find(&Filter::new().eq("team_id", team_id))
+            .await?
+            .into_iter()
+            .next();
+        Ok(settings.unwrap_or(FairnessSetting {
+            team_id,
+            ..Default::default()
+        }))
+    }
+}
+
+/// How many minutes a week someone is meant to work on a team.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(team_id, user_id)]
+pub struct HoursTarget {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub weekly_minutes: i64,
+}
+
+impl HoursTarget {
+    /// Saves the target, replacing any the person had on the team, on behalf of `by`, who must
+    /// manage the team.
+    #[allow(dead_code)]
+    pub async fn set<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        if self.weekly_minutes < 0 {
+            return Err(ScheduleError::Invalid("hours targets can't be negative"));
+        }
+        TeamMember::require_manager(store, self.team_id, by, NOT_MANAGER).await?;
+
+        Ok(store.upsert(&["team_id", "user_id"], self).await?)
+    }
+}
+
+/// Why a shift is undesirable, if it is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Undesirable {
+    pub weekend: bool,
+    pub night: bool,
+    pub holiday: bool,
+}
+
+impl Undesirable {
+    pub fn any(&self) -> bool {
+        self.weekend || self.night || self.holiday
+    }
+}
+
+/// A team's settings and holidays, for telling which of its shifts are undesirable.
+pub struct Undesirability {
+    settings: FairnessSetting,
+    tz: Tz,
+    holidays: BTreeSet<NaiveDate>,
+}
+
+impl Undesirability {
+    /// Loads what's needed for shifts starting from `from` through `to`.
+    pub async fn load<S>(
+        store: &S,
+        team_id: i64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<FairnessSetting> + Store<Team> + Store<Holiday>,
+    {
+        let team: Team = store.get(team_id).await?;
+        let holidays = Holiday::between(store, team_id, from, to).await?;
+        Ok(Undesirability {
+            settings: FairnessSetting::for_team(store, team_id).await?,
+            tz: team.tz()?,
+            holidays: holidays.into_iter().map(|h| h.date).collect(),
+        })
+    }
+
+    /// Weekends and holidays go by the day the shift starts on. Nights count if the shift
+    /// overlaps any part of one.
+    pub fn of(&self, position: &Position) -> Undesirable {
+        let day = position.starts_at.with_timezone(&self.tz).date_naive();
+        Undesirable {
+            weekend: self.settings.weekends && matches!(day.weekday(), Weekday::Sat | Weekday::Sun),
+            night: self.at_night(position),
+            holiday: self.settings.holidays && self.holidays.contains(&day),
+        }
+    }
+
+    fn at_night(&self, position: &Position) -> bool {
+        let (Some(start), Some(end)) = (self.settings.night_start, self.settings.night_end) else {
+            return false;
+        };
+        // The night before the shift's first day may run into it.
+        let mut day = position.starts_at.with_timezone(&self.tz).date_naive() - Duration::days(1);
+        let last = position.ends_at.with_timezone(&self.tz).date_naive();
+        while day <= last {
+            let ends_on = if end <= start {
+                day + Duration::days(1)
+            } else {
+                day
+            };
+            let night_start = to_instant(self.tz, day.and_time(start));
+            let night_end = to_instant(self.tz, ends_on.and_time(end));
+            if position.starts_at < night_end && night_start < position.ends_at {
+                return true;
+            }
+            day += Duration::days(1);
+        }
+        false
+    }
+}
+
+/// How evenly undesirable shifts and hours were shared across a team from `from` through `to`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FairnessReport {
+    pub team_id: i64,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// The share of undesirable shifts each member would have if they were spread evenly.
+    pub fair_share: f64,
+    /// The variance of the members' undesirable shift counts.
+    pub undesirable_variance: f64,
+    /// The variance of the members' hours.
+    pub hours_variance: f64,
+    pub members: Vec<MemberFairness>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MemberFairness {
+    pub user_id: i64,
+    pub username: String,
+    pub shifts: usize,
+    pub weekend_shifts: usize,
+    pub night_shifts: usize,
+    pub holiday_shifts: usize,
+    /// Shifts that were undesirable for any reason, each counted once.
+    pub undesirable_shifts: usize,
+    /// Their part of all the team's undesirable shifts, from 0 to 1.
+    pub undesirable_share: f64,
+    pub minutes: i64,
+    /// Their weekly target over the length of the report, if they have one.
+    pub target_minutes: Option<i64>,
+    /// Their minutes over their target, so 1 is right on it.
+    pub target_ratio: Option<f64>,
+}
+
+/// Works out who worked which of the team's undesirable shifts starting from `from` through
+/// `to`, and their hours against their targets, for `by`, who must manage the team. Managers
+/// are left out unless they worked.
+#[allow(dead_code)]
+pub async fn report<S>(
+    store: &S,
+    by: &User,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<FairnessReport, ScheduleError>
+where
+    S: Store<FairnessSetting>
+        + Store<HoursTarget>
+        + Store<Holiday>
+        + Store<Team>
+        + Store<TeamMember>
+        + Store<User>
+        + Store<Position>
+        + Store<ScheduledPosition>,
+{
+    if to < from {
+        return Err(ScheduleError::Invalid(
+            "reports can't end before they start",
+        ));
+    }
+    TeamMember::require_manager(store, team_id, by, NOT_MANAGER_REPORT).await?;
+    let undesirability = Undesirability::load(store, team_id, from, to).await?;
+    let tz = undesirability.tz;
+    let positions: Vec<Position> = store
+        .find(
+            &Filter::new()
+                .eq("team_id", team_id)
+                .ge("starts_at", midnight(tz, from))
+                .lt("starts_at", midnight(tz, to + Duration::days(1))),
+        )
+        .await?;
+
+    let mut members: BTreeMap<i64, Tally> = BTreeMap::new();
+    let team: Vec<TeamMember> = store.find(&Filter::new().eq("team_id", team_id)).await?;
+    for m in &team {
+        if !m.manager {
+            members.entry(m.user_id).or_default();
+        }
+    }
+    for position in positions {
+        let why = undesirability.of(&position);
+        let assignments: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::worked().eq("position_id", position.id))
+            .await?;
+        for sp in assignments {
+            let tally = members.entry(sp.user_id).or_default();
+            tally.shifts += 1;
+            tally.weekend += why.weekend as usize;
+            tally.night += why.night as usize;
+            tally.holiday += why.holiday as usize;
+            tally.undesirable += why.any() as usize;
+            tally.minutes += (position.ends_at - position.starts_at).num_minutes();
+        }
+    }
+
+    let days = (to - from).num_days() + 1;
+    let total: usize = members.values().map(|t| t.undesirable).sum();
+    let mut report = FairnessReport {
+        team_id,
+        from,
+        to,
+        fair_share: if members.is_empty() {
+            0.0
+        } else {
+            1.0 / members.len() as f64
+        },
+        undesirable_variance: variance(members.values().map(|t| t.undesirable as f64)),
+        hours_variance: variance(members.values().map(|t| t.minutes as f64 / 60.0)),
+        members: Vec::new(),
+    };
+    for (user_id, tally) in members {
+        let user: User = store.get(user_id).await?;
+        let target: Option<HoursTarget> = store
+            .find(&Filter::new().eq("team_id", team_id).eq("user_id", user_id))
+            .await?
+            .into_iter()
+            .next();
+        let target_minutes = target.map(|t| t.weekly_minutes * days / 7);
+        report.members.push(MemberFairness {
+            user_id,
+            username: user.username,
+            shifts: tally.shifts,
+            weekend_shifts: tally.weekend,
+            night_shifts: tally.night,
+            holiday_shifts: tally.holiday,
+            undesirable_shifts: tally.undesirable,
+            undesirable_share: if total == 0 {
+                0.0
+            } else {
+                tally.undesirable as f64 / total as f64
+            },
+            minutes: tally.minutes,
+            target_minutes,
+            target_ratio: target_minutes
+                .filter(|&t| t > 0)
+                .map(|t| tally.minutes as f64 / t as f64),
+        });
+    }
+    Ok(report)
+}
+
+#[derive(Default)]
+struct Tally {
+    shifts: usize,
+    weekend: usize,
+    night: usize,
+    holiday: usize,
+    undesirable: usize,
+    minutes: i64,
+}
+
+/// The population variance, or 0 if there's nothing to vary.
+fn variance(values: impl Iterator<Item = f64>) -> f64 {
+    let values: Vec<f64> = values.collect();
+    if values.is_empty() {
+        return 0.0;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n
+}
+
+#[cfg(test)]
+mod fairness_tests {
+    use crate::models::auto_schedule::PlanRequest;
+    use crate::models::db_test;
+    use crate::models::fairness::{report, FairnessSetting, HoursTarget};
+    use crate::models::holiday::Holiday;
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone, Utc};
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 11, d)
+    }
+
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    /// User 1 manages team 1, with Friday 11 Nov 2022 a holiday. User 2 works a night from
+    /// Wednesday into Thursday and days on the weekend, and user 3 works days on Monday, Tuesday
+    /// and Friday.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, true), (1, 2, false), (1, 3, false)]).await?;
+        store
+            .create(&Holiday {
+                id: 0,
+                team_id: 1,
+                date: date(11),
+                name: "Armistice Day".into(),
+            })
+            .await?;
+
+        let shifts = [
+            (2, at(9, 22), at(10, 6)),
+            (2, at(12, 9), at(12, 17)),
+            (2, at(13, 9), at(13, 17)),
+            (3, at(7, 9), at(7, 17)),
+            (3, at(8, 9), at(8, 17)),
+            (3, at(11, 9), at(11, 17)),
+        ];
+        for (user_id, starts_at, ends_at) in shifts {
+            let position = store
+                .insert(&Position {
+                    team_id: 1,
+                    starts_at,
+                    ends_at,
+                    ..Default::default()
+                })
+                .await?;
+            store
+                .create(&ScheduledPosition {
+                    position_id: position.id,
+                    user_id,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(store)
+    }
+
+    #[actix_web::test]
+    async fn test_report() -> Result<()> {
+        let store = setup().await?;
+        let target = HoursTarget {
+            id: 0,
+            team_id: 1,
+            user_id: 2,
+            weekly_minutes: 20 * 60,
+        };
+        assert!(matches!(
+            target.set(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        target.set(&store, &user(1)).await?;
+
+        let report = report(&store, &user(1), 1, date(7), date(13)).await?;
+        assert_eq!(0.5, report.fair_share);
+        assert_eq!(1.0, report.undesirable_variance);
+        assert_eq!(0.0, report.hours_variance);
+
+        let (user2, user3) = (&report.members[0], &report.members[1]);
+        assert_eq!(2, report.members.len());
+        assert_eq!(
+            (3, 2, 1, 0),
+            (
+                user2.shifts,
+                user2.weekend_shifts,
+                user2.night_shifts,
+                user2.holiday_shifts
+            )
+        );
+        assert_eq!(3, user2.undesirable_shifts);
+        assert_eq!(0.75, user2.undesirable_share);
+        assert_eq!(Some(20 * 60), user2.target_minutes);
+        assert_eq!(Some(1.2), user2.target_ratio);
+        assert_eq!((1, 1), (user3.holiday_shifts, user3.undesirable_shifts));
+        assert_eq!(0.25, user3.undesirable_share);
+        assert_eq!(None, user3.target_ratio);
+
+        // Without nights, only the weekend counts against user 2.
+        FairnessSetting {
+            team_id: 1,
+            night_start: None,
+            ..Default::default()
+        }
+        .set(&store, &user(1))
+        .await?;
+        let report = super::report(&store, &user(1), 1, date(7), date(13)).await?;
+        assert_eq!(0, report.members[0].night_shifts);
+        assert_eq!(2, report.members[0].undesirable_shifts);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_plan() -> Result<()> {
+        let store = setup().await?;
+        let saturday = store
+            .insert(&Position {
+                team_id: 1,
+                starts_at: at(19, 9),
+                ends_at: at(19, 17),
+                min_headcount: 2,
+                ..Default::default()
+            })
+            .await?;
+
+        // User 2 has had the most undesirable shifts, so never gets Saturday.
+        for seed in 0..8 {
+            let plan = PlanRequest {
+                team_id: 1,
+                from: at(14, 0),
+                to: at(21, 0),
+                seed,
+                max_hours: None,
+            }
+            .plan(&store)
+            .await?;
+            let users: Vec<i64> = plan
+                .assignments
+                .iter()
+                .filter(|sp| sp.position_id == saturday.id)
+                .map(|sp| sp.user_id)
+                .collect();
+            assert_eq!(vec![1, 3], users);
+        }
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams")]
+        async fn test_create_fairness_setting(pool) -> Result<()> {
+            let settings = FairnessSetting {
+                team_id: 1,
+                night_start: Some(NaiveTime::from_hms(23, 0, 0)),
+                ..Default::default()
+            };
+            let settings = settings.insert(&pool).await?;
+            assert_eq!(settings, FairnessSetting::get(&pool, settings.id).await?);
+            assert!(FairnessSetting { weekends: false, ..settings }.create(&pool).await.is_err());
+
+            let target = HoursTarget {
+                id: 0,
+                team_id: 1,
+                user_id: 1,
+                weekly_minutes: 38 * 60,
+            };
+            let target = target.insert(&pool).await?;
+            assert_eq!(target, HoursTarget::get(&pool, target.id).await?);
+            assert!(target.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
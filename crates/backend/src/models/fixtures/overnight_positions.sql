@@ -0,0 +1,3 @@
+INSERT INTO positions (team_id, name, starts_at, ends_at)
+VALUES
+    (2, 'night', '2022-02-03T21:15:00+00:00', '2022-02-04T00:45:00+00:00');
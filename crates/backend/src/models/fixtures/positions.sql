@@ -1,4 +1,4 @@
-INSERT INTO positions (id, team_id, name, date, start_time, end_time) OVERRIDING SYSTEM VALUE
+INSERT INTO positions (team_id, name, starts_at, ends_at)
 VALUES
-    (1, 1, 'pos1', '2022-01-01', '09:15', '10:45'),
-    (2, 2, 'pos2', '2022-02-03', '21:15', '22:45');
+    (1, 'pos1', '2022-01-01T09:15:00+00:00', '2022-01-01T10:45:00+00:00'),
+    (2, 'pos2', '2022-02-03T21:15:00+00:00', '2022-02-03T22:45:00+00:00');
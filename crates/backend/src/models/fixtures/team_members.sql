@@ -1,9 +1,9 @@
-INSERT INTO teams (id, name) OVERRIDING SYSTEM VALUE VALUES
-    (1, 'team1'),
-    (2, 'team2')
+INSERT INTO teams (name) VALUES
+    ('team1'),
+    ('team2')
 ;
 
-INSERT INTO users (id, username) OVERRIDING SYSTEM VALUE VALUES
-    (1, 'user1'),
-    (2, 'user2')
+INSERT INTO users (username) VALUES
+    ('user1'),
+    ('user2')
 ;
@@ -1,4 +1,4 @@
-INSERT INTO teams (id, name, description) OVERRIDING SYSTEM VALUE VALUES
-    (1, 'team1', 'this is a good team'),
-    (2, 'team2', 'this is also a good team'),
-    (3, 'team3', 'this team sucks');
+INSERT INTO teams (name, description) VALUES
+    ('team1', 'this is a good team'),
+    ('team2', 'this is also a good team'),
+    ('team3', 'this team sucks');
@@ -5,7 +5,7 @@ VALUES (
     'Juan',
     'user@email.com',
     '46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b',
-    'f'
+    false
 ),
 (
     'userNoPass',
@@ -13,7 +13,7 @@ VALUES (
     'NoPass',
     'usernopass@email.com',
     '',
-    'f'
+    false
 ),
 (
     'userCanLogin',
@@ -21,6 +21,6 @@ VALUES (
     'CanLogin',
     'usercanlog@email.com',
     '$argon2i$v=19$m=65536,t=3,p=1$6JGByse/9Ous9DCnkgfFnA$lrixZa334c0rLb0k8SWK67q6TtSWoYjwXje67aKK0cU',
-    'f'
+    false
 )
 ;
@@ -0,0 +1,96 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::resource::{Filter, Resource};
+use super::scheduled_position::ScheduleError;
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+
+const NOT_MANAGER: &str = "only managers of the team may change its holidays";
+
+/// A day the team doesn't work, like a public holiday. Dates are in the team's time zone.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(team_id, date)]
+pub struct Holiday {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub date: NaiveDate,
+    pub name: String,
+}
+
+impl Holiday {
+    /// Adds the holiday on behalf of `by`, who must manage the team.
+    #[allow(dead_code)]
+    pub async fn add<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        TeamMember::require_manager(store, self.team_id, by, NOT_MANAGER).await?;
+        Ok(store.insert(self).await?)
+    }
+
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        let existing: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, existing.team_id, by, NOT_MANAGER).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+
+    /// The team's holidays from `from` through `to`.
+    pub async fn between<S: Store<Self>>(
+        store: &S,
+        team_id: i64,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        store
+            .find(
+                &Filter::new()
+                    .eq("team_id", team_id)
+                    .ge("date", from)
+                    .le("date", to),
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod holiday_tests {
+    use crate::models::db_test;
+    use crate::models::holiday::Holiday;
+    use crate::models::resource::Resource;
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_create_holiday(pool) -> Result<()> {
+            let holiday = Holiday {
+                id: 0,
+                team_id: 1,
+                date: NaiveDate::from_ymd(2022, 12, 25),
+                name: "Christmas Day".into(),
+            };
+            let holiday = holiday.insert(&pool).await?;
+            assert_eq!(holiday, Holiday::get(&pool, holiday.id).await?);
+
+            // Each day is only one holiday.
+            let again = Holiday {
+                name: "Christmas".into(),
+                ..holiday
+            };
+            assert!(again.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone::midnight;
+
+const NOT_MANAGER_REPORT: &str = "only managers of the team may see its hours";
+
+/// How a team's hours are paid: how long its pay periods are, and when hours count as
+/// overtime. Thresholds are in minutes, and either may be left unset.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct PaySetting {
+    #[primary_key]
+    pub id: i64,
+    #[unique]
+    #[references(Team)]
+    pub team_id: i64,
+    /// The first day of any one pay period. The others follow on every `period_days` days.
+    pub period_start: NaiveDate,
+    pub period_days: i64,
+    /// Hours past this in a day are overtime.
+    pub daily_overtime_after: Option<i64>,
+    /// Regular hours past this in a week from Monday are overtime.
+    pub weekly_overtime_after: Option<i64>,
+}
+
+impl Default for PaySetting {
+    /// Fortnightly pay periods starting on Mondays, with no overtime.
+    fn default() -> Self {
+        PaySetting {
+            id: 0,
+            team_id: 0,
+            period_start: NaiveDate::from_ymd(2000, 1, 3),
+            period_days: 14,
+            daily_overtime_after: None,
+            weekly_overtime_after: None,
+        }
+    }
+}
+
+impl PaySetting {
+    /// Saves the team's settings, replacing any it had, on behalf of `by`, who must manage the
+    /// team.
+    #[allow(dead_code)]
+    pub async fn set<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        let valid = self.period_days > 0
+            && self.daily_overtime_after.is_none_or(|m| m > 0)
+            && self.weekly_overtime_after.is_none_or(|m| m > 0);
+        if !valid {
+            return Err(ScheduleError::Invalid(
+                "pay periods and overtime thresholds must be positive",
+            ));
+        }
+        TeamMember::require_manager(
+            store,
+            self.team_id,
+            by,
+            "only managers of the team may change how it's paid",
+        )
+        .await?;
+
+        Ok(store.upsert(&["team_id"], self).await?)
+    }
+
+    /// The team's settings, or the default if it hasn't set one.
+    pub async fn for_team<S: Store<Self>>(store: &S, team_id: i64) -> Result<Self, sqlx::Error> {
+        let settings = store
+            .find(&Filter::new().eq("team_id", team_id))
+            .await?
+            .into_iter()
+            .next();
+        Ok(settings.unwrap_or(PaySetting {
+            team_id,
+            ..Default::default()
+        }))
+    }
+
+    /// The first day of the pay period containing `date`.
+    fn period_of(&self, date: NaiveDate) -> NaiveDate {
+        let n = (date - self.period_start)
+            .num_days()
+            .div_euclid(self.period_days);
+        self.period_start + Duration::days(n * self.period_days)
+    }
+}
+
+/// Scheduled hours worked on a team from `from` through `to`, per person.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HoursReport {
+    pub team_id: i64,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub users: Vec<UserHours>,
+}
+
+/// Someone's hours by day, by week from Monday and by pay period, leaving out those with none.
+/// Weeks and pay periods running past the ends of the report are cut short, though overtime is
+/// still worked out over the whole week.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserHours {
+    pub user_id: i64,
+    pub username: String,
+    pub days: Vec<Hours>,
+    pub weeks: Vec<Hours>,
+    pub pay_periods: Vec<Hours>,
+    pub total: Hours,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Hours {
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub regular_minutes: i64,
+    pub overtime_minutes: i64,
+}
+
+impl Hours {
+    fn empty(starts_on: NaiveDate, ends_on: NaiveDate) -> Self {
+        Hours {
+            starts_on,
+            ends_on,
+            regular_minutes: 0,
+            overtime_minutes: 0,
+        }
+    }
+
+    fn add(&mut self, other: &Hours) {
+        self.regular_minutes += other.regular_minutes;
+        self.overtime_minutes += other.overtime_minutes;
+    }
+}
+
+/// Works out everyone's hours on the team's positions from `from` through `to`, in the team's
+/// time zone, for `by`, who must manage the team as the report counts draft assignments. Shifts
+/// running past midnight count towards each day they cover.
+#[allow(dead_code)]
+pub async fn report<S>(
+    store: &S,
+    by: &User,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<HoursReport, ScheduleError>
+where
+    S: Store<PaySetting>
+        + Store<Team>
+        + Store<TeamMember>
+        + Store<User>
+        + Store<Position>
+        + Store<ScheduledPosition>,
+{
+    if to < from {
+        return Err(ScheduleError::Invalid(
+            "reports can't end before they start",
+        ));
+    }
+    TeamMember::require_manager(store, team_id, by, NOT_MANAGER_REPORT).await?;
+    let settings = PaySetting::for_team(store, team_id).await?;
+    let team: Team = store.get(team_id).await?;
+    let tz = team.tz()?;
+
+    // Whole weeks, so weekly overtime counts hours either side of the report.
+    let first = monday(from);
+    let last = monday(to) + Duration::days(6);
+    let positions: Vec<Position> = store
+        .find(
+            &Filter::new()
+                .eq("team_id", team_id)
+                .ge("starts_at", midnight(tz, first - Duration::days(1)))
+                .lt("starts_at", midnight(tz, last + Duration::days(1))),
+        )
+        .await?;
+
+    // Minutes worked by each user on each day.
+    let mut worked: BTreeMap<i64, BTreeMap<NaiveDate, i64>> = BTreeMap::new();
+    for position in positions {
+        let assignments: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::worked().eq("position_id", position.id))
+            .await?;
+        for sp in assignments {
+            let days = worked.entry(sp.user_id).or_default();
+            let mut day = position.starts_at.with_timezone(&tz).date_naive();
+            while midnight(tz, day) < position.ends_at {
+                let next = day + Duration::days(1);
+                let minutes = (position.ends_at.min(midnight(tz, next))
+                    - position.starts_at.max(midnight(tz, day)))
+                .num_minutes();
+                if first <= day && day <= last {
+                    *days.entry(day).or_default() += minutes;
+                }
+                day = next;
+            }
+        }
+    }
+
+    let mut users = Vec::new();
+    for (user_id, minutes) in worked {
+        let user: User = store.get(user_id).await?;
+        let days = classify(&settings, &minutes, first, last);
+        let days: Vec<Hours> = days
+            .into_iter()
+            .filter(|h| from <= h.starts_on && h.starts_on <= to)
+            .collect();
+        if days.is_empty() {
+            continue;
+        }
+
+        let mut total = Hours::empty(from, to);
+        let mut weeks: Vec<Hours> = Vec::new();
+        let mut pay_periods: Vec<Hours> = Vec::new();
+        for day in &days {
+            total.add(day);
+            let week = monday(day.starts_on);
+            let period = settings.period_of(day.starts_on);
+            for (group, start, end) in [
+                (&mut weeks, week, week + Duration::days(6)),
+                (
+                    &mut pay_periods,
+                    period,
+                    period + Duration::days(settings.period_days - 1),
+                ),
+            ] {
+                let start = start.max(from);
+                if group.last().is_none_or(|h| h.starts_on != start) {
+                    group.push(Hours::empty(start, end.min(to)));
+                }
+                group.last_mut().into_iter().for_each(|h| h.add(day));
+            }
+        }
+
+        users.push(UserHours {
+            user_id,
+            username: user.username,
+            days,
+            weeks,
+            pay_periods,
+            total,
+        });
+    }
+
+    Ok(HoursReport {
+        team_id,
+        from,
+        to,
+        users,
+    })
+}
+
+/// Splits each day's minutes from `first` through `last`, which must be whole weeks, into
+/// regular time and overtime. Daily overtime comes first, then regular time past the weekly
+/// threshold is overtime too.
+fn classify(
+    settings: &PaySetting,
+    minutes: &BTreeMap<NaiveDate, i64>,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Vec<Hours> {
+    let mut days = Vec::new();
+    let mut day = first;
+    let mut regular_this_week = 0;
+    while day <= last {
+        if day.weekday().num_days_from_monday() == 0 {
+            regular_this_week = 0;
+        }
+        let worked = minutes.get(&day).copied().unwrap_or_default();
+        let daily = settings
+            .daily_overtime_after
+            .map_or(0, |after| (worked - after).max(0));
+        let mut regular = worked - daily;
+        let weekly = settings.weekly_overtime_after.map_or(0, |after| {
+            let room = (after - regular_this_week).max(0);
+            (regular - room).max(0)
+        });
+        regular -= weekly;
+        regular_this_week += regular;
+
+        if worked > 0 {
+            days.push(Hours {
+                starts_on: day,
+                ends_on: day,
+                regular_minutes: regular,
+                overtime_minutes: daily + weekly,
+            });
+        }
+        day += Duration::days(1);
+    }
+    days
+}
+
+impl HoursReport {
+    /// Writes the report as CSV for payroll, with a row for each person's days, weeks, pay
+    /// periods and total. Hours are decimal, so 7h30m is 7.50.
+    #[allow(dead_code)]
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "user_id,username,period,starts_on,ends_on,regular_hours,overtime_hours"
+        )?;
+        for user in &self.users {
+            let rows = [
+                ("day", &user.days[..]),
+                ("week", &user.weeks[..]),
+                ("pay_period", &user.pay_periods[..]),
+                ("total", std::slice::from_ref(&user.total)),
+            ];
+            for (period, hours) in rows {
+                for h in hours {
+                    writeln!(
+                        writer,
+                        "{},{},{},{},{},{:.2},{:.2}",
+                        user.user_id,
+                        csv_field(&user.username),
+                        period,
+                        h.starts_on,
+                        h.ends_on,
+                        h.regular_minutes as f64 / 60.0,
+                        h.overtime_minutes as f64 / 60.0
+                    )?;
+                }
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Quotes `s` if it has anything CSV treats specially.
+fn csv_field(s: &str) -> String {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn monday(date: NaiveDate) -> NaiveDate {
+    date - Duration::days(date.weekday().num_days_from_monday() as i64)
+}
+
+#[cfg(test)]
+mod hours_tests {
+    use crate::models::db_test;
+    use crate::models::hours::{csv_field, report, Hours, PaySetting};
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{AssignmentStatus, ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 11, d)
+    }
+
+    fn hours(from: u32, to: u32, regular: i64, overtime: i64) -> Hours {
+        Hours {
+            starts_on: date(from),
+            ends_on: date(to),
+            regular_minutes: regular * 60,
+            overtime_minutes: overtime * 60,
+        }
+    }
+
+    /// User 1 manages team 1, paid fortnightly from Monday 31 Oct 2022 with overtime after 8
+    /// hours a day or 40 a week. User 2 works 10 hours on Monday 7 Nov, 8 hours Tuesday to
+    /// Friday, 6 on Saturday, and a night shift from Sunday into Monday. User 3 works a shift
+    /// on another team.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        for _ in 0..2 {
+            store.create(&Team::default()).await?;
+        }
+        people(&store, 3, &[(1, 1, true)]).await?;
+        let settings = PaySetting {
+            team_id: 1,
+            period_start: NaiveDate::from_ymd(2022, 10, 31),
+            daily_overtime_after: Some(8 * 60),
+            weekly_overtime_after: Some(40 * 60),
+            ..Default::default()
+        };
+        assert!(matches!(
+            settings.set(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        settings.set(&store, &user(1)).await?;
+
+        let mut shifts = vec![(1, 2, (7, 8), (7, 18)), (2, 3, (8, 9), (8, 17))];
+        shifts.extend((8..=11).map(|d| (1, 2, (d, 9), (d, 17))));
+        shifts.push((1, 2, (12, 9), (12, 15)));
+        shifts.push((1, 2, (13, 22), (14, 2)));
+        for (team_id, user_id, (d1, h1), (d2, h2)) in shifts {
+            let position = store
+                .insert(&Position {
+                    team_id,
+                    starts_at: Utc.ymd(2022, 11, d1).and_hms(h1, 0, 0),
+                    ends_at: Utc.ymd(2022, 11, d2).and_hms(h2, 0, 0),
+                    ..Default::default()
+                })
+                .await?;
+            store
+                .create(&ScheduledPosition {
+                    position_id: position.id,
+                    user_id,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(store)
+    }
+
+    #[actix_web::test]
+    async fn test_report() -> Result<()> {
+        let store = setup().await?;
+        // User 3 missed one team 1 shift and hasn't agreed to another, so has no hours.
+        for (d, status) in [
+            (9, AssignmentStatus::NoShow),
+            (10, AssignmentStatus::Proposed),
+        ] {
+            let position = store
+                .insert(&Position {
+                    team_id: 1,
+                    starts_at: Utc.ymd(2022, 11, d).and_hms(9, 0, 0),
+                    ends_at: Utc.ymd(2022, 11, d).and_hms(17, 0, 0),
+                    ..Default::default()
+                })
+                .await?;
+            store
+                .create(&ScheduledPosition {
+                    position_id: position.id,
+                    user_id: 3,
+                    status,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        assert!(matches!(
+            report(&store, &user(2), 1, date(7), date(14)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let report = report(&store, &user(1), 1, date(7), date(14)).await?;
+        assert_eq!(1, report.users.len());
+        let hours2 = &report.users[0];
+
+        assert_eq!(hours(7, 7, 8, 2), hours2.days[0]);
+        assert_eq!(hours(12, 12, 0, 6), hours2.days[5]);
+        assert_eq!(hours(13, 13, 0, 2), hours2.days[6]);
+        assert_eq!(hours(14, 14, 2, 0), hours2.days[7]);
+        assert_eq!(
+            vec![hours(7, 13, 40, 10), hours(14, 14, 2, 0)],
+            hours2.weeks
+        );
+        assert_eq!(hours2.weeks, hours2.pay_periods);
+        assert_eq!(hours(7, 14, 42, 10), hours2.total);
+
+        // Weekly overtime counts the whole week, even when the report starts part way through.
+        let friday_on = super::report(&store, &user(1), 1, date(11), date(13)).await?;
+        assert_eq!(vec![hours(11, 13, 8, 8)], friday_on.users[0].weeks);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_csv() -> Result<()> {
+        let store = setup().await?;
+        let mut out = Vec::new();
+        report(&store, &user(1), 1, date(7), date(14))
+            .await?
+            .write_csv(&mut out)?;
+        let csv = String::from_utf8(out)?;
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            "user_id,username,period,starts_on,ends_on,regular_hours,overtime_hours",
+            lines[0]
+        );
+        assert!(lines.contains(&"2,user2,week,2022-11-07,2022-11-13,40.00,10.00"));
+        assert_eq!(
+            "2,user2,total,2022-11-07,2022-11-14,42.00,10.00",
+            lines[lines.len() - 1]
+        );
+        assert_eq!("\"Smith, J\"", csv_field("Smith, J"));
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_create_pay_setting(pool) -> Result<()> {
+            let settings = PaySetting {
+                team_id: 1,
+                weekly_overtime_after: Some(40 * 60),
+                ..Default::default()
+            };
+            let settings = settings.insert(&pool).await?;
+            assert_eq!(settings, PaySetting::get(&pool, settings.id).await?);
+
+            let second = PaySetting {
+                period_days: 7,
+                ..settings
+            };
+            assert!(second.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,701 @@
+use std::collections::BTreeSet;
+
+use chrono::{Datelike, Duration, NaiveDate};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::position_constraint::age;
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone::{local_date, midnight};
+
+const NOT_MANAGER: &str = "only managers of the team may change its rules";
+const NOT_MANAGER_REPORT: &str = "only managers of the team may see where its rules are broken";
+
+text_enum! {
+    #[derive(Default)]
+    pub enum RuleKind {
+        /// Nobody may work more than `threshold` minutes in a calendar day.
+        #[default]
+        MaxHoursPerDay => "max_hours_per_day",
+        /// Nobody may work more than `threshold` minutes in a week from Monday.
+        MaxHoursPerWeek => "max_hours_per_week",
+        /// Shifts starting on different days must be at least `threshold` minutes apart.
+        /// Split shifts on the same day are left to `break_after`.
+        MinRest => "min_rest",
+        /// Nobody may start shifts on more than `threshold` days in a row.
+        MaxConsecutiveDays => "max_consecutive_days",
+        /// Nobody may work more than `threshold` minutes without a break of at least
+        /// `break_minutes`.
+        BreakAfter => "break_after",
+        /// Shifts starting on a school night, Sunday to Thursday, must end by `threshold` minutes
+        /// after midnight at their start. Usually limited to minors with `under_age`.
+        LatestEnd => "latest_end",
+        /// Nobody younger than `threshold` years may work positions flagged `adults_only`, nor
+        /// anyone whose date of birth isn't known.
+        AdultsOnly => "adults_only",
+    }
+}
+
+/// A working-time rule for the team, checked whenever someone is assigned or moved. Days and
+/// weeks are read in the team's time zone, and all of a user's shifts count, whichever team
+/// they're on.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct LaborRule {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub kind: RuleKind,
+    /// In minutes, or days for `max_consecutive_days`.
+    pub threshold: i64,
+    /// Only for `break_after`.
+    pub break_minutes: Option<i64>,
+    /// Limits the rule to people younger than this on the day of the shift, like minors. People
+    /// whose date of birth isn't known are taken to be adults.
+    pub under_age: Option<i64>,
+    /// Whether breaking the rule stops the assignment. Otherwise it goes ahead with a warning,
+    /// and is only flagged in reports.
+    pub blocking: bool,
+}
+
+impl Default for LaborRule {
+    fn default() -> Self {
+        LaborRule {
+            id: 0,
+            team_id: 0,
+            kind: RuleKind::default(),
+            threshold: 0,
+            break_minutes: None,
+            under_age: None,
+            blocking: true,
+        }
+    }
+}
+
+/// A rule someone's schedule breaks, on the day given.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct RuleViolation {
+    pub rule_id: i64,
+    pub kind: RuleKind,
+    pub user_id: i64,
+    pub date: NaiveDate,
+    pub message: String,
+    pub blocking: bool,
+}
+
+impl LaborRule {
+    /// Adds the rule on behalf of `by`, who must manage the team.
+    #[allow(dead_code)]
+    pub async fn add<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        let valid = self.threshold > 0
+            && self.under_age.is_none_or(|age| age > 0)
+            && match self.kind {
+                RuleKind::BreakAfter => self.break_minutes.is_some_and(|b| b > 0),
+                RuleKind::AdultsOnly => self.break_minutes.is_none() && self.under_age.is_none(),
+                _ => self.break_minutes.is_none(),
+            };
+        if !valid {
+            return Err(ScheduleError::Invalid(
+                "rule is missing its settings, or has another kind's",
+            ));
+        }
+        TeamMember::require_manager(store, self.team_id, by, NOT_MANAGER).await?;
+        Ok(store.insert(self).await?)
+    }
+
+    /// Removes the rule on behalf of `by`, who must manage the team.
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        let existing: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, existing.team_id, by, NOT_MANAGER).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+
+    pub async fn for_team<S: Store<Self>>(
+        store: &S,
+        team_id: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        store.find(&Filter::new().eq("team_id", team_id)).await
+    }
+}
+
+/// Checks `assignment` against the rules of its position's team, returning those it breaks.
+pub async fn evaluate<S>(
+    store: &S,
+    assignment: &ScheduledPosition,
+) -> Result<Vec<RuleViolation>, sqlx::Error>
+where
+    S: Store<LaborRule> + Store<Team> + Store<User> + Store<Position> + Store<ScheduledPosition>,
+{
+    let position: Position = store.get(assignment.position_id).await?;
+    let rules = LaborRule::for_team(store, position.team_id).await?;
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+    let team: Team = store.get(position.team_id).await?;
+    let user: User = store.get(assignment.user_id).await?;
+
+    // Only shifts near this one can break a rule along with it.
+    let reach = reach(&rules);
+    let mut working =
+        Position::overlapping(store, position.starts_at - reach, position.ends_at + reach).await?;
+    let others: Vec<i64> = Store::<ScheduledPosition>::find(
+        store,
+        &ScheduledPosition::active()
+            .eq("user_id", assignment.user_id)
+            .ne("id", assignment.id)
+            .one_of("position_id", working.iter().map(|p| p.id)),
+    )
+    .await?
+    .iter()
+    .map(|sp| sp.position_id)
+    .collect();
+    working.retain(|p| others.contains(&p.id));
+
+    Ok(breaches(
+        &rules,
+        team.tz()?,
+        assignment.user_id,
+        user.date_of_birth,
+        &working,
+        &position,
+    ))
+}
+
+/// How far either side of a shift other shifts can break `rules` along with it: a week for the
+/// weekly limit, which covers the daily one, and otherwise however long each rule looks.
+fn reach(rules: &[LaborRule]) -> Duration {
+    rules
+        .iter()
+        .map(|rule| match rule.kind {
+            RuleKind::MaxConsecutiveDays => Duration::days(rule.threshold + 1),
+            RuleKind::MinRest => Duration::minutes(rule.threshold),
+            RuleKind::BreakAfter => {
+                Duration::minutes(rule.threshold + rule.break_minutes.unwrap_or_default())
+            }
+            _ => Duration::zero(),
+        })
+        .fold(Duration::weeks(1), std::cmp::max)
+}
+
+/// The rules `user_id`, born on `born`, would break by working `position` as well as `working`.
+pub fn breaches(
+    rules: &[LaborRule],
+    tz: Tz,
+    user_id: i64,
+    born: Option<NaiveDate>,
+    working: &[Position],
+    position: &Position,
+) -> Vec<RuleViolation> {
+    let mut shifts: Vec<&Position> = working.iter().collect();
+    shifts.push(position);
+    let start = local_date(tz, position.starts_at);
+    // A shift ending at midnight doesn't touch the next day.
+    let end = local_date(tz, position.ends_at - Duration::seconds(1));
+    let worked = |from: NaiveDate, to: NaiveDate| {
+        let (from, to) = (midnight(tz, from), midnight(tz, to));
+        shifts
+            .iter()
+            .map(|p| std::cmp::min(p.ends_at, to) - std::cmp::max(p.starts_at, from))
+            .filter(|d| *d > Duration::zero())
+            .fold(Duration::zero(), |total, d| total + d)
+    };
+
+    let mut violations = Vec::new();
+    for rule in rules {
+        let applies = match (rule.under_age, born) {
+            (Some(under), Some(born)) => age(born, start) < under,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        if !applies {
+            continue;
+        }
+        let limit = Duration::minutes(rule.threshold);
+        let mut breach = |date, message| {
+            violations.push(RuleViolation {
+                rule_id: rule.id,
+                kind: rule.kind,
+                user_id,
+                date,
+                message,
+                blocking: rule.blocking,
+            })
+        };
+        match rule.kind {
+            RuleKind::MaxHoursPerDay => {
+                let mut day = start;
+                while day <= end {
+                    let total = worked(day, day + Duration::days(1));
+                    if total > limit {
+                        breach(day, too_long(total, limit, "in a day"));
+                    }
+                    day += Duration::days(1);
+                }
+            }
+            RuleKind::MaxHoursPerWeek => {
+                let mut monday =
+                    start - Duration::days(start.weekday().num_days_from_monday() as i64);
+                while monday <= end {
+                    let total = worked(monday, monday + Duration::days(7));
+                    if total > limit {
+                        breach(monday, too_long(total, limit, "in a week"));
+                    }
+                    monday += Duration::days(7);
+                }
+            }
+            RuleKind::MinRest => {
+                let rest = working
+                    .iter()
+                    .filter(|p| local_date(tz, p.starts_at) != start && !p.overlaps(position))
+                    .map(|p| {
+                        if p.ends_at <= position.starts_at {
+                            position.starts_at - p.ends_at
+                        } else {
+                            p.starts_at - position.ends_at
+                        }
+                    })
+                    .min();
+                if let Some(rest) = rest.filter(|rest| *rest < limit) {
+                    breach(
+                        start,
+                        format!(
+                            "only {} rest between shifts, less than {}",
+                            hours(rest),
+                            hours(limit)
+                        ),
+                    );
+                }
+            }
+            RuleKind::MaxConsecutiveDays => {
+                let days: BTreeSet<NaiveDate> =
+                    shifts.iter().map(|p| local_date(tz, p.starts_at)).collect();
+                let mut first = start;
+                while days.contains(&(first - Duration::days(1))) {
+                    first -= Duration::days(1);
+                }
+                let mut run = 0;
+                while days.contains(&(first + Duration::days(run))) {
+                    run += 1;
+                }
+                if run > rule.threshold {
+                    breach(
+                        first,
+                        format!("works {} days in a row, more than {}", run, rule.threshold),
+                    );
+                }
+            }
+            RuleKind::BreakAfter => {
+                // Shifts less than a break apart join into one stretch of work.
+                let gap = Duration::minutes(rule.break_minutes.unwrap_or_default());
+                let (mut from, mut to) = (position.starts_at, position.ends_at);
+                loop {
+                    let joined = shifts
+                        .iter()
+                        .filter(|p| p.starts_at < to + gap && from - gap < p.ends_at)
+                        .fold((from, to), |(from, to), p| {
+                            (from.min(p.starts_at), to.max(p.ends_at))
+                        });
+                    if joined == (from, to) {
+                        break;
+                    }
+                    (from, to) = joined;
+                }
+                if to - from > limit {
+                    breach(
+                        local_date(tz, from),
+                        format!(
+                            "works {} without a {} break, more than {}",
+                            hours(to - from),
+                            hours(gap),
+                            hours(limit)
+                        ),
+                    );
+                }
+            }
+            RuleKind::LatestEnd => {
+                let school_night = matches!(start.weekday().num_days_from_monday(), 0..=3 | 6);
+                let latest = midnight(tz, start) + limit;
+                if school_night && position.ends_at > latest {
+                    breach(
+                        start,
+                        format!(
+                            "works until {} on a school night, later than {}",
+                            position.ends_at.with_timezone(&tz).format("%H:%M"),
+                            latest.with_timezone(&tz).format("%H:%M")
+                        ),
+                    );
+                }
+            }
+            RuleKind::AdultsOnly => {
+                let message = match born {
+                    _ if !position.adults_only => None,
+                    None => Some("user's date of birth isn't known".to_string()),
+                    Some(born) if age(born, start) < rule.threshold => Some(format!(
+                        "position is only for people aged {} or over",
+                        rule.threshold
+                    )),
+                    Some(_) => None,
+                };
+                if let Some(message) = message {
+                    breach(start, message);
+                }
+            }
+        }
+    }
+
+    violations
+}
+
+/// Every rule broken by the team's assignments to positions starting from `from` through `to`,
+/// by day and then user, for `by`, who must manage the team.
+#[allow(dead_code)]
+pub async fn report<S>(
+    store: &S,
+    by: &User,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<RuleViolation>, ScheduleError>
+where
+    S: Store<LaborRule>
+        + Store<Team>
+        + Store<TeamMember>
+        + Store<User>
+        + Store<Position>
+        + Store<ScheduledPosition>,
+{
+    TeamMember::require_manager(store, team_id, by, NOT_MANAGER_REPORT).await?;
+    let team: Team = store.get(team_id).await?;
+    let tz = team.tz()?;
+    let positions: Vec<Position> = store
+        .find(
+            &Filter::new()
+                .eq("team_id", team_id)
+                .ge("starts_at", midnight(tz, from))
+                .lt("starts_at", midnight(tz, to + Duration::days(1))),
+        )
+        .await?;
+
+    let mut violations = Vec::new();
+    for position in positions {
+        let assignments: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::active().eq("position_id", position.id))
+            .await?;
+        for sp in assignments {
+            for v in evaluate(store, &sp).await? {
+                // Each shift in a long day breaks the daily limit, but it's one breach.
+                let seen = violations.iter().any(|seen: &RuleViolation| {
+                    (seen.rule_id, seen.user_id, seen.date) == (v.rule_id, v.user_id, v.date)
+                });
+                if !seen {
+                    violations.push(v);
+                }
+            }
+        }
+    }
+    violations.sort_by_key(|v| (v.date, v.user_id, v.rule_id));
+
+    Ok(violations)
+}
+
+fn too_long(total: Duration, limit: Duration, period: &str) -> String {
+    format!(
+        "works {} {}, more than {}",
+        hours(total),
+        period,
+        hours(limit)
+    )
+}
+
+/// A duration for people, like "8h" or "7h30m".
+fn hours(d: Duration) -> String {
+    let minutes = d.num_minutes();
+    if minutes % 60 == 0 {
+        format!("{}h", minutes / 60)
+    } else {
+        format!("{}h{:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod labor_rule_tests {
+    use crate::models::db_test;
+    use crate::models::labor_rule::{report, LaborRule, RuleKind};
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition, Warning};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    fn rule(kind: RuleKind, threshold: i64) -> LaborRule {
+        LaborRule {
+            team_id: 1,
+            kind,
+            threshold,
+            ..Default::default()
+        }
+    }
+
+    /// User 1 manages team 1, and user 2 works on it. Position `d` runs from `h1` until `h2` on
+    /// day `d` of November 2022, ending the next day if `h2` is earlier.
+    async fn setup(shifts: &[(u32, u32, u32)]) -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 2, &[(1, 1, true), (1, 2, false)]).await?;
+        for &(d, h1, h2) in shifts {
+            let d2 = if h2 <= h1 { d + 1 } else { d };
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: Utc.ymd(2022, 11, d).and_hms(h1, 0, 0),
+                    ends_at: Utc.ymd(2022, 11, d2).and_hms(h2, 0, 0),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(store)
+    }
+
+    async fn assign(store: &MemoryStore, position_id: i64) -> Result<(), ScheduleError> {
+        let sp = ScheduledPosition {
+            position_id,
+            user_id: 2,
+            ..Default::default()
+        };
+        sp.schedule(store, &user(1)).await.map(|_| ())
+    }
+
+    fn broken(result: Result<(), ScheduleError>) -> Vec<RuleKind> {
+        match result {
+            Err(ScheduleError::LaborRules(v)) => v.iter().map(|v| v.kind).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_add_rule() -> Result<()> {
+        let store = setup(&[]).await?;
+        assert!(matches!(
+            rule(RuleKind::MinRest, 11 * 60).add(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assert!(matches!(
+            rule(RuleKind::BreakAfter, 6 * 60)
+                .add(&store, &user(1))
+                .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(matches!(
+            rule(RuleKind::MinRest, 0).add(&store, &user(1)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        rule(RuleKind::MinRest, 11 * 60)
+            .add(&store, &user(1))
+            .await?;
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_hours() -> Result<()> {
+        // Two shifts on Monday the 7th, then one a day through to Saturday.
+        let mut shifts = vec![(7, 6, 12), (7, 13, 18)];
+        shifts.extend((8..=12).map(|d| (d, 9, 17)));
+        let store = setup(&shifts).await?;
+        let manager = user(1);
+        rule(RuleKind::MaxHoursPerDay, 10 * 60)
+            .add(&store, &manager)
+            .await?;
+        rule(RuleKind::MaxHoursPerWeek, 40 * 60)
+            .add(&store, &manager)
+            .await?;
+
+        assign(&store, 1).await?;
+        assert_eq!(
+            vec![RuleKind::MaxHoursPerDay],
+            broken(assign(&store, 2).await)
+        );
+        for id in 3..=6 {
+            assign(&store, id).await?;
+        }
+        // Working Saturday as well would make 46 hours.
+        assert_eq!(
+            vec![RuleKind::MaxHoursPerWeek],
+            broken(assign(&store, 7).await)
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_rest_and_breaks() -> Result<()> {
+        // A late finish on the 7th, an early start on the 8th, and a split shift on the 9th.
+        let store = setup(&[(7, 17, 23), (8, 7, 12), (9, 8, 12), (9, 12, 17)]).await?;
+        let manager = user(1);
+        rule(RuleKind::MinRest, 11 * 60)
+            .add(&store, &manager)
+            .await?;
+        let breaks = LaborRule {
+            break_minutes: Some(30),
+            ..rule(RuleKind::BreakAfter, 6 * 60)
+        };
+        breaks.add(&store, &manager).await?;
+
+        assign(&store, 1).await?;
+        assert_eq!(vec![RuleKind::MinRest], broken(assign(&store, 2).await));
+        assign(&store, 3).await?;
+        assert_eq!(vec![RuleKind::BreakAfter], broken(assign(&store, 4).await));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_runs_longer_than_a_week() -> Result<()> {
+        let shifts: Vec<(u32, u32, u32)> = (1..=11).map(|d| (d, 9, 12)).collect();
+        let store = setup(&shifts).await?;
+        rule(RuleKind::MaxConsecutiveDays, 10)
+            .add(&store, &user(1))
+            .await?;
+        for id in 1..=10 {
+            assign(&store, id).await?;
+        }
+        // The run started well over a week before the 11th.
+        assert_eq!(
+            vec![RuleKind::MaxConsecutiveDays],
+            broken(assign(&store, 11).await)
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_consecutive_days_and_report() -> Result<()> {
+        let shifts: Vec<(u32, u32, u32)> = (7..=13).map(|d| (d, 9, 12)).collect();
+        let store = setup(&shifts).await?;
+        for id in 1..=7 {
+            assign(&store, id).await?;
+        }
+
+        // Rules added later don't stop existing assignments, but show up in the report.
+        rule(RuleKind::MaxConsecutiveDays, 6)
+            .add(&store, &user(1))
+            .await?;
+        let date = |d| NaiveDate::from_ymd(2022, 11, d);
+        let violations = report(&store, &user(1), 1, date(1), date(30)).await?;
+        assert_eq!(1, violations.len());
+        assert_eq!(date(7), violations[0].date);
+        assert_eq!(2, violations[0].user_id);
+        assert!(report(&store, &user(1), 1, date(14), date(30))
+            .await?
+            .is_empty());
+
+        // Moving the last day away from the run fixes it, and moving it back is refused.
+        let mut sp: ScheduledPosition = store.get(7).await?;
+        let rest_day = store
+            .insert(&Position {
+                team_id: 1,
+                starts_at: Utc.ymd(2022, 11, 15).and_hms(9, 0, 0),
+                ends_at: Utc.ymd(2022, 11, 15).and_hms(12, 0, 0),
+                ..Default::default()
+            })
+            .await?;
+        sp.position_id = rest_day.id;
+        sp.reschedule(&store, &user(1)).await?;
+        assert!(report(&store, &user(1), 1, date(1), date(30))
+            .await?
+            .is_empty());
+        sp.position_id = 7;
+        assert!(matches!(
+            sp.reschedule(&store, &user(1)).await,
+            Err(ScheduleError::LaborRules(_))
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_minors() -> Result<()> {
+        // A Monday evening, a Friday evening and an adults-only Saturday shift.
+        let store = setup(&[(7, 16, 22), (11, 16, 23), (12, 10, 14)]).await?;
+        let mut bar: Position = store.get(3).await?;
+        bar.adults_only = true;
+        store.update(&bar).await?;
+        let mut minor = user(2);
+        minor.date_of_birth = Some(NaiveDate::from_ymd(2006, 1, 1));
+        store.update(&minor).await?;
+
+        let manager = user(1);
+        let minors = |kind, threshold| LaborRule {
+            under_age: Some(18),
+            ..rule(kind, threshold)
+        };
+        minors(RuleKind::LatestEnd, 21 * 60)
+            .add(&store, &manager)
+            .await?;
+        let hours = LaborRule {
+            blocking: false,
+            ..minors(RuleKind::MaxHoursPerDay, 4 * 60)
+        };
+        hours.add(&store, &manager).await?;
+        rule(RuleKind::AdultsOnly, 18).add(&store, &manager).await?;
+
+        // Only the school night is blocked, and the long Friday is flagged.
+        assert_eq!(vec![RuleKind::LatestEnd], broken(assign(&store, 1).await));
+        let friday = ScheduledPosition {
+            position_id: 2,
+            user_id: 2,
+            ..Default::default()
+        };
+        let scheduled = friday.schedule(&store, &manager).await?;
+        assert_eq!(vec![Warning::LaborRules], scheduled.warnings);
+        assert_eq!(vec![RuleKind::AdultsOnly], broken(assign(&store, 3).await));
+
+        let date = |d| NaiveDate::from_ymd(2022, 11, d);
+        let violations = report(&store, &user(1), 1, date(1), date(30)).await?;
+        assert_eq!(1, violations.len());
+        assert!(!violations[0].blocking);
+
+        // None of it applies to adults.
+        minor.date_of_birth = Some(NaiveDate::from_ymd(2004, 11, 7));
+        store.update(&minor).await?;
+        assign(&store, 1).await?;
+        assign(&store, 3).await?;
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_create_labor_rule(pool) -> Result<()> {
+            let r = LaborRule {
+                break_minutes: Some(30),
+                ..rule(RuleKind::BreakAfter, 6 * 60)
+            };
+            let r = r.insert(&pool).await?;
+            assert_eq!(r, LaborRule::get(&pool, r.id).await?);
+
+            let missing_break = rule(RuleKind::BreakAfter, 6 * 60);
+            assert!(missing_break.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -1,13 +1,43 @@
 use anyhow::Result;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
-use std::{env, process::exit};
+use sqlx::{
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Postgres, Sqlite,
+};
+use std::{env, process::exit, str::FromStr};
 
+mod attribute;
+mod auto_schedule;
+mod availability;
+mod export;
+mod fairness;
+mod holiday;
+mod hours;
+mod labor_rule;
+mod open_shift;
+mod position_constraint;
+mod position_slot;
 mod positions;
+mod qualification;
+mod recurring_position;
 mod resource;
+mod schedule_template;
+mod schedule_version;
 mod scheduled_position;
+mod shift_trade;
+mod store;
 mod team;
 mod team_member;
+#[cfg(test)]
+mod test_support;
+mod time_off;
 mod user;
+mod waitlist;
+
+pub use export::export_assignments;
+pub use recurring_position::materialise_every;
+use store::PoolStore;
+pub use waitlist::expire_offers_every;
 
 pub fn _default_false() -> bool {
     false
@@ -17,7 +47,16 @@ pub fn _default_true() -> bool {
     false
 }
 
-pub async fn db() -> Result<Pool<Postgres>> {
+/// A connection pool for whichever database `DATABASE_URL` points at.
+#[derive(Debug, Clone)]
+pub enum Db {
+    Postgres(PoolStore<Postgres>),
+    Sqlite(PoolStore<Sqlite>),
+}
+
+/// Connects to `DATABASE_URL` and runs the migrations for its dialect. `sqlite:` URLs use SQLite,
+/// creating the database file if needed. Anything else is handed to Postgres.
+pub async fn db() -> Result<Db> {
     let db_url = match env::var("DATABASE_URL") {
         Ok(u) => u,
         Err(e) => {
@@ -26,6 +65,19 @@ pub async fn db() -> Result<Pool<Postgres>> {
         }
     };
 
+    if db_url.starts_with("sqlite:") {
+        let options = SqliteConnectOptions::from_str(&db_url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect_with(options)
+            .await?;
+
+        let migrator = sqlx::migrate!("./migrations_sqlite");
+        migrator.run(&pool).await?;
+
+        return Ok(Db::Sqlite(pool.into()));
+    }
+
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(&db_url)
@@ -34,5 +86,28 @@ pub async fn db() -> Result<Pool<Postgres>> {
     let migrator = sqlx::migrate!();
     migrator.run(&pool).await?;
 
-    Ok(pool)
+    Ok(Db::Postgres(pool.into()))
 }
+
+/// Defines a test which runs once against Postgres and once against SQLite, as `$name::postgres`
+/// and `$name::sqlite`. Fixtures are shared, so they must be valid in both dialects.
+#[cfg(test)]
+macro_rules! db_test {
+    (
+        $(#[fixtures($($fixture:literal),*)])?
+        async fn $name:ident($pool:ident) -> Result<()> $body:block
+    ) => {
+        mod $name {
+            use super::*;
+
+            #[sqlx::test($(fixtures($($fixture),*))?)]
+            async fn postgres($pool: sqlx::PgPool) -> Result<()> $body
+
+            #[sqlx::test(migrations = "./migrations_sqlite", $(fixtures($($fixture),*))?)]
+            async fn sqlite($pool: sqlx::SqlitePool) -> Result<()> $body
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) use db_test;
@@ -0,0 +1,510 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::position_slot::PositionSlot;
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::schedule_version::{is_published, ScheduleVersion};
+use super::scheduled_position::{ScheduleError, ScheduleStore, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use super::waitlist::{has_room, live_offers, WaitlistSpot};
+
+const NOT_MANAGER: &str = "only managers of the position's team may do this";
+
+text_enum! {
+    #[derive(Default)]
+    pub enum ClaimMode {
+        /// The first eligible claims are assigned straight away.
+        #[default]
+        FirstCome => "first_come",
+        /// Claims wait for a manager to approve them.
+        Approval => "approval",
+    }
+}
+
+text_enum! {
+    #[derive(Default)]
+    pub enum ClaimStatus {
+        #[default]
+        Pending => "pending",
+        Approved => "approved",
+        Denied => "denied",
+        Withdrawn => "withdrawn",
+    }
+}
+
+/// A position published for members of its team to claim. Claims close at `claim_deadline`, or
+/// when the position starts if there's no deadline.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct OpenShift {
+    #[primary_key]
+    pub id: i64,
+    #[references(Position)]
+    #[unique]
+    pub position_id: i64,
+    pub claim_mode: ClaimMode,
+    pub claim_deadline: Option<DateTime<Utc>>,
+    #[references(User)]
+    pub published_by: i64,
+}
+
+/// A user's claim on an open shift, and the assignment it led to once approved.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct ShiftClaim {
+    #[primary_key]
+    pub id: i64,
+    #[references(OpenShift)]
+    pub open_shift_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    /// The slot claimed, required when the position has slots.
+    #[references(PositionSlot)]
+    pub slot_id: Option<i64>,
+    pub status: ClaimStatus,
+    #[references(ScheduledPosition)]
+    pub assignment_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OpenShift {
+    /// Publishes the position on behalf of `by`, who must manage its team. The position must
+    /// still have room and not have started.
+    #[allow(dead_code)]
+    pub async fn publish<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<Position>
+            + Store<TeamMember>
+            + Store<ScheduledPosition>
+            + Store<PositionSlot>
+            + Store<WaitlistSpot>,
+    {
+        let position: Position = store.get(self.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        if position.starts_at <= Utc::now() {
+            return Err(ScheduleError::Invalid("position has already started"));
+        }
+        let offers = live_offers(store, position.id).await?;
+        if !has_room(store, &position, None, &offers).await? {
+            return Err(ScheduleError::Full);
+        }
+
+        Ok(store
+            .insert(&OpenShift {
+                published_by: by.id,
+                ..self.clone()
+            })
+            .await?)
+    }
+
+    /// Stops taking claims for the shift. Pending claims can still be decided.
+    #[allow(dead_code)]
+    pub async fn close<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<TeamMember>,
+    {
+        let mut shift: Self = store.get(self.id).await?;
+        let position: Position = store.get(shift.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+
+        shift.claim_deadline = Some(Utc::now());
+        store.update(&shift).await?;
+        Ok(shift)
+    }
+
+    /// Shifts `user_id` could claim: open on one of their teams, in a published schedule, still
+    /// taking claims and with room left.
+    #[allow(dead_code)]
+    pub async fn open_for<S>(store: &S, user_id: i64) -> Result<Vec<Self>, sqlx::Error>
+    where
+        S: Store<Self>
+            + Store<Position>
+            + Store<TeamMember>
+            + Store<ScheduledPosition>
+            + Store<PositionSlot>
+            + Store<ScheduleVersion>
+            + Store<Team>
+            + Store<WaitlistSpot>,
+    {
+        let teams: Vec<i64> =
+            Store::<TeamMember>::find(store, &Filter::new().eq("user_id", user_id))
+                .await?
+                .iter()
+                .map(|m| m.team_id)
+                .collect();
+        let now = Utc::now();
+
+        let mut shifts = Vec::new();
+        for shift in Store::<Self>::find(store, &Filter::new()).await? {
+            let position: Position = store.get(shift.position_id).await?;
+            if !teams.contains(&position.team_id) || !shift.accepting_claims(&position, now) {
+                continue;
+            }
+            let offers = live_offers(store, position.id).await?;
+            if has_room(store, &position, None, &offers).await?
+                && is_published(store, &position).await?
+            {
+                shifts.push(shift);
+            }
+        }
+
+        Ok(shifts)
+    }
+
+    /// Claims the shift, or `slot_id` within it, on behalf of `by`, who must be on the
+    /// position's team and able to see it in a published schedule. First come claims are
+    /// assigned straight away, subject to the usual scheduling checks, and the database makes
+    /// sure only one claim gets the last place. The assignment and the claim are saved together.
+    #[allow(dead_code)]
+    pub async fn claim<S>(
+        &self,
+        store: &S,
+        by: &User,
+        slot_id: Option<i64>,
+    ) -> Result<ShiftClaim, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftClaim> + Store<ScheduleVersion>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let shift: Self = store.get(self.id).await?;
+        let position: Position = store.get(shift.position_id).await?;
+        if !shift.accepting_claims(&position, Utc::now()) {
+            return Err(ScheduleError::Invalid("claims for this shift have closed"));
+        }
+        if !is_published(store, &position).await? {
+            return Err(ScheduleError::Invalid(
+                "the schedule with this shift hasn't been published",
+            ));
+        }
+        let teammate = Store::<TeamMember>::exists(
+            store,
+            &Filter::new()
+                .eq("team_id", position.team_id)
+                .eq("user_id", by.id),
+        )
+        .await?;
+        if !teammate {
+            return Err(ScheduleError::Forbidden(
+                "only members of the position's team may claim it",
+            ));
+        }
+        let pending = Store::<ShiftClaim>::exists(
+            store,
+            &Filter::new()
+                .eq("open_shift_id", shift.id)
+                .eq("user_id", by.id)
+                .eq("status", ClaimStatus::Pending),
+        )
+        .await?;
+        if pending {
+            return Err(ScheduleError::Invalid(
+                "user has already claimed this shift",
+            ));
+        }
+
+        let assignment = ScheduledPosition {
+            position_id: shift.position_id,
+            user_id: by.id,
+            slot_id,
+            ..Default::default()
+        };
+        let mut claim = ShiftClaim {
+            id: 0,
+            open_shift_id: shift.id,
+            user_id: by.id,
+            slot_id,
+            status: ClaimStatus::Pending,
+            assignment_id: None,
+            created_at: Utc::now(),
+        };
+        match shift.claim_mode {
+            ClaimMode::FirstCome => {
+                let scheduled = assignment.place(store, by).await?;
+                claim.status = ClaimStatus::Approved;
+                claim.assignment_id = Some(scheduled.assignment.id);
+            }
+            ClaimMode::Approval => {
+                // Don't leave a manager to turn down claims which could never be approved.
+                let offers = live_offers(store, position.id).await?;
+                if !has_room(store, &position, slot_id, &offers).await? {
+                    return Err(ScheduleError::Full);
+                }
+                let conflicts = assignment.conflicts(store).await?;
+                if !conflicts.is_empty() {
+                    return Err(ScheduleError::Conflict(conflicts));
+                }
+            }
+        }
+
+        let claim = store.insert(&claim).await?;
+        tx.commit().await?;
+        Ok(claim)
+    }
+
+    fn accepting_claims(&self, position: &Position, now: DateTime<Utc>) -> bool {
+        now < self.claim_deadline.unwrap_or(position.starts_at) && now < position.starts_at
+    }
+}
+
+impl ShiftClaim {
+    /// Approves a pending claim and assigns the claimant, on behalf of `by`, who must manage the
+    /// position's team. Fails if the shift has filled up or the claimant is no longer eligible.
+    /// The assignment and the approval are saved together.
+    #[allow(dead_code)]
+    pub async fn approve<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<OpenShift>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let (mut claim, position) = self.decide(store, by).await?;
+        let assignment = ScheduledPosition {
+            position_id: position.id,
+            user_id: claim.user_id,
+            slot_id: claim.slot_id,
+            ..Default::default()
+        }
+        .place(store, by)
+        .await?
+        .assignment;
+
+        claim.status = ClaimStatus::Approved;
+        claim.assignment_id = Some(assignment.id);
+        store.update(&claim).await?;
+        tx.commit().await?;
+        Ok(claim)
+    }
+
+    #[allow(dead_code)]
+    pub async fn deny<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<OpenShift> + Store<Position> + Store<TeamMember>,
+    {
+        let (mut claim, _) = self.decide(store, by).await?;
+        claim.status = ClaimStatus::Denied;
+        store.update(&claim).await?;
+        Ok(claim)
+    }
+
+    /// Withdraws a pending claim. Only the claimant may withdraw it.
+    #[allow(dead_code)]
+    pub async fn withdraw<S: Store<Self>>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        let mut claim: Self = store.get(self.id).await?;
+        if claim.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only the claimant may withdraw a claim",
+            ));
+        }
+        if claim.status != ClaimStatus::Pending {
+            return Err(ScheduleError::Invalid("claim has already been decided"));
+        }
+
+        claim.status = ClaimStatus::Withdrawn;
+        store.update(&claim).await?;
+        Ok(claim)
+    }
+
+    async fn decide<S>(&self, store: &S, by: &User) -> Result<(Self, Position), ScheduleError>
+    where
+        S: Store<Self> + Store<OpenShift> + Store<Position> + Store<TeamMember>,
+    {
+        let claim: Self = store.get(self.id).await?;
+        if claim.status != ClaimStatus::Pending {
+            return Err(ScheduleError::Invalid("claim has already been decided"));
+        }
+        let shift: OpenShift = store.get(claim.open_shift_id).await?;
+        let position: Position = store.get(shift.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+
+        Ok((claim, position))
+    }
+}
+
+#[cfg(test)]
+mod open_shift_tests {
+    use crate::models::db_test;
+    use crate::models::open_shift::{ClaimMode, ClaimStatus, OpenShift};
+    use crate::models::positions::Position;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::schedule_version::ScheduleVersion;
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{DateTime, Duration, Utc};
+
+    fn in_days(days: i64) -> DateTime<Utc> {
+        Utc::now() + Duration::days(days)
+    }
+
+    /// Users 1 and 2 work on team 1, which user 3 manages. User 4 is on team 2. Position 1 is
+    /// next week and needs one person.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&Team::default()).await?;
+        people(
+            &store,
+            4,
+            &[(1, 1, false), (1, 2, false), (1, 3, true), (2, 4, false)],
+        )
+        .await?;
+        store
+            .create(&Position {
+                team_id: 1,
+                starts_at: in_days(7),
+                ends_at: in_days(7) + Duration::hours(8),
+                ..Default::default()
+            })
+            .await?;
+
+        Ok(store)
+    }
+
+    /// Publishes team 1's schedule for the next fortnight, so its open shifts are listed.
+    async fn publish_schedule(store: &MemoryStore) -> Result<()> {
+        store
+            .create(&ScheduleVersion {
+                team_id: 1,
+                starts_on: Utc::now().date_naive(),
+                ends_on: in_days(14).date_naive(),
+                number: 1,
+                published_at: Utc::now(),
+                published_by: 3,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    fn open(claim_mode: ClaimMode) -> OpenShift {
+        OpenShift {
+            position_id: 1,
+            claim_mode,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_first_come_claims() -> Result<()> {
+        let store = setup().await?;
+        assert!(matches!(
+            open(ClaimMode::FirstCome).publish(&store, &user(1)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let shift = open(ClaimMode::FirstCome).publish(&store, &user(3)).await?;
+        // Nobody else sees the position until the schedule it's in is published.
+        assert!(OpenShift::open_for(&store, 1).await?.is_empty());
+        assert!(matches!(
+            shift.claim(&store, &user(1), None).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        publish_schedule(&store).await?;
+        assert_eq!(vec![shift.clone()], OpenShift::open_for(&store, 1).await?);
+        assert!(OpenShift::open_for(&store, 4).await?.is_empty());
+
+        assert!(matches!(
+            shift.claim(&store, &user(4), None).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let claim = shift.claim(&store, &user(1), None).await?;
+        assert_eq!(ClaimStatus::Approved, claim.status);
+        let assignment: ScheduledPosition = store.get(claim.assignment_id.unwrap()).await?;
+        assert_eq!(1, assignment.user_id);
+
+        // The only place has gone.
+        assert!(matches!(
+            shift.claim(&store, &user(2), None).await,
+            Err(ScheduleError::Full)
+        ));
+        assert!(OpenShift::open_for(&store, 2).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_claims_by_approval() -> Result<()> {
+        let store = setup().await?;
+        let shift = open(ClaimMode::Approval).publish(&store, &user(3)).await?;
+        publish_schedule(&store).await?;
+        let first = shift.claim(&store, &user(1), None).await?;
+        let second = shift.claim(&store, &user(2), None).await?;
+        assert_eq!(ClaimStatus::Pending, first.status);
+        assert!(shift.claim(&store, &user(1), None).await.is_err());
+
+        assert!(matches!(
+            second.approve(&store, &user(1)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let second = second.approve(&store, &user(3)).await?;
+        assert_eq!(ClaimStatus::Approved, second.status);
+        assert!(matches!(
+            first.approve(&store, &user(3)).await,
+            Err(ScheduleError::Full)
+        ));
+        let first = first.deny(&store, &user(3)).await?;
+        assert_eq!(ClaimStatus::Denied, first.status);
+        // Once the shift is full nobody can queue up another claim.
+        assert!(matches!(
+            shift.claim(&store, &user(1), None).await,
+            Err(ScheduleError::Full)
+        ));
+        assert_eq!(
+            1,
+            Store::<ScheduledPosition>::count(&store, &Filter::new().eq("position_id", 1)).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_claim_deadline() -> Result<()> {
+        let store = setup().await?;
+        let shift = OpenShift {
+            claim_deadline: Some(in_days(1)),
+            ..open(ClaimMode::Approval)
+        };
+        let shift = shift.publish(&store, &user(3)).await?;
+        publish_schedule(&store).await?;
+        let claim = shift.claim(&store, &user(1), None).await?;
+        claim.withdraw(&store, &user(1)).await?;
+
+        let shift = shift.close(&store, &user(3)).await?;
+        assert!(matches!(
+            shift.claim(&store, &user(1), None).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(OpenShift::open_for(&store, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions")]
+        async fn test_create_open_shift(pool) -> Result<()> {
+            let shift = OpenShift {
+                published_by: 1,
+                ..open(ClaimMode::Approval)
+            };
+            let shift = shift.insert(&pool).await?;
+            assert_eq!(shift, OpenShift::get(&pool, shift.id).await?);
+
+            // A position is only published once.
+            assert!(shift.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,414 @@
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::attribute::{AttributeChoice, AttributeType, UserAttribute};
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduledPosition};
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+
+const NOT_MANAGER: &str = "only managers of the position's team may change its constraints";
+
+text_enum! {
+    #[derive(Default)]
+    pub enum ConstraintKind {
+        /// Whoever works the position must be at least `min_age` on the day.
+        #[default]
+        MinimumAge => "minimum_age",
+        /// Whoever works the position can't also work `other_position_id`, and vice versa.
+        DifferentPerson => "different_person",
+        /// Whoever works the position must have `attribute_value` for `attribute_type_id`.
+        Attribute => "attribute",
+    }
+}
+
+/// A rule a position puts on who may work it. Which of the optional fields are set depends on
+/// the kind.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct PositionConstraint {
+    #[primary_key]
+    pub id: i64,
+    #[references(Position)]
+    pub position_id: i64,
+    pub kind: ConstraintKind,
+    pub min_age: Option<i64>,
+    #[references(Position)]
+    pub other_position_id: Option<i64>,
+    #[references(AttributeType)]
+    pub attribute_type_id: Option<i64>,
+    pub attribute_value: Option<String>,
+}
+
+/// A constraint an assignment would break.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Violation {
+    pub constraint_id: i64,
+    pub kind: ConstraintKind,
+    pub message: String,
+}
+
+impl PositionConstraint {
+    /// Adds the constraint on behalf of `by`, who must manage the position's team.
+    #[allow(dead_code)]
+    pub async fn add<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<Position>
+            + Store<TeamMember>
+            + Store<AttributeType>
+            + Store<AttributeChoice>,
+    {
+        let attribute = self.attribute_type_id.is_some() || self.attribute_value.is_some();
+        let valid = match self.kind {
+            ConstraintKind::MinimumAge => {
+                self.min_age.is_some_and(|age| age >= 0)
+                    && self.other_position_id.is_none()
+                    && !attribute
+            }
+            ConstraintKind::DifferentPerson => {
+                self.min_age.is_none()
+                    && self
+                        .other_position_id
+                        .is_some_and(|other| other != self.position_id)
+                    && !attribute
+            }
+            ConstraintKind::Attribute => {
+                self.min_age.is_none()
+                    && self.other_position_id.is_none()
+                    && self.attribute_type_id.is_some()
+                    && self.attribute_value.is_some()
+            }
+        };
+        if !valid {
+            return Err(ScheduleError::Invalid(
+                "constraint is missing its settings, or has another kind's",
+            ));
+        }
+        let position: Position = store.get(self.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        if let Some(other) = self.other_position_id {
+            let _: Position = store.get(other).await?;
+        }
+        let mut constraint = self.clone();
+        if let (Some(type_id), Some(value)) = (self.attribute_type_id, &self.attribute_value) {
+            let ty: AttributeType = store.get(type_id).await?;
+            let value = ty.parse(store, value).await?;
+            constraint.attribute_value = Some(value.canonical());
+        }
+
+        Ok(store.insert(&constraint).await?)
+    }
+
+    /// Removes the constraint on behalf of `by`, who must manage the position's team.
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<TeamMember>,
+    {
+        let existing: Self = store.get(self.id).await?;
+        let position: Position = store.get(existing.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+
+    /// The constraints on whoever works `position_id`, including `different_person`
+    /// constraints other positions have with it.
+    pub async fn for_position<S: Store<Self>>(
+        store: &S,
+        position_id: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut constraints = store
+            .find(&Filter::new().eq("position_id", position_id))
+            .await?;
+        constraints.extend(
+            store
+                .find(
+                    &Filter::new()
+                        .eq("kind", ConstraintKind::DifferentPerson)
+                        .eq("other_position_id", position_id),
+                )
+                .await?,
+        );
+
+        Ok(constraints)
+    }
+}
+
+/// Checks `assignment` against its position's constraints, returning those it breaks.
+pub async fn evaluate<S>(
+    store: &S,
+    assignment: &ScheduledPosition,
+) -> Result<Vec<Violation>, sqlx::Error>
+where
+    S: Store<PositionConstraint>
+        + Store<Position>
+        + Store<Team>
+        + Store<User>
+        + Store<ScheduledPosition>
+        + Store<AttributeType>
+        + Store<UserAttribute>,
+{
+    let constraints = PositionConstraint::for_position(store, assignment.position_id).await?;
+    if constraints.is_empty() {
+        return Ok(Vec::new());
+    }
+    let position: Position = store.get(assignment.position_id).await?;
+    let team: Team = store.get(position.team_id).await?;
+    let user: User = store.get(assignment.user_id).await?;
+    let day = position.starts_at.with_timezone(&team.tz()?).date_naive();
+
+    let mut violations = Vec::new();
+    for c in constraints {
+        let message = match c.kind {
+            ConstraintKind::MinimumAge => {
+                let min_age = c.min_age.unwrap_or_default();
+                match user.date_of_birth {
+                    None => Some("user's date of birth isn't known".to_string()),
+                    Some(born) if age(born, day) < min_age => {
+                        Some(format!("user must be at least {} years old", min_age))
+                    }
+                    Some(_) => None,
+                }
+            }
+            ConstraintKind::DifferentPerson => {
+                let other = if c.position_id == assignment.position_id {
+                    c.other_position_id.unwrap_or_default()
+                } else {
+                    c.position_id
+                };
+                let working = Store::<ScheduledPosition>::exists(
+                    store,
+                    &ScheduledPosition::active()
+                        .eq("position_id", other)
+                        .eq("user_id", assignment.user_id)
+                        .ne("id", assignment.id),
+                )
+                .await?;
+                working.then(|| format!("user already works position {}", other))
+            }
+            ConstraintKind::Attribute => {
+                let ty: AttributeType = store.get(c.attribute_type_id.unwrap_or_default()).await?;
+                let wanted = c.attribute_value.clone().unwrap_or_default();
+                let has = UserAttribute::of(store, user.id, ty.id).await?;
+                match has {
+                    Some(a) if ty.read(&a.value) == ty.read(&wanted) => None,
+                    _ => Some(format!("user must have {} {}", ty.name, wanted)),
+                }
+            }
+        };
+        if let Some(message) = message {
+            violations.push(Violation {
+                constraint_id: c.id,
+                kind: c.kind,
+                message,
+            });
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Whole years from `born` until `on`.
+pub fn age(born: NaiveDate, on: NaiveDate) -> i64 {
+    let mut years = (on.year() - born.year()) as i64;
+    if (on.month(), on.day()) < (born.month(), born.day()) {
+        years -= 1;
+    }
+    years
+}
+
+#[cfg(test)]
+mod position_constraint_tests {
+    use crate::models::attribute::{AttributeType, Datatype, UserAttribute};
+    use crate::models::db_test;
+    use crate::models::position_constraint::{age, evaluate, ConstraintKind, PositionConstraint};
+    use crate::models::positions::Position;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    /// User 1 was born on 2 Jan 2005 and user 2's birthday isn't known. They work on team 1,
+    /// which user 3 manages and has an opening and a closing shift on 1 Jan 2023.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, false), (1, 2, false), (1, 3, true)]).await?;
+        let mut born = user(1);
+        born.date_of_birth = Some(NaiveDate::from_ymd(2005, 1, 2));
+        store.update(&born).await?;
+        for (start, end) in [(6, 14), (14, 22)] {
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: Utc.ymd(2023, 1, 1).and_hms(start, 0, 0),
+                    ends_at: Utc.ymd(2023, 1, 1).and_hms(end, 0, 0),
+                    max_headcount: 2,
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(store)
+    }
+
+    fn assign(position_id: i64, user_id: i64) -> ScheduledPosition {
+        ScheduledPosition {
+            position_id,
+            user_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_age() {
+        let born = NaiveDate::from_ymd(2005, 1, 2);
+        assert_eq!(17, age(born, NaiveDate::from_ymd(2023, 1, 1)));
+        assert_eq!(18, age(born, NaiveDate::from_ymd(2023, 1, 2)));
+    }
+
+    #[actix_web::test]
+    async fn test_minimum_age() -> Result<()> {
+        let store = setup().await?;
+        let adults = PositionConstraint {
+            position_id: 1,
+            kind: ConstraintKind::MinimumAge,
+            min_age: Some(18),
+            ..Default::default()
+        };
+        assert!(matches!(
+            adults.add(&store, &user(1)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let adults = adults.add(&store, &user(3)).await?;
+
+        // User 1 turns 18 the day after.
+        let violations = evaluate(&store, &assign(1, 1)).await?;
+        assert_eq!(
+            vec![adults.id],
+            violations
+                .iter()
+                .map(|v| v.constraint_id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, evaluate(&store, &assign(1, 2)).await?.len());
+        assert!(evaluate(&store, &assign(2, 1)).await?.is_empty());
+
+        assert!(matches!(
+            assign(1, 1).schedule(&store, &user(3)).await,
+            Err(ScheduleError::Constraints(v)) if v.len() == 1
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_different_person() -> Result<()> {
+        let store = setup().await?;
+        let invalid = PositionConstraint {
+            position_id: 1,
+            kind: ConstraintKind::DifferentPerson,
+            ..Default::default()
+        };
+        assert!(matches!(
+            invalid.add(&store, &user(3)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        PositionConstraint {
+            other_position_id: Some(2),
+            ..invalid
+        }
+        .add(&store, &user(3))
+        .await?;
+
+        assign(1, 2).schedule(&store, &user(3)).await?;
+        assign(1, 1).schedule(&store, &user(3)).await?;
+        // The constraint works both ways.
+        assert!(matches!(
+            assign(2, 2).schedule(&store, &user(3)).await,
+            Err(ScheduleError::Constraints(_))
+        ));
+        assert_eq!(
+            2,
+            Store::<ScheduledPosition>::count(&store, &Filter::new()).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_attribute() -> Result<()> {
+        let store = setup().await?;
+        let mut admin = user(3);
+        admin.admin = true;
+        let forklift = AttributeType {
+            id: 0,
+            name: "forklift".into(),
+            datatype: Datatype::Boolean,
+        }
+        .define(&store, &admin, &[])
+        .await?;
+        let needs_forklift = PositionConstraint {
+            position_id: 1,
+            kind: ConstraintKind::Attribute,
+            attribute_type_id: Some(forklift.id),
+            attribute_value: Some("yes".into()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            needs_forklift.add(&store, &admin).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        PositionConstraint {
+            attribute_value: Some(" true".into()),
+            ..needs_forklift
+        }
+        .add(&store, &admin)
+        .await?;
+
+        UserAttribute {
+            id: 0,
+            user_id: 1,
+            attribute_type_id: forklift.id,
+            value: "true".into(),
+        }
+        .set(&store, &admin)
+        .await?;
+        assert!(evaluate(&store, &assign(1, 1)).await?.is_empty());
+        let violations = evaluate(&store, &assign(1, 2)).await?;
+        assert_eq!("user must have forklift true", violations[0].message);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("teams", "positions")]
+        async fn test_create_position_constraint(pool) -> Result<()> {
+            let c = PositionConstraint {
+                position_id: 1,
+                kind: ConstraintKind::DifferentPerson,
+                other_position_id: Some(2),
+                ..Default::default()
+            };
+            let c = c.insert(&pool).await?;
+            assert_eq!(c, PositionConstraint::get(&pool, c.id).await?);
+
+            // The settings have to match the kind.
+            let mixed = PositionConstraint {
+                min_age: Some(18),
+                ..c
+            };
+            assert!(mixed.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::Resource;
+
+/// A named role within a `Position`, like "cashier", needing its own headcount. A position with
+/// slots only accepts assignments to one of them.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(position_id, role)]
+pub struct PositionSlot {
+    #[primary_key]
+    pub id: i64,
+    #[references(Position)]
+    pub position_id: i64,
+    pub role: String,
+    pub min_headcount: i64,
+    pub max_headcount: i64,
+}
+
+impl Default for PositionSlot {
+    fn default() -> Self {
+        PositionSlot {
+            id: 0,
+            position_id: 0,
+            role: String::new(),
+            min_headcount: 1,
+            max_headcount: 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod position_slot_tests {
+    use crate::models::db_test;
+    use crate::models::position_slot::PositionSlot;
+    use crate::models::resource::{Filter, Resource};
+    use anyhow::Result;
+
+    db_test! {
+        #[fixtures("teams", "positions")]
+        async fn test_create_position_slots(pool) -> Result<()> {
+            let cashiers = PositionSlot {
+                position_id: 1,
+                role: "cashier".into(),
+                min_headcount: 2,
+                max_headcount: 2,
+                ..Default::default()
+            };
+            cashiers.create(&pool).await?;
+            assert!(cashiers.create(&pool).await.is_err());
+
+            let inverted = PositionSlot {
+                position_id: 1,
+                role: "supervisor".into(),
+                min_headcount: 2,
+                max_headcount: 1,
+                ..Default::default()
+            };
+            assert!(inverted.create(&pool).await.is_err());
+            assert_eq!(
+                1,
+                PositionSlot::count(&pool, &Filter::new().eq("position_id", 1)).await?
+            );
+
+            Ok(())
+        }
+    }
+}
@@ -1,41 +1,380 @@
-use chrono::{NaiveDate, NaiveTime};
+use chrono::{DateTime, Duration, FixedOffset, Offset, Utc};
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-use super::resource::Resource;
+use super::position_slot::PositionSlot;
+use super::recurring_position::RecurringPosition;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::ScheduledPosition;
+use super::store::Store;
+use super::team::Team;
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+/// A shift running from `starts_at` until `ends_at`. Both are instants, so a shift may cross
+/// midnight or span several days. The database rejects shifts which don't end after they start.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
 pub struct Position {
     #[primary_key]
-    id: i64,
-    team_id: i64,
-    name: String,
-    date: NaiveDate,
-    start_time: NaiveTime,
-    end_time: NaiveTime,
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub name: String,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    /// The series this position is an occurrence of, if any.
+    #[references(RecurringPosition)]
+    pub recurring_position_id: Option<i64>,
+    /// How many people the position needs. If it has `PositionSlot`s these bound the total
+    /// across them.
+    pub min_headcount: i64,
+    pub max_headcount: i64,
+    /// Whether only adults may work the position, as the team's `adults_only` rule defines them.
+    pub adults_only: bool,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            id: 0,
+            team_id: 0,
+            name: String::new(),
+            starts_at: DateTime::default(),
+            ends_at: DateTime::default(),
+            recurring_position_id: None,
+            min_headcount: 1,
+            max_headcount: 1,
+            adults_only: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FillStatus {
+    Understaffed,
+    Filled,
+    Overstaffed,
+}
+
+impl FillStatus {
+    fn of(assigned: i64, min: i64, max: i64) -> Self {
+        if assigned < min {
+            FillStatus::Understaffed
+        } else if assigned > max {
+            FillStatus::Overstaffed
+        } else {
+            FillStatus::Filled
+        }
+    }
+}
+
+/// How well a position is staffed, overall and per slot. Overstaffing anywhere wins over
+/// understaffing, since it means an assignment has to go.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Staffing {
+    pub status: FillStatus,
+    pub assigned: i64,
+    pub slots: Vec<SlotStaffing>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SlotStaffing {
+    pub slot_id: i64,
+    pub role: String,
+    pub status: FillStatus,
+    pub assigned: i64,
+}
+
+/// A `Position` with its times given in a viewer's time zone. Serialized times carry their
+/// offset, so they stay unambiguous across DST changes.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct LocalPosition {
+    pub id: i64,
+    pub team_id: i64,
+    pub name: String,
+    pub starts_at: DateTime<FixedOffset>,
+    pub ends_at: DateTime<FixedOffset>,
+    pub recurring_position_id: Option<i64>,
+    pub min_headcount: i64,
+    pub max_headcount: i64,
+    pub adults_only: bool,
+    /// Filled in by callers which looked it up with `Position::staffing`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub staffing: Option<Staffing>,
+}
+
+impl Position {
+    /// This position as seen from `tz`.
+    #[allow(dead_code)]
+    pub fn in_zone(&self, tz: Tz) -> LocalPosition {
+        let local = |t: DateTime<Utc>| {
+            let t = t.with_timezone(&tz);
+            t.with_timezone(&t.offset().fix())
+        };
+        LocalPosition {
+            id: self.id,
+            team_id: self.team_id,
+            name: self.name.clone(),
+            starts_at: local(self.starts_at),
+            ends_at: local(self.ends_at),
+            recurring_position_id: self.recurring_position_id,
+            min_headcount: self.min_headcount,
+            max_headcount: self.max_headcount,
+            adults_only: self.adults_only,
+            staffing: None,
+        }
+    }
+
+    pub async fn staffing<S>(&self, store: &S) -> Result<Staffing, sqlx::Error>
+    where
+        S: Store<ScheduledPosition> + Store<PositionSlot>,
+    {
+        let assignments: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::active().eq("position_id", self.id))
+            .await?;
+        let slots: Vec<PositionSlot> = store
+            .find(&Filter::new().eq("position_id", self.id))
+            .await?;
+
+        let slots: Vec<SlotStaffing> = slots
+            .into_iter()
+            .map(|slot| {
+                let assigned = assignments
+                    .iter()
+                    .filter(|sp| sp.slot_id == Some(slot.id))
+                    .count() as i64;
+                SlotStaffing {
+                    slot_id: slot.id,
+                    status: FillStatus::of(assigned, slot.min_headcount, slot.max_headcount),
+                    role: slot.role,
+                    assigned,
+                }
+            })
+            .collect();
+
+        let assigned = assignments.len() as i64;
+        let overall = FillStatus::of(assigned, self.min_headcount, self.max_headcount);
+        let statuses: Vec<FillStatus> = std::iter::once(overall)
+            .chain(slots.iter().map(|s| s.status))
+            .collect();
+        let status = if statuses.contains(&FillStatus::Overstaffed) {
+            FillStatus::Overstaffed
+        } else if statuses.contains(&FillStatus::Understaffed) {
+            FillStatus::Understaffed
+        } else {
+            FillStatus::Filled
+        };
+
+        Ok(Staffing {
+            status,
+            assigned,
+            slots,
+        })
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.ends_at - self.starts_at
+    }
+
+    /// Whether the two shifts share any time. Shifts which only touch end to start don't overlap.
+    pub fn overlaps(&self, other: &Position) -> bool {
+        self.starts_at < other.ends_at && other.starts_at < self.ends_at
+    }
+
+    /// Every position overlapping the window `from` until `to`.
+    pub async fn overlapping<S: Store<Self>>(
+        store: &S,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        store
+            .find(&Filter::new().lt("starts_at", to).gt("ends_at", from))
+            .await
+    }
 }
 
 #[cfg(test)]
 mod position_tests {
-    use crate::models::positions::Position;
-    use crate::models::resource::Resource;
+    use crate::models::db_test;
+    use crate::models::position_slot::PositionSlot;
+    use crate::models::positions::{FillStatus, Position};
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::ScheduledPosition;
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::user::User;
     use anyhow::Result;
-    use chrono::{NaiveDate, NaiveTime};
-    use sqlx::PgPool;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
 
-    #[sqlx::test(fixtures("teams"))]
-    async fn test_create_position(pool: PgPool) -> Result<()> {
-        let pos1 = Position {
+    fn at(d: u32, h: u32, m: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, m, 0)
+    }
+
+    fn shift(starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> Position {
+        Position {
             id: 0,
             team_id: 1,
-            name: "Position1".into(),
-            date: NaiveDate::from_ymd(2022, 11, 4),
-            start_time: NaiveTime::from_hms(9, 30, 0),
-            end_time: NaiveTime::from_hms(10, 45, 0),
+            name: "Shift".into(),
+            starts_at,
+            ends_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_overnight_shift() {
+        let night = shift(at(4, 22, 0), at(5, 6, 0));
+        assert_eq!(Duration::hours(8), night.duration());
+
+        let on_call = shift(at(5, 6, 0), at(6, 6, 0));
+        assert_eq!(Duration::hours(24), on_call.duration());
+        assert!(!night.overlaps(&on_call));
+        assert!(on_call.overlaps(&shift(at(5, 23, 0), at(6, 1, 0))));
+        assert!(night.overlaps(&shift(at(5, 5, 0), at(5, 9, 0))));
+    }
+
+    #[test]
+    fn test_in_zone() -> Result<()> {
+        // The fall-back night in New York is an hour longer.
+        let night = shift(
+            Utc.ymd(2022, 11, 6).and_hms(2, 0, 0),
+            Utc.ymd(2022, 11, 6).and_hms(11, 0, 0),
+        );
+        let local = night.in_zone(chrono_tz::America::New_York);
+
+        assert_eq!("2022-11-05T22:00:00-04:00", local.starts_at.to_rfc3339());
+        assert_eq!("2022-11-06T06:00:00-05:00", local.ends_at.to_rfc3339());
+        assert_eq!(Duration::hours(9), night.duration());
+
+        let json = serde_json::to_value(&local)?;
+        assert_eq!("2022-11-05T22:00:00-04:00", json["starts_at"]);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_overlapping() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&shift(at(4, 22, 0), at(5, 6, 0))).await?;
+        store.create(&shift(at(5, 9, 0), at(5, 17, 0))).await?;
+
+        let early = Position::overlapping(&store, at(5, 0, 0), at(5, 8, 0)).await?;
+        assert_eq!(1, early.len());
+        assert_eq!(at(4, 22, 0), early[0].starts_at);
+
+        let day = Position::overlapping(&store, at(4, 0, 0), at(6, 0, 0)).await?;
+        assert_eq!(2, day.len());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_staffing() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&User::default()).await?;
+        let till = Position {
+            min_headcount: 2,
+            max_headcount: 3,
+            ..shift(at(4, 9, 0), at(4, 17, 0))
+        };
+        let till = store.insert(&till).await?;
+        let slot = |role: &str, min_headcount, max_headcount| PositionSlot {
+            position_id: till.id,
+            role: role.into(),
+            min_headcount,
+            max_headcount,
+            ..Default::default()
+        };
+        let cashiers = store.insert(&slot("cashier", 2, 2)).await?;
+        let supervisor = store.insert(&slot("supervisor", 0, 1)).await?;
+
+        let assign = |slot_id| ScheduledPosition {
+            position_id: till.id,
+            user_id: 1,
+            slot_id: Some(slot_id),
+            ..Default::default()
         };
-        let res = pos1.create(&pool).await?;
-        assert_eq!(1, res.rows_affected());
+        store.create(&assign(cashiers.id)).await?;
+        store.create(&assign(supervisor.id)).await?;
+
+        let staffing = till.staffing(&store).await?;
+        assert_eq!(FillStatus::Understaffed, staffing.status);
+        assert_eq!(2, staffing.assigned);
+        assert_eq!(FillStatus::Understaffed, staffing.slots[0].status);
+        assert_eq!(FillStatus::Filled, staffing.slots[1].status);
+
+        store.create(&assign(cashiers.id)).await?;
+        assert_eq!(FillStatus::Filled, till.staffing(&store).await?.status);
+
+        store.create(&assign(supervisor.id)).await?;
+        let staffing = till.staffing(&store).await?;
+        assert_eq!(FillStatus::Overstaffed, staffing.status);
+        assert_eq!(FillStatus::Overstaffed, staffing.slots[1].status);
+
+        let mut local = till.in_zone(chrono_tz::UTC);
+        local.staffing = Some(staffing);
+        let json = serde_json::to_value(&local)?;
+        assert_eq!("overstaffed", json["staffing"]["status"]);
 
         Ok(())
     }
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_create_position(pool) -> Result<()> {
+            let pos1 = Position {
+                id: 0,
+                team_id: 1,
+                name: "Position1".into(),
+                starts_at: at(4, 9, 30),
+                ends_at: at(4, 10, 45),
+                recurring_position_id: None,
+                min_headcount: 1,
+                max_headcount: 3,
+                adults_only: false,
+            };
+            let res = pos1.create(&pool).await?;
+            assert_eq!(1, res.rows_affected());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("teams", "overnight_positions")]
+        async fn test_overnight_position(pool) -> Result<()> {
+            let night: Vec<Position> =
+                Store::find(&pool, &Filter::new().eq("name", "night")).await?;
+            assert_eq!(Utc.ymd(2022, 2, 4).and_hms(0, 45, 0), night[0].ends_at);
+
+            let night = shift(at(4, 22, 0), at(5, 6, 0)).insert(&pool).await?;
+            assert_eq!(night, Position::get(&pool, night.id).await?);
+
+            assert!(shift(at(5, 6, 0), at(4, 22, 0)).create(&pool).await.is_err());
+            assert!(shift(at(5, 6, 0), at(5, 6, 0)).create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("teams", "positions")]
+        async fn test_count_positions(pool) -> Result<()> {
+            assert_eq!(2, Position::count(&pool, &Filter::new()).await?);
+            assert_eq!(
+                1,
+                Position::count(&pool, &Filter::new().eq("team_id", 2)).await?
+            );
+
+            let date = Utc.ymd(2022, 1, 2).and_hms(0, 0, 0);
+            assert_eq!(
+                1,
+                Position::count(&pool, &Filter::new().gt("starts_at", date)).await?
+            );
+
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,377 @@
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::ScheduleError;
+use super::store::Store;
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+
+const NOT_ADMIN: &str = "only admins may manage qualifications";
+const NOT_MANAGER: &str = "only managers of the position's team may change its requirements";
+
+/// A certification users can hold, like "First aid" or "Food hygiene level 2".
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct Qualification {
+    #[primary_key]
+    pub id: i64,
+    #[unique]
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A user holding a qualification from `issued_on` until the end of `expires_on`, if it expires.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct UserQualification {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    #[references(Qualification)]
+    pub qualification_id: i64,
+    pub issued_on: NaiveDate,
+    pub expires_on: Option<NaiveDate>,
+    /// How the qualification was checked, like a certificate number.
+    pub evidence: Option<String>,
+}
+
+/// A qualification everyone working a position must hold.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(position_id, qualification_id)]
+pub struct PositionQualification {
+    #[primary_key]
+    pub id: i64,
+    #[references(Position)]
+    pub position_id: i64,
+    #[references(Qualification)]
+    pub qualification_id: i64,
+}
+
+/// A team member's qualification which runs out soon.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Expiring {
+    pub user_id: i64,
+    pub qualification: Qualification,
+    pub expires_on: NaiveDate,
+}
+
+impl Qualification {
+    /// Adds the qualification to the catalogue. Only admins may do this.
+    #[allow(dead_code)]
+    pub async fn define<S: Store<Self>>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        by.require_admin(NOT_ADMIN)?;
+        Ok(store.insert(self).await?)
+    }
+}
+
+impl UserQualification {
+    /// Records that the user holds the qualification. Only admins may do this, so users can't
+    /// vouch for themselves.
+    #[allow(dead_code)]
+    pub async fn grant<S: Store<Self>>(&self, store: &S, by: &User) -> Result<Self, ScheduleError> {
+        by.require_admin(NOT_ADMIN)?;
+        if self
+            .expires_on
+            .is_some_and(|expires| expires < self.issued_on)
+        {
+            return Err(ScheduleError::Invalid(
+                "qualifications can't expire before they're issued",
+            ));
+        }
+        Ok(store.insert(self).await?)
+    }
+
+    #[allow(dead_code)]
+    pub async fn revoke<S: Store<Self>>(&self, store: &S, by: &User) -> Result<(), ScheduleError> {
+        by.require_admin(NOT_ADMIN)?;
+        let existing: Self = store.get(self.id).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+
+    /// Whether the qualification is valid every day from `from` until `to`.
+    fn covers(&self, from: NaiveDate, to: NaiveDate) -> bool {
+        self.issued_on <= from && self.expires_on.is_none_or(|expires| to <= expires)
+    }
+}
+
+impl PositionQualification {
+    /// Requires the qualification for the position, on behalf of `by`, who must manage the
+    /// position's team.
+    #[allow(dead_code)]
+    pub async fn require<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<TeamMember>,
+    {
+        let position: Position = store.get(self.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        Ok(store.insert(self).await?)
+    }
+
+    /// Stops requiring the qualification for the position, on behalf of `by`, who must manage
+    /// the position's team.
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<TeamMember>,
+    {
+        let existing: Self = store.get(self.id).await?;
+        let position: Position = store.get(existing.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        store.delete(&existing).await?;
+        Ok(())
+    }
+}
+
+/// The qualifications `position` requires which `user_id` doesn't hold for all of it, reading
+/// its dates in the team's time zone.
+pub async fn missing<S>(
+    store: &S,
+    user_id: i64,
+    position: &Position,
+) -> Result<Vec<Qualification>, sqlx::Error>
+where
+    S: Store<Team> + Store<Qualification> + Store<UserQualification> + Store<PositionQualification>,
+{
+    let required: Vec<PositionQualification> = store
+        .find(&Filter::new().eq("position_id", position.id))
+        .await?;
+    if required.is_empty() {
+        return Ok(Vec::new());
+    }
+    let team: Team = store.get(position.team_id).await?;
+    let tz = team.tz()?;
+    let from = position.starts_at.with_timezone(&tz).date_naive();
+    let to = position.ends_at.with_timezone(&tz).date_naive();
+
+    let held: Vec<UserQualification> = store.find(&Filter::new().eq("user_id", user_id)).await?;
+    let mut missing = Vec::new();
+    for r in required {
+        let covered = held
+            .iter()
+            .any(|q| q.qualification_id == r.qualification_id && q.covers(from, to));
+        if !covered {
+            missing.push(store.get(r.qualification_id).await?);
+        }
+    }
+
+    Ok(missing)
+}
+
+/// Qualifications held by members of `team_id` which expire in the `days` days from `today`,
+/// soonest first.
+#[allow(dead_code)]
+pub async fn expiring<S>(
+    store: &S,
+    team_id: i64,
+    today: NaiveDate,
+    days: i64,
+) -> Result<Vec<Expiring>, sqlx::Error>
+where
+    S: Store<TeamMember> + Store<Qualification> + Store<UserQualification>,
+{
+    let until = today + Duration::days(days);
+    let members: Vec<TeamMember> = store.find(&Filter::new().eq("team_id", team_id)).await?;
+
+    let mut expiring = Vec::new();
+    for m in members {
+        let held: Vec<UserQualification> = store
+            .find(
+                &Filter::new()
+                    .eq("user_id", m.user_id)
+                    .ge("expires_on", today)
+                    .le("expires_on", until),
+            )
+            .await?;
+        for q in held {
+            expiring.push(Expiring {
+                user_id: q.user_id,
+                qualification: store.get(q.qualification_id).await?,
+                expires_on: q.expires_on.unwrap_or(until),
+            });
+        }
+    }
+    expiring.sort_by_key(|e| (e.expires_on, e.user_id, e.qualification.id));
+
+    Ok(expiring)
+}
+
+#[cfg(test)]
+mod qualification_tests {
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::qualification::{
+        expiring, missing, PositionQualification, Qualification, UserQualification,
+    };
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use crate::models::user::User;
+    use anyhow::Result;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 11, d)
+    }
+
+    /// User 1 is an admin and user 2 manages team 1, which users 3 and 4 work on. Position 1 is
+    /// a night shift from the 9th into the 10th which needs first aid.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 4, &[(1, 2, true), (1, 3, false), (1, 4, false)]).await?;
+        let mut admin = user(1);
+        admin.admin = true;
+        store.update(&admin).await?;
+        store
+            .create(&Position {
+                team_id: 1,
+                starts_at: Utc.ymd(2022, 11, 9).and_hms(22, 0, 0),
+                ends_at: Utc.ymd(2022, 11, 10).and_hms(6, 0, 0),
+                max_headcount: 2,
+                ..Default::default()
+            })
+            .await?;
+
+        let first_aid = Qualification {
+            name: "First aid".into(),
+            ..Default::default()
+        };
+        first_aid.define(&store, &admin).await?;
+        let required = PositionQualification {
+            id: 0,
+            position_id: 1,
+            qualification_id: 1,
+        };
+        assert!(matches!(
+            required.require(&store, &user(3)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        required.require(&store, &user(2)).await?;
+
+        Ok(store)
+    }
+
+    fn held(user_id: i64, expires_on: Option<NaiveDate>) -> UserQualification {
+        UserQualification {
+            user_id,
+            qualification_id: 1,
+            issued_on: date(1),
+            expires_on,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_assignments_need_qualifications() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        assert!(matches!(
+            held(3, None).grant(&store, &user(3)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        // User 3's certificate runs out on the 9th, part way through the shift.
+        held(3, Some(date(9))).grant(&store, &admin).await?;
+
+        let position: Position = store.get(1).await?;
+        assert_eq!(1, missing(&store, 3, &position).await?.len());
+        assert_eq!(1, missing(&store, 4, &position).await?.len());
+
+        let assign = |user_id| ScheduledPosition {
+            position_id: 1,
+            user_id,
+            ..Default::default()
+        };
+        assert!(matches!(
+            assign(3).schedule(&store, &user(2)).await,
+            Err(ScheduleError::Unqualified(q)) if q[0].name == "First aid"
+        ));
+
+        held(4, Some(date(10))).grant(&store, &admin).await?;
+        assign(4).schedule(&store, &user(2)).await?;
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_remove() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        let granted = held(3, None).grant(&store, &admin).await?;
+        granted.revoke(&store, &admin).await?;
+        assert!(matches!(
+            granted.revoke(&store, &admin).await,
+            Err(ScheduleError::Database(sqlx::Error::RowNotFound))
+        ));
+
+        // Only the team's managers may drop the requirement.
+        let required: PositionQualification = store.get(1).await?;
+        assert!(matches!(
+            required.remove(&store, &user(3)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        required.remove(&store, &user(2)).await?;
+        let position: Position = store.get(1).await?;
+        assert!(missing(&store, 3, &position).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_expiring() -> Result<()> {
+        let store = setup().await?;
+        let admin: User = store.get(1).await?;
+        held(3, Some(date(20))).grant(&store, &admin).await?;
+        held(4, Some(date(12))).grant(&store, &admin).await?;
+        // Admins aren't on the team, and user 2's doesn't expire.
+        held(1, Some(date(12))).grant(&store, &admin).await?;
+        held(2, None).grant(&store, &admin).await?;
+
+        let soon = |days| {
+            let store = &store;
+            async move {
+                let e = expiring(store, 1, date(7), days).await?;
+                Ok::<_, sqlx::Error>(e.iter().map(|e| e.user_id).collect::<Vec<_>>())
+            }
+        };
+        assert_eq!(vec![4], soon(7).await?);
+        assert_eq!(vec![4, 3], soon(30).await?);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users")]
+        async fn test_create_user_qualification(pool) -> Result<()> {
+            let q = Qualification {
+                name: "First aid".into(),
+                ..Default::default()
+            };
+            let q = q.insert(&pool).await?;
+            let uq = UserQualification {
+                qualification_id: q.id,
+                evidence: Some("Certificate 1234".into()),
+                ..held(1, Some(date(30)))
+            };
+            let uq = uq.insert(&pool).await?;
+            assert_eq!(uq, UserQualification::get(&pool, uq.id).await?);
+
+            let backwards = UserQualification {
+                expires_on: Some(date(1) - chrono::Duration::days(1)),
+                ..uq
+            };
+            assert!(backwards.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
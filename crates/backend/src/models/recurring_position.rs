@@ -0,0 +1,891 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::open_shift::OpenShift;
+use super::position_constraint::PositionConstraint;
+use super::position_slot::PositionSlot;
+use super::positions::Position;
+use super::qualification::PositionQualification;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduleStore, ScheduledPosition};
+use super::store::{Store, Transactional};
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use super::waitlist::WaitlistSpot;
+use crate::rrule::RRule;
+use crate::timezone::{local_date, midnight, to_instant};
+
+const NOT_MANAGER: &str = "only managers of the team may change its recurring positions";
+
+/// A series of positions repeating according to an RFC 5545 `RRULE`, starting on `start_date`
+/// and never past `end_date`. Occurrences are materialised as concrete `Position`s linked back
+/// through `Position::recurring_position_id`. Times are wall-clock times in the team's zone, and
+/// an `end_time` at or before `start_time` finishes the next day, so equal times give a 24 hour
+/// block.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct RecurringPosition {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub name: String,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub rrule: String,
+}
+
+/// A date excluded from a `RecurringPosition`, like an `EXDATE`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(recurring_position_id, date)]
+pub struct RecurrenceException {
+    #[primary_key]
+    pub id: i64,
+    #[references(RecurringPosition)]
+    pub recurring_position_id: i64,
+    pub date: NaiveDate,
+}
+
+/// What `RecurringPosition::materialise` changed.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize)]
+pub struct Materialised {
+    pub created: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    /// Occurrences which no longer match the series, or would have moved, but already had people
+    /// assigned or waiting, or were posted as open shifts. They are kept as standalone positions
+    /// rather than dropping any of that or moving people without checking they can still work
+    /// it, and a new occurrence takes the place of one which would have moved.
+    pub detached: Vec<i64>,
+}
+
+impl RecurringPosition {
+    pub fn rule(&self) -> Result<RRule, sqlx::Error> {
+        self.rrule
+            .parse()
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+    }
+
+    /// Fails as `Invalid` unless the rule parses, so a bad rule from an edit isn't reported as
+    /// a database error.
+    fn check_rule(&self) -> Result<(), ScheduleError> {
+        match self.rrule.parse::<RRule>() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(ScheduleError::Invalid("recurrence rule isn't valid")),
+        }
+    }
+
+    /// The dates of every occurrence on or before `until`, excluding exception dates.
+    pub async fn occurrences<S>(
+        &self,
+        store: &S,
+        until: NaiveDate,
+    ) -> Result<Vec<NaiveDate>, sqlx::Error>
+    where
+        S: Store<RecurrenceException>,
+    {
+        let until = match self.end_date {
+            Some(end) if end < until => end,
+            _ => until,
+        };
+        let exceptions: Vec<NaiveDate> = store
+            .find(&Filter::new().eq("recurring_position_id", self.id))
+            .await?
+            .into_iter()
+            .map(|e| e.date)
+            .collect();
+
+        let mut dates = self.rule()?.occurrences(self.start_date, until);
+        dates.retain(|d| !exceptions.contains(d));
+        Ok(dates)
+    }
+
+    /// Brings the series' positions dated `from` through `horizon` in line with the series.
+    /// Existing occurrences are updated in place, missing ones are created and stale ones are
+    /// deleted along with their slots, constraints and required qualifications. Occurrences in
+    /// use are detached instead of being deleted or moved to another time or team.
+    pub async fn materialise<S>(
+        &self,
+        store: &S,
+        from: NaiveDate,
+        horizon: NaiveDate,
+    ) -> Result<Materialised, sqlx::Error>
+    where
+        S: ScheduleStore + Store<OpenShift> + Store<RecurrenceException>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let tz = self.tz(store).await?;
+        let mut result = Materialised::default();
+        let mut dates = self.occurrences(store, horizon).await?;
+        dates.retain(|d| *d >= from);
+
+        let existing: Vec<Position> = store
+            .find(
+                &Filter::new()
+                    .eq("recurring_position_id", self.id)
+                    .ge("starts_at", midnight(tz, from))
+                    .lt("starts_at", midnight(tz, horizon + Duration::days(1))),
+            )
+            .await?;
+
+        // Dates whose occurrence is still part of the series.
+        let mut kept = Vec::new();
+        for mut position in existing {
+            let date = local_date(tz, position.starts_at);
+            let detach = if dates.contains(&date) {
+                let mut updated = position.clone();
+                if !self.apply_to(&mut updated, date, tz) {
+                    kept.push(date);
+                    continue;
+                }
+                let moved = (updated.team_id, updated.starts_at, updated.ends_at)
+                    != (position.team_id, position.starts_at, position.ends_at);
+                if !moved || !in_use(store, position.id).await? {
+                    store.update(&updated).await?;
+                    result.updated += 1;
+                    kept.push(date);
+                    continue;
+                }
+                true
+            } else {
+                in_use(store, position.id).await?
+            };
+
+            if detach {
+                position.recurring_position_id = None;
+                store.update(&position).await?;
+                result.detached.push(position.id);
+            } else {
+                delete_position(store, &position).await?;
+                result.deleted += 1;
+            }
+        }
+
+        for date in dates {
+            if kept.contains(&date) {
+                continue;
+            }
+            let mut position = Position {
+                recurring_position_id: Some(self.id),
+                ..Default::default()
+            };
+            self.apply_to(&mut position, date, tz);
+            store.create(&position).await?;
+            result.created += 1;
+        }
+
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Materialises every series from its team's local date at `now` through `days` days ahead.
+    /// Run periodically so the horizon keeps rolling forward. A series which fails, like one
+    /// with a rule that no longer parses, is logged and skipped so the others still go ahead.
+    pub async fn materialise_all<S>(
+        store: &S,
+        now: DateTime<Utc>,
+        days: i64,
+    ) -> Result<(), sqlx::Error>
+    where
+        S: Store<Self> + ScheduleStore + Store<OpenShift> + Store<RecurrenceException>,
+    {
+        let all: Vec<Self> = store.find(&Filter::new()).await?;
+        for series in all {
+            let materialised = async {
+                let today = local_date(series.tz(store).await?, now);
+                series
+                    .materialise(store, today, today + Duration::days(days))
+                    .await
+            };
+            if let Err(e) = materialised.await {
+                log::error!(
+                    "materialising recurring position {} failed: {}",
+                    series.id,
+                    e
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Edits a single occurrence on behalf of `by`, who must manage its team, and the team it
+    /// moves to if that's another. The position leaves the series and its date becomes an
+    /// exception, so regenerating the series won't undo the edit.
+    #[allow(dead_code)]
+    pub async fn edit_occurrence<S>(
+        store: &S,
+        by: &User,
+        edited: &Position,
+    ) -> Result<(), ScheduleError>
+    where
+        S: Store<Team>
+            + Store<TeamMember>
+            + Store<Position>
+            + Store<RecurrenceException>
+            + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let original: Position = store.get(edited.id).await?;
+        TeamMember::require_manager(store, original.team_id, by, NOT_MANAGER).await?;
+        TeamMember::require_manager(store, edited.team_id, by, NOT_MANAGER).await?;
+        if let Some(series_id) = original.recurring_position_id {
+            let team: Team = store.get(original.team_id).await?;
+            store
+                .create(&RecurrenceException {
+                    id: 0,
+                    recurring_position_id: series_id,
+                    date: local_date(team.tz()?, original.starts_at),
+                })
+                .await?;
+        }
+
+        let mut edited = edited.clone();
+        edited.recurring_position_id = None;
+        store.update(&edited).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Edits this occurrence and all following ones on behalf of `by`, who must manage the
+    /// series' team and `updated`'s, by ending this series the day before `from` and starting
+    /// `updated` as a new series on `from`. Positions and exceptions from `from` onwards move to
+    /// the new series, which is then materialised through `horizon`.
+    #[allow(dead_code)]
+    pub async fn edit_following<S>(
+        &self,
+        store: &S,
+        by: &User,
+        from: NaiveDate,
+        updated: &RecurringPosition,
+        horizon: NaiveDate,
+    ) -> Result<RecurringPosition, ScheduleError>
+    where
+        S: Store<Self> + ScheduleStore + Store<OpenShift> + Store<RecurrenceException>,
+    {
+        updated.check_rule()?;
+        let tx = store.begin().await?;
+        let store = &tx;
+        let stored: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, stored.team_id, by, NOT_MANAGER).await?;
+        TeamMember::require_manager(store, updated.team_id, by, NOT_MANAGER).await?;
+        let tz = self.tz(store).await?;
+        let mut ended = self.clone();
+        ended.end_date = Some(from - Duration::days(1));
+        store.update(&ended).await?;
+
+        let mut following = updated.clone();
+        following.start_date = from;
+        following.end_date = match (updated.end_date, self.end_date) {
+            (Some(end), _) | (None, Some(end)) => Some(end),
+            (None, None) => None,
+        };
+        let following = store.insert(&following).await?;
+
+        let moved: Vec<Position> = store
+            .find(
+                &Filter::new()
+                    .eq("recurring_position_id", self.id)
+                    .ge("starts_at", midnight(tz, from)),
+            )
+            .await?;
+        for mut position in moved {
+            position.recurring_position_id = Some(following.id);
+            store.update(&position).await?;
+        }
+
+        let exceptions: Vec<RecurrenceException> = store
+            .find(
+                &Filter::new()
+                    .eq("recurring_position_id", self.id)
+                    .ge("date", from),
+            )
+            .await?;
+        for mut exception in exceptions {
+            exception.recurring_position_id = following.id;
+            store.update(&exception).await?;
+        }
+
+        following.materialise(store, from, horizon).await?;
+
+        tx.commit().await?;
+        Ok(following)
+    }
+
+    /// Edits every occurrence on behalf of `by`, who must manage the series' team and
+    /// `updated`'s, by saving `updated` over this series and regenerating it from its start
+    /// through `horizon`.
+    #[allow(dead_code)]
+    pub async fn edit_all<S>(
+        &self,
+        store: &S,
+        by: &User,
+        updated: &RecurringPosition,
+        horizon: NaiveDate,
+    ) -> Result<Materialised, ScheduleError>
+    where
+        S: Store<Self> + ScheduleStore + Store<OpenShift> + Store<RecurrenceException>,
+    {
+        let mut updated = updated.clone();
+        updated.id = self.id;
+        updated.check_rule()?;
+        let tx = store.begin().await?;
+        let stored: Self = tx.get(self.id).await?;
+        TeamMember::require_manager(&tx, stored.team_id, by, NOT_MANAGER).await?;
+        TeamMember::require_manager(&tx, updated.team_id, by, NOT_MANAGER).await?;
+        tx.update(&updated).await?;
+
+        let from = std::cmp::min(self.start_date, updated.start_date);
+        let result = updated.materialise(&tx, from, horizon).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// When the occurrence on `date` starts and ends, with the times read in `tz`. Across a DST
+    /// change the occurrence is an hour shorter or longer than usual.
+    pub fn occurrence(&self, date: NaiveDate, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        let mut end_date = date;
+        if self.end_time <= self.start_time {
+            end_date += Duration::days(1);
+        }
+        (
+            to_instant(tz, date.and_time(self.start_time)),
+            to_instant(tz, end_date.and_time(self.end_time)),
+        )
+    }
+
+    async fn tz<S: Store<Team>>(&self, store: &S) -> Result<Tz, sqlx::Error> {
+        let team: Team = store.get(self.team_id).await?;
+        team.tz()
+    }
+
+    /// Copies the series' fields onto its occurrence on `date`, returning whether anything
+    /// changed.
+    fn apply_to(&self, position: &mut Position, date: NaiveDate, tz: Tz) -> bool {
+        let before = position.clone();
+        position.team_id = self.team_id;
+        position.name = self.name.clone();
+        (position.starts_at, position.ends_at) = self.occurrence(date, tz);
+        *position != before
+    }
+}
+
+/// How many days ahead `materialise_every` keeps each series' positions.
+const HORIZON_DAYS: i64 = 90;
+
+/// Materialises every series through `HORIZON_DAYS` ahead every `period`, for as long as the
+/// server runs, logging any failure and trying again next time.
+pub async fn materialise_every<S>(store: S, period: std::time::Duration)
+where
+    S: Store<RecurringPosition> + ScheduleStore + Store<OpenShift> + Store<RecurrenceException>,
+{
+    let mut interval = actix_web::rt::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = RecurringPosition::materialise_all(&store, Utc::now(), HORIZON_DAYS).await {
+            log::error!("materialising recurring positions failed: {}", e);
+        }
+    }
+}
+
+/// Whether anyone is assigned to or waiting for the position, or it's posted as an open shift.
+async fn in_use<S>(store: &S, position_id: i64) -> Result<bool, sqlx::Error>
+where
+    S: Store<ScheduledPosition> + Store<WaitlistSpot> + Store<OpenShift>,
+{
+    let filter = Filter::new().eq("position_id", position_id);
+    Ok(Store::<ScheduledPosition>::exists(store, &filter).await?
+        || Store::<WaitlistSpot>::exists(store, &filter).await?
+        || Store::<OpenShift>::exists(store, &filter).await?)
+}
+
+/// Deletes the position after the rows which only describe it: its slots, its required
+/// qualifications and constraints on or against it.
+async fn delete_position<S>(store: &S, position: &Position) -> Result<(), sqlx::Error>
+where
+    S: Store<Position>
+        + Store<PositionSlot>
+        + Store<PositionConstraint>
+        + Store<PositionQualification>,
+{
+    let own = Filter::new().eq("position_id", position.id);
+    let mut constraints: Vec<PositionConstraint> = store.find(&own).await?;
+    constraints.extend(
+        Store::<PositionConstraint>::find(
+            store,
+            &Filter::new().eq("other_position_id", position.id),
+        )
+        .await?
+        .into_iter()
+        .filter(|c| c.position_id != position.id),
+    );
+    for constraint in constraints {
+        store.delete(&constraint).await?;
+    }
+    let required: Vec<PositionQualification> = store.find(&own).await?;
+    for pq in required {
+        store.delete(&pq).await?;
+    }
+    let slots: Vec<PositionSlot> = store.find(&own).await?;
+    for slot in slots {
+        store.delete(&slot).await?;
+    }
+    store.delete(position).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod recurring_position_tests {
+    use crate::models::db_test;
+    use crate::models::open_shift::OpenShift;
+    use crate::models::position_constraint::PositionConstraint;
+    use crate::models::position_slot::PositionSlot;
+    use crate::models::positions::Position;
+    use crate::models::qualification::{PositionQualification, Qualification};
+    use crate::models::recurring_position::{RecurrenceException, RecurringPosition};
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::{AssignmentStatus, ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, PoolStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use crate::models::waitlist::WaitlistSpot;
+    use anyhow::Result;
+    use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(y, m, d)
+    }
+
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    /// Weekly on Mondays, 9-5, from Monday 7 Nov 2022.
+    fn front_desk() -> RecurringPosition {
+        RecurringPosition {
+            id: 0,
+            team_id: 1,
+            name: "Front desk".into(),
+            start_time: NaiveTime::from_hms(9, 0, 0),
+            end_time: NaiveTime::from_hms(17, 0, 0),
+            start_date: date(2022, 11, 7),
+            end_date: None,
+            rrule: "FREQ=WEEKLY;BYDAY=MO".into(),
+        }
+    }
+
+    /// User 1 manages team 1 and user 2 doesn't work on it.
+    async fn setup() -> Result<(MemoryStore, RecurringPosition)> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 2, &[(1, 1, true)]).await?;
+        let series = store.insert(&front_desk()).await?;
+        Ok((store, series))
+    }
+
+    async fn positions(store: &MemoryStore) -> Result<Vec<Position>> {
+        Ok(store.find(&Filter::new()).await?)
+    }
+
+    #[actix_web::test]
+    async fn test_materialise() -> Result<()> {
+        let (store, series) = setup().await?;
+        store
+            .create(&RecurrenceException {
+                id: 0,
+                recurring_position_id: series.id,
+                date: date(2022, 11, 14),
+            })
+            .await?;
+
+        let res = series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+        assert_eq!(3, res.created);
+
+        let dates: Vec<NaiveDate> = positions(&store)
+            .await?
+            .iter()
+            .map(|p| p.starts_at.date_naive())
+            .collect();
+        assert_eq!(
+            vec![date(2022, 11, 7), date(2022, 11, 21), date(2022, 11, 28)],
+            dates
+        );
+
+        // Rolling the horizon forward only adds the new occurrences.
+        let res = series
+            .materialise(&store, date(2022, 11, 1), date(2022, 12, 6))
+            .await?;
+        assert_eq!(1, res.created);
+        assert_eq!(0, res.updated);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_materialise_overnight() -> Result<()> {
+        let (store, _) = setup().await?;
+        let night = RecurringPosition {
+            start_time: NaiveTime::from_hms(22, 0, 0),
+            end_time: NaiveTime::from_hms(6, 0, 0),
+            rrule: "FREQ=DAILY".into(),
+            ..front_desk()
+        };
+        let night = store.insert(&night).await?;
+        night
+            .materialise(&store, date(2022, 11, 7), date(2022, 11, 8))
+            .await?;
+
+        let all = positions(&store).await?;
+        assert_eq!(2, all.len());
+        assert_eq!((at(7, 22), at(8, 6)), (all[0].starts_at, all[0].ends_at));
+        assert!(all.iter().all(|p| p.duration() == Duration::hours(8)));
+
+        let (starts_at, ends_at) = RecurringPosition {
+            end_time: NaiveTime::from_hms(9, 0, 0),
+            ..front_desk()
+        }
+        .occurrence(date(2022, 11, 7), chrono_tz::UTC);
+        assert_eq!(Duration::hours(24), ends_at - starts_at);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_materialise_across_dst() -> Result<()> {
+        let store = MemoryStore::new();
+        store
+            .create(&Team {
+                time_zone: "America/New_York".into(),
+                ..Default::default()
+            })
+            .await?;
+        let night = RecurringPosition {
+            start_time: NaiveTime::from_hms(22, 0, 0),
+            end_time: NaiveTime::from_hms(6, 0, 0),
+            start_date: date(2022, 11, 4),
+            rrule: "FREQ=DAILY".into(),
+            ..front_desk()
+        };
+        let night = store.insert(&night).await?;
+        night
+            .materialise(&store, date(2022, 11, 4), date(2022, 11, 6))
+            .await?;
+
+        // The night of the 5th gets the repeated hour when the clocks go back.
+        let hours: Vec<i64> = positions(&store)
+            .await?
+            .iter()
+            .map(|p| p.duration().num_hours())
+            .collect();
+        assert_eq!(vec![8, 9, 8], hours);
+
+        let first = &positions(&store).await?[0];
+        assert_eq!(Utc.ymd(2022, 11, 5).and_hms(2, 0, 0), first.starts_at);
+
+        // A 02:30 start doesn't exist when the clocks go forward, so it starts at 03:30.
+        let early = RecurringPosition {
+            start_time: NaiveTime::from_hms(2, 30, 0),
+            end_time: NaiveTime::from_hms(10, 0, 0),
+            ..night.clone()
+        };
+        let (starts_at, ends_at) =
+            early.occurrence(date(2023, 3, 12), chrono_tz::America::New_York);
+        assert_eq!(Utc.ymd(2023, 3, 12).and_hms(7, 30, 0), starts_at);
+        assert_eq!(Duration::minutes(390), ends_at - starts_at);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_materialise_all_uses_team_date() -> Result<()> {
+        let store = MemoryStore::new();
+        store
+            .create(&Team {
+                time_zone: "Pacific/Auckland".into(),
+                ..Default::default()
+            })
+            .await?;
+        store.insert(&front_desk()).await?;
+
+        // Still Sunday in UTC, but already Monday morning in Auckland.
+        RecurringPosition::materialise_all(&store, at(6, 12), 0).await?;
+        let all = positions(&store).await?;
+        assert_eq!(1, all.len());
+        assert_eq!(at(6, 20), all[0].starts_at);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_materialise_all_skips_broken_series() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store
+            .insert(&RecurringPosition {
+                rrule: "FREQ=FORTNIGHTLY".into(),
+                ..front_desk()
+            })
+            .await?;
+        store.insert(&front_desk()).await?;
+
+        RecurringPosition::materialise_all(&store, at(7, 0), 0).await?;
+        let all = positions(&store).await?;
+        assert_eq!(
+            vec![Some(2)],
+            all.iter()
+                .map(|p| p.recurring_position_id)
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_edit_occurrence() -> Result<()> {
+        let (store, series) = setup().await?;
+        series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+
+        let mut position: Position = store.get(2).await?;
+        position.starts_at = at(14, 10);
+        assert!(matches!(
+            RecurringPosition::edit_occurrence(&store, &user(2), &position).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        RecurringPosition::edit_occurrence(&store, &user(1), &position).await?;
+
+        series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+        let edited: Position = store.get(2).await?;
+        assert_eq!(at(14, 10), edited.starts_at);
+        assert_eq!(None, edited.recurring_position_id);
+        assert_eq!(4, positions(&store).await?.len());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_edit_following_keeps_assignments() -> Result<()> {
+        let (store, series) = setup().await?;
+        series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+        // Assign someone to the 21st, which the edit below keeps.
+        store
+            .create(&ScheduledPosition {
+                id: 0,
+                position_id: 3,
+                user_id: 1,
+                allow_overlap: false,
+                slot_id: None,
+                needs_reassignment: false,
+                status: AssignmentStatus::Assigned,
+            })
+            .await?;
+
+        let updated = RecurringPosition {
+            end_time: NaiveTime::from_hms(18, 0, 0),
+            ..series.clone()
+        };
+        let following = series
+            .edit_following(
+                &store,
+                &user(1),
+                date(2022, 11, 21),
+                &updated,
+                date(2022, 11, 30),
+            )
+            .await?;
+
+        // The assigned occurrence keeps its time and leaves the series rather than moving its
+        // assignment unchecked, and a new occurrence takes its place.
+        let all = positions(&store).await?;
+        assert_eq!(5, all.len());
+        assert_eq!(Some(series.id), all[1].recurring_position_id);
+        assert_eq!(at(14, 17), all[1].ends_at);
+        assert_eq!((3, None), (all[2].id, all[2].recurring_position_id));
+        assert_eq!(at(21, 17), all[2].ends_at);
+        assert_eq!(Some(following.id), all[3].recurring_position_id);
+        assert_eq!(at(28, 18), all[3].ends_at);
+        assert_eq!(Some(following.id), all[4].recurring_position_id);
+        assert_eq!(at(21, 18), all[4].ends_at);
+        assert_eq!(
+            1,
+            Store::<ScheduledPosition>::count(&store, &Filter::new()).await?
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_edit_following_rejects_bad_rule() -> Result<()> {
+        let (store, series) = setup().await?;
+        let updated = RecurringPosition {
+            rrule: "FREQ=FORTNIGHTLY".into(),
+            ..series.clone()
+        };
+        assert!(matches!(
+            series
+                .edit_following(
+                    &store,
+                    &user(1),
+                    date(2022, 11, 21),
+                    &updated,
+                    date(2022, 11, 30),
+                )
+                .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // The series wasn't ended, and no new one was started.
+        let all: Vec<RecurringPosition> = store.find(&Filter::new()).await?;
+        assert_eq!(vec![series], all);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_edit_all_detaches_assigned_occurrences() -> Result<()> {
+        let (store, series) = setup().await?;
+        series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+        store
+            .create(&ScheduledPosition {
+                id: 0,
+                position_id: 1,
+                user_id: 1,
+                allow_overlap: false,
+                slot_id: None,
+                needs_reassignment: false,
+                status: AssignmentStatus::Assigned,
+            })
+            .await?;
+
+        // Move the series from Mondays to Tuesdays.
+        let updated = RecurringPosition {
+            start_date: date(2022, 11, 8),
+            rrule: "FREQ=WEEKLY;BYDAY=TU".into(),
+            ..series.clone()
+        };
+        assert!(matches!(
+            series
+                .edit_all(&store, &user(2), &updated, date(2022, 11, 30))
+                .await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let res = series
+            .edit_all(&store, &user(1), &updated, date(2022, 11, 30))
+            .await?;
+
+        assert_eq!(4, res.created);
+        assert_eq!(3, res.deleted);
+        assert_eq!(vec![1], res.detached);
+        let kept: Position = store.get(1).await?;
+        assert_eq!(at(7, 9), kept.starts_at);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_edit_all_clears_stale_occurrences() -> Result<()> {
+        let (store, series) = setup().await?;
+        series
+            .materialise(&store, date(2022, 11, 1), date(2022, 11, 30))
+            .await?;
+        // The 7th has a slot and needs first aid, and the 14th a constraint against the 21st.
+        // The 21st is posted as an open shift and someone's waiting for the 28th.
+        store
+            .create(&PositionSlot {
+                position_id: 1,
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&Qualification {
+                name: "First aid".into(),
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&PositionQualification {
+                id: 0,
+                position_id: 1,
+                qualification_id: 1,
+            })
+            .await?;
+        store
+            .create(&PositionConstraint {
+                position_id: 2,
+                other_position_id: Some(3),
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&OpenShift {
+                position_id: 3,
+                published_by: 1,
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&WaitlistSpot {
+                position_id: 4,
+                user_id: 1,
+                ..Default::default()
+            })
+            .await?;
+
+        let updated = RecurringPosition {
+            start_date: date(2022, 11, 8),
+            rrule: "FREQ=WEEKLY;BYDAY=TU".into(),
+            ..series.clone()
+        };
+        let res = series
+            .edit_all(&store, &user(1), &updated, date(2022, 11, 30))
+            .await?;
+
+        assert_eq!(2, res.deleted);
+        assert_eq!(vec![3, 4], res.detached);
+        assert!(!Store::<PositionSlot>::exists(&store, &Filter::new()).await?);
+        assert!(!Store::<PositionQualification>::exists(&store, &Filter::new()).await?);
+        assert!(!Store::<PositionConstraint>::exists(&store, &Filter::new()).await?);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_create_recurring_position(pool) -> Result<()> {
+            let series = front_desk().insert(&pool).await?;
+            assert_eq!(series, RecurringPosition::get(&pool, series.id).await?);
+
+            let res = series
+                .materialise(
+                    &PoolStore::from(pool.clone()),
+                    date(2022, 11, 1),
+                    date(2022, 11, 30),
+                )
+                .await?;
+            assert_eq!(4, res.created);
+            assert_eq!(
+                4,
+                Position::count(&pool, &Filter::new().eq("recurring_position_id", series.id))
+                    .await?
+            );
+
+            Ok(())
+        }
+    }
+}
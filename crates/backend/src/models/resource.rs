@@ -1,18 +1,426 @@
 use async_trait::async_trait;
-use sqlx::{
-    postgres::{PgQueryResult, PgRow},
-    PgPool,
-};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use futures::stream::BoxStream;
+use sqlx::{Acquire, Database, Encode, QueryBuilder, Type};
 
 pub use resource_derive::Resource;
 
+/// CRUD for a table. `#[derive(Resource)]` implements it for every supported `Database`, so the
+/// backend is picked by the type of connection passed in. Anything which can hand out a
+/// connection works: a pool, a single connection or an open transaction.
 #[async_trait]
-pub trait Resource: Sized + for<'r> sqlx::FromRow<'r, PgRow> + Unpin + Send {
-    async fn create(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error>;
+pub trait Resource<DB: Database>:
+    Sized + for<'r> sqlx::FromRow<'r, DB::Row> + Unpin + Send
+{
+    async fn create<'c, A>(&self, conn: A) -> Result<DB::QueryResult, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
 
-    async fn get_all(pool: &PgPool) -> Result<Vec<Self>, sqlx::Error>;
+    /// Like `create`, but returns the stored row including its generated primary key.
+    async fn insert<'c, A>(&self, conn: A) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
 
-    async fn update(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error>;
+    /// Inserts the row, or updates the one with the same `key` columns in its place, keeping
+    /// that row's primary key. `key` must be one of the table's unique keys. Returns the stored
+    /// row. It's one statement, so there's no failed insert to spoil an open transaction.
+    async fn upsert<'c, A>(&self, conn: A, key: &[&'static str]) -> Result<Self, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
 
-    async fn delete(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error>;
+    #[allow(dead_code)]
+    async fn get_all<'c, A>(conn: A) -> Result<Vec<Self>, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    /// Yields every row ordered by primary key without collecting the table into memory.
+    #[allow(dead_code)]
+    fn stream_all<'c, A>(conn: A) -> BoxStream<'c, Result<Self, sqlx::Error>>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    /// Yields the rows matching `filter` ordered by primary key without collecting them into
+    /// memory.
+    fn stream<'c, A>(conn: A, filter: &Filter) -> BoxStream<'c, Result<Self, sqlx::Error>>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    async fn update<'c, A>(&self, conn: A) -> Result<DB::QueryResult, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    async fn delete<'c, A>(&self, conn: A) -> Result<DB::QueryResult, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    /// Sets `columns` on every row matching `filter` in a single statement, leaving the other
+    /// columns alone. `None` writes `NULL`.
+    async fn update_where<'c, A>(
+        conn: A,
+        columns: &[(&'static str, Option<Value>)],
+        filter: &Filter,
+    ) -> Result<DB::QueryResult, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    async fn count<'c, A>(conn: A, filter: &Filter) -> Result<i64, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+
+    async fn exists<'c, A>(conn: A, filter: &Filter) -> Result<bool, sqlx::Error>
+    where
+        A: Acquire<'c, Database = DB> + Send + 'c;
+}
+
+/// Table metadata and column access for a `Resource`, used by storage backends which can't
+/// lean on a database to enforce the schema. Implemented by `#[derive(Resource)]`.
+pub trait Record: Clone + Send + Sync + 'static {
+    const TABLE_NAME: &'static str;
+    const PRIMARY_KEY: &'static str;
+    /// The columns of each unique key, from a field marked `#[unique]` or a struct marked
+    /// `#[unique(a, b)]`.
+    const UNIQUE: &'static [&'static [&'static str]];
+    /// `(column, referenced table)` for each column marked `#[references(Type)]`.
+    const REFERENCES: &'static [(&'static str, &'static str)];
+
+    fn id(&self) -> i64;
+
+    fn set_id(&mut self, id: i64);
+
+    /// Every column, including the primary key, with `None` for `NULL`.
+    fn columns(&self) -> Vec<(&'static str, Option<Value>)>;
+}
+
+/// A value which can be compared against a column in a `Filter`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd)]
+pub enum Value {
+    Int(i64),
+    Text(String),
+    Bool(bool),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    Timestamp(DateTime<Utc>),
+}
+
+impl Value {
+    /// Converts a column value, mapping `None` to `NULL`.
+    pub fn of<T: ToValue>(v: &T) -> Option<Value> {
+        v.to_value()
+    }
+
+    /// Appends this value to `query` as a bind parameter.
+    pub fn push_bind<'args, DB: Database>(&self, query: &mut QueryBuilder<'args, DB>)
+    where
+        i64: Encode<'args, DB> + Type<DB>,
+        String: Encode<'args, DB> + Type<DB>,
+        bool: Encode<'args, DB> + Type<DB>,
+        NaiveDate: Encode<'args, DB> + Type<DB>,
+        NaiveTime: Encode<'args, DB> + Type<DB>,
+        DateTime<Utc>: Encode<'args, DB> + Type<DB>,
+    {
+        match self {
+            Value::Int(v) => query.push_bind(*v),
+            Value::Text(v) => query.push_bind(v.clone()),
+            Value::Bool(v) => query.push_bind(*v),
+            Value::Date(v) => query.push_bind(*v),
+            Value::Time(v) => query.push_bind(*v),
+            Value::Timestamp(v) => query.push_bind(*v),
+        };
+    }
+}
+
+pub trait ToValue {
+    fn to_value(&self) -> Option<Value>;
+}
+
+impl<T: Clone + Into<Value>> ToValue for T {
+    fn to_value(&self) -> Option<Value> {
+        Some(self.clone().into())
+    }
+}
+
+impl<T: Clone + Into<Value>> ToValue for Option<T> {
+    fn to_value(&self) -> Option<Value> {
+        self.clone().map(Into::into)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Int(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Text(v.into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Text(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Bool(v)
+    }
+}
+
+impl From<NaiveDate> for Value {
+    fn from(v: NaiveDate) -> Self {
+        Value::Date(v)
+    }
+}
+
+impl From<NaiveTime> for Value {
+    fn from(v: NaiveTime) -> Self {
+        Value::Time(v)
+    }
+}
+
+impl From<DateTime<Utc>> for Value {
+    fn from(v: DateTime<Utc>) -> Self {
+        Value::Timestamp(v)
+    }
+}
+
+/// Declares an enum stored in a text column as the given names, like `'approved'`. It encodes
+/// and decodes on every backend and converts to a `Value` for use in a `Filter`.
+macro_rules! text_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($(#[$vmeta:meta])* $variant:ident => $text:literal),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+        $vis enum $name {
+            $($(#[$vmeta])* #[serde(rename = $text)] $variant),+
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $text),+
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($text => Ok($name::$variant),)+
+                    _ => Err(format!("unknown {}: {}", stringify!($name), s)),
+                }
+            }
+        }
+
+        impl From<$name> for $crate::models::resource::Value {
+            fn from(v: $name) -> Self {
+                $crate::models::resource::Value::Text(v.as_str().into())
+            }
+        }
+
+        impl<DB: sqlx::Database> sqlx::Type<DB> for $name
+        where
+            String: sqlx::Type<DB>,
+        {
+            fn type_info() -> DB::TypeInfo {
+                <String as sqlx::Type<DB>>::type_info()
+            }
+
+            fn compatible(ty: &DB::TypeInfo) -> bool {
+                <String as sqlx::Type<DB>>::compatible(ty)
+            }
+        }
+
+        impl<'q, DB: sqlx::Database> sqlx::Encode<'q, DB> for $name
+        where
+            String: sqlx::Encode<'q, DB>,
+        {
+            fn encode_by_ref(
+                &self,
+                buf: &mut <DB as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+            ) -> sqlx::encode::IsNull {
+                <String as sqlx::Encode<'q, DB>>::encode(self.as_str().to_owned(), buf)
+            }
+        }
+
+        impl<'r, DB: sqlx::Database> sqlx::Decode<'r, DB> for $name
+        where
+            String: sqlx::Decode<'r, DB>,
+        {
+            fn decode(
+                value: <DB as sqlx::database::HasValueRef<'r>>::ValueRef,
+            ) -> Result<Self, sqlx::error::BoxDynError> {
+                let s = <String as sqlx::Decode<'r, DB>>::decode(value)?;
+                Ok(s.parse()?)
+            }
+        }
+    };
+}
+
+pub(crate) use text_enum;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    Compare(&'static str, &'static str, Value),
+    OneOf(&'static str, Vec<Value>),
+    IsNull(&'static str),
+    NotNull(&'static str),
+}
+
+/// A set of conditions which are ANDed together into a `WHERE` clause.
+///
+/// Column names are `&'static str` so they can only come from code, never from user input.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eq(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " = ", value.into())
+    }
+
+    pub fn ne(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " <> ", value.into())
+    }
+
+    pub fn lt(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " < ", value.into())
+    }
+
+    pub fn le(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " <= ", value.into())
+    }
+
+    pub fn gt(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " > ", value.into())
+    }
+
+    pub fn ge(self, column: &'static str, value: impl Into<Value>) -> Self {
+        self.compare(column, " >= ", value.into())
+    }
+
+    /// Matches rows whose `column` is any of `values`, and no rows when there are none.
+    pub fn one_of<V: Into<Value>>(
+        mut self,
+        column: &'static str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        let values = values.into_iter().map(Into::into).collect();
+        self.conditions.push(Condition::OneOf(column, values));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn null(mut self, column: &'static str) -> Self {
+        self.conditions.push(Condition::IsNull(column));
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn not_null(mut self, column: &'static str) -> Self {
+        self.conditions.push(Condition::NotNull(column));
+        self
+    }
+
+    fn compare(mut self, column: &'static str, op: &'static str, value: Value) -> Self {
+        self.conditions.push(Condition::Compare(column, op, value));
+        self
+    }
+
+    /// Evaluates the filter against a row's columns with SQL semantics, so comparisons against
+    /// `NULL` never match.
+    pub fn matches(&self, columns: &[(&'static str, Option<Value>)]) -> bool {
+        let column = |name: &str| {
+            columns
+                .iter()
+                .find(|(c, _)| *c == name)
+                .and_then(|(_, v)| v.as_ref())
+        };
+
+        self.conditions.iter().all(|c| match c {
+            Condition::Compare(name, op, value) => match column(name) {
+                Some(v) => match *op {
+                    " = " => v == value,
+                    " <> " => v != value,
+                    " < " => v < value,
+                    " <= " => v <= value,
+                    " > " => v > value,
+                    " >= " => v >= value,
+                    _ => unreachable!("unknown operator {}", op),
+                },
+                None => false,
+            },
+            Condition::OneOf(name, values) => column(name).is_some_and(|v| values.contains(v)),
+            Condition::IsNull(name) => column(name).is_none(),
+            Condition::NotNull(name) => column(name).is_some(),
+        })
+    }
+
+    /// Appends the `WHERE` clause for this filter to `query`. Does nothing for an empty filter.
+    pub fn push_where<'args, DB: Database>(&self, query: &mut QueryBuilder<'args, DB>)
+    where
+        i64: Encode<'args, DB> + Type<DB>,
+        String: Encode<'args, DB> + Type<DB>,
+        bool: Encode<'args, DB> + Type<DB>,
+        NaiveDate: Encode<'args, DB> + Type<DB>,
+        NaiveTime: Encode<'args, DB> + Type<DB>,
+        DateTime<Utc>: Encode<'args, DB> + Type<DB>,
+    {
+        if self.conditions.is_empty() {
+            return;
+        }
+
+        query.push(" WHERE ");
+        for (i, c) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                query.push(" AND ");
+            }
+            match c {
+                Condition::Compare(column, op, value) => {
+                    query.push(column).push(op);
+                    value.push_bind(query);
+                }
+                // `IN ()` isn't valid in Postgres.
+                Condition::OneOf(_, values) if values.is_empty() => {
+                    query.push("1 = 0");
+                }
+                Condition::OneOf(column, values) => {
+                    query.push(column).push(" IN (");
+                    for (i, value) in values.iter().enumerate() {
+                        if i > 0 {
+                            query.push(", ");
+                        }
+                        value.push_bind(query);
+                    }
+                    query.push(")");
+                }
+                Condition::IsNull(column) => {
+                    query.push(column).push(" IS NULL");
+                }
+                Condition::NotNull(column) => {
+                    query.push(column).push(" IS NOT NULL");
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,617 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::holiday::Holiday;
+use super::positions::Position;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduleStore, ScheduledPosition};
+use super::store::{Store, Transactional};
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone::{midnight, to_instant};
+
+const NOT_MANAGER: &str = "only managers of the team may manage its templates";
+
+/// A team's week saved under a name, so it can be copied forward onto later weeks.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(team_id, name)]
+pub struct ScheduleTemplate {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub name: String,
+}
+
+/// A position in a template. Offsets are minutes of wall-clock time after midnight at the start
+/// of the week, so a 9am shift stays at 9am in weeks with a DST change.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct TemplatePosition {
+    #[primary_key]
+    pub id: i64,
+    #[references(ScheduleTemplate)]
+    pub template_id: i64,
+    pub name: String,
+    pub start_offset: i64,
+    pub end_offset: i64,
+    pub min_headcount: i64,
+    pub max_headcount: i64,
+    pub adults_only: bool,
+}
+
+/// Someone working a template position, for templates saved with their assignments.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct TemplateAssignment {
+    #[primary_key]
+    pub id: i64,
+    #[references(TemplatePosition)]
+    pub template_position_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+}
+
+/// What applying a template to the week from `week_start` creates. For a dry run nothing is
+/// saved, so positions have no ids yet and their assignments' `position_id`s are 0.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Copied {
+    pub week_start: NaiveDate,
+    pub positions: Vec<CopiedPosition>,
+    /// Positions the week already has with the same name and times as template positions,
+    /// which were left as they are rather than copied again.
+    pub existing: Vec<Position>,
+    /// Holidays in the week. Positions starting on them are left out.
+    pub holidays: Vec<Holiday>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CopiedPosition {
+    pub template_position_id: i64,
+    pub position: Position,
+    pub assignments: Vec<ScheduledPosition>,
+    /// Template assignments which couldn't be copied, like people already working then.
+    pub conflicts: Vec<Blocked>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Blocked {
+    pub user_id: i64,
+    pub error: String,
+}
+
+impl ScheduleTemplate {
+    /// Saves the team's positions starting in the seven days from `week_start` as a template
+    /// called `name`, along with who works them if `with_assignments` is set. Only managers of
+    /// the team may do this. Slots aren't saved, so copies are single positions. The template is
+    /// saved whole or not at all.
+    #[allow(dead_code)]
+    pub async fn save<S>(
+        store: &S,
+        by: &User,
+        team_id: i64,
+        name: &str,
+        week_start: NaiveDate,
+        with_assignments: bool,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<TemplatePosition>
+            + Store<TemplateAssignment>
+            + Store<Team>
+            + Store<TeamMember>
+            + Store<Position>
+            + Store<ScheduledPosition>
+            + Transactional,
+    {
+        TeamMember::require_manager(store, team_id, by, NOT_MANAGER).await?;
+        let tx = store.begin().await?;
+        let store = &tx;
+        let taken = Store::<Self>::exists(
+            store,
+            &Filter::new().eq("team_id", team_id).eq("name", name),
+        )
+        .await?;
+        if taken {
+            return Err(ScheduleError::Invalid(
+                "the team already has a template with that name",
+            ));
+        }
+
+        let team: Team = store.get(team_id).await?;
+        let tz = team.tz()?;
+        let positions: Vec<Position> = store
+            .find(
+                &Filter::new()
+                    .eq("team_id", team_id)
+                    .ge("starts_at", midnight(tz, week_start))
+                    .lt("starts_at", midnight(tz, week_start + Duration::days(7))),
+            )
+            .await?;
+
+        let template = store
+            .insert(&ScheduleTemplate {
+                id: 0,
+                team_id,
+                name: name.into(),
+            })
+            .await?;
+        let offset = |t: DateTime<Utc>| {
+            (t.with_timezone(&tz).naive_local() - week_start.and_hms(0, 0, 0)).num_minutes()
+        };
+        for position in positions {
+            let saved = store
+                .insert(&TemplatePosition {
+                    id: 0,
+                    template_id: template.id,
+                    name: position.name.clone(),
+                    start_offset: offset(position.starts_at),
+                    end_offset: offset(position.ends_at),
+                    min_headcount: position.min_headcount,
+                    max_headcount: position.max_headcount,
+                    adults_only: position.adults_only,
+                })
+                .await?;
+            if !with_assignments {
+                continue;
+            }
+            let assignments: Vec<ScheduledPosition> = store
+                .find(&ScheduledPosition::active().eq("position_id", position.id))
+                .await?;
+            for sp in assignments {
+                store
+                    .create(&TemplateAssignment {
+                        id: 0,
+                        template_position_id: saved.id,
+                        user_id: sp.user_id,
+                    })
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(template)
+    }
+
+    /// Deletes the template on behalf of `by`, who must manage the team. Positions already
+    /// copied from it are kept.
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<Self>
+            + Store<TemplatePosition>
+            + Store<TemplateAssignment>
+            + Store<TeamMember>
+            + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let existing: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, existing.team_id, by, NOT_MANAGER).await?;
+        for tp in existing.positions(store).await? {
+            let assignments: Vec<TemplateAssignment> = store
+                .find(&Filter::new().eq("template_position_id", tp.id))
+                .await?;
+            for ta in assignments {
+                store.delete(&ta).await?;
+            }
+            store.delete(&tp).await?;
+        }
+        store.delete(&existing).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn positions<S: Store<TemplatePosition>>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<TemplatePosition>, sqlx::Error> {
+        store.find(&Filter::new().eq("template_id", self.id)).await
+    }
+
+    /// A dry run of `apply` on behalf of `by`, who must manage the team: what copying the
+    /// template onto the week from `week_start` would create, and which assignments would be
+    /// refused. The copy is made in a transaction which is then rolled back, so it gets exactly
+    /// the checks `apply` would, and nothing is saved.
+    #[allow(dead_code)]
+    pub async fn preview<S>(
+        &self,
+        store: &S,
+        by: &User,
+        week_start: NaiveDate,
+    ) -> Result<Copied, ScheduleError>
+    where
+        S: ScheduleStore
+            + Store<Self>
+            + Store<TeamMember>
+            + Store<TemplatePosition>
+            + Store<TemplateAssignment>
+            + Store<Holiday>,
+    {
+        let tx = store.begin().await?;
+        let mut copied = self.copy(&tx, by, week_start).await?;
+        tx.rollback().await?;
+
+        for planned in &mut copied.positions {
+            planned.position.id = 0;
+            for sp in &mut planned.assignments {
+                sp.id = 0;
+                sp.position_id = 0;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Copies the template onto the week from `week_start` on behalf of `by`, who must manage
+    /// the team. Positions on the team's holidays are left out, as are ones the week already
+    /// has with the same name and times, so applying a template twice doesn't duplicate them.
+    /// Assignments go through the usual scheduling checks, and any refused are reported rather
+    /// than failing the copy.
+    #[allow(dead_code)]
+    pub async fn apply<S>(
+        &self,
+        store: &S,
+        by: &User,
+        week_start: NaiveDate,
+    ) -> Result<Copied, ScheduleError>
+    where
+        S: ScheduleStore
+            + Store<Self>
+            + Store<TeamMember>
+            + Store<TemplatePosition>
+            + Store<TemplateAssignment>
+            + Store<Holiday>,
+    {
+        let tx = store.begin().await?;
+        let copied = self.copy(&tx, by, week_start).await?;
+        tx.commit().await?;
+        Ok(copied)
+    }
+
+    async fn copy<S>(
+        &self,
+        store: &S,
+        by: &User,
+        week_start: NaiveDate,
+    ) -> Result<Copied, ScheduleError>
+    where
+        S: ScheduleStore
+            + Store<Self>
+            + Store<TeamMember>
+            + Store<TemplatePosition>
+            + Store<TemplateAssignment>
+            + Store<Holiday>,
+    {
+        let template: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, template.team_id, by, NOT_MANAGER).await?;
+        let team: Team = store.get(template.team_id).await?;
+        let tz = team.tz()?;
+        if week_start <= Utc::now().with_timezone(&tz).date_naive() {
+            return Err(ScheduleError::Invalid(
+                "templates can only be applied to future weeks",
+            ));
+        }
+
+        let holidays = Holiday::between(
+            store,
+            template.team_id,
+            week_start,
+            week_start + Duration::days(6),
+        )
+        .await?;
+        let mut copied = Copied {
+            week_start,
+            positions: Vec::new(),
+            existing: Vec::new(),
+            holidays,
+        };
+        for tp in template.positions(store).await? {
+            let at =
+                |offset| to_instant(tz, week_start.and_hms(0, 0, 0) + Duration::minutes(offset));
+            let position = Position {
+                team_id: template.team_id,
+                name: tp.name.clone(),
+                starts_at: at(tp.start_offset),
+                ends_at: at(tp.end_offset),
+                min_headcount: tp.min_headcount,
+                max_headcount: tp.max_headcount,
+                adults_only: tp.adults_only,
+                ..Default::default()
+            };
+            let date = position.starts_at.with_timezone(&tz).date_naive();
+            if copied.holidays.iter().any(|h| h.date == date) {
+                continue;
+            }
+            let existing: Vec<Position> = store
+                .find(
+                    &Filter::new()
+                        .eq("team_id", position.team_id)
+                        .eq("name", position.name.as_str())
+                        .eq("starts_at", position.starts_at)
+                        .eq("ends_at", position.ends_at),
+                )
+                .await?;
+            if !existing.is_empty() {
+                copied.existing.extend(existing);
+                continue;
+            }
+
+            let mut planned = CopiedPosition {
+                template_position_id: tp.id,
+                position: store.insert(&position).await?,
+                assignments: Vec::new(),
+                conflicts: Vec::new(),
+            };
+            let assignments: Vec<TemplateAssignment> = store
+                .find(&Filter::new().eq("template_position_id", tp.id))
+                .await?;
+            for ta in assignments {
+                let sp = ScheduledPosition {
+                    position_id: planned.position.id,
+                    user_id: ta.user_id,
+                    ..Default::default()
+                };
+                match sp.schedule(store, by).await {
+                    Ok(scheduled) => planned.assignments.push(scheduled.assignment),
+                    Err(ScheduleError::Database(e)) => return Err(e.into()),
+                    Err(e) => planned.conflicts.push(Blocked {
+                        user_id: ta.user_id,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+            copied.positions.push(planned);
+        }
+
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod schedule_template_tests {
+    use crate::models::db_test;
+    use crate::models::holiday::Holiday;
+    use crate::models::positions::Position;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::schedule_template::{
+        ScheduleTemplate, TemplateAssignment, TemplatePosition,
+    };
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::team_member::TeamMember;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+    use chrono_tz::Europe::London;
+
+    /// The Monday after next, so always in the future.
+    fn next_week() -> NaiveDate {
+        let today = Utc::now().with_timezone(&London).date_naive();
+        today + Duration::days(14 - today.weekday().num_days_from_monday() as i64)
+    }
+
+    /// User 1 manages team 1, in London. In the week from Monday 7 Nov 2022 user 2 works the
+    /// Monday 9-5 and user 3 the Tuesday night shift into Wednesday, which only adults may work.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store
+            .create(&Team {
+                time_zone: "Europe/London".into(),
+                ..Default::default()
+            })
+            .await?;
+        people(&store, 3, &[(1, 1, true), (1, 2, false), (1, 3, false)]).await?;
+        let shifts = [("Day", (7, 9), (7, 17), 2), ("Night", (8, 22), (9, 6), 3)];
+        for (name, (d1, h1), (d2, h2), user_id) in shifts {
+            let position = store
+                .insert(&Position {
+                    team_id: 1,
+                    name: name.into(),
+                    starts_at: Utc.ymd(2022, 11, d1).and_hms(h1, 0, 0),
+                    ends_at: Utc.ymd(2022, 11, d2).and_hms(h2, 0, 0),
+                    adults_only: name == "Night",
+                    ..Default::default()
+                })
+                .await?;
+            store
+                .create(&ScheduledPosition {
+                    position_id: position.id,
+                    user_id,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(store)
+    }
+
+    async fn save(store: &MemoryStore, by: i64) -> Result<ScheduleTemplate, ScheduleError> {
+        let week = NaiveDate::from_ymd(2022, 11, 7);
+        ScheduleTemplate::save(store, &user(by), 1, "Usual", week, true).await
+    }
+
+    #[actix_web::test]
+    async fn test_save_template() -> Result<()> {
+        let store = setup().await?;
+        assert!(matches!(
+            save(&store, 2).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let template = save(&store, 1).await?;
+        assert!(matches!(
+            save(&store, 1).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        let offsets: Vec<(i64, i64, bool)> = template
+            .positions(&store)
+            .await?
+            .iter()
+            .map(|tp| (tp.start_offset, tp.end_offset, tp.adults_only))
+            .collect();
+        assert_eq!(
+            vec![(9 * 60, 17 * 60, false), (46 * 60, 54 * 60, true)],
+            offsets
+        );
+        let assigned: Vec<TemplateAssignment> = store.find(&Filter::new()).await?;
+        assert_eq!(2, assigned.len());
+
+        // User 2 manages team 2, but that doesn't let them pass team 1's template off as theirs.
+        store
+            .create(&Team {
+                time_zone: "Europe/London".into(),
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&TeamMember {
+                id: 0,
+                team_id: 2,
+                user_id: 2,
+                manager: true,
+            })
+            .await?;
+        let forged = ScheduleTemplate {
+            team_id: 2,
+            ..template.clone()
+        };
+        assert!(matches!(
+            forged.remove(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assert!(matches!(
+            forged.apply(&store, &user(2), next_week()).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let holiday = Holiday {
+            id: 0,
+            team_id: 1,
+            date: next_week(),
+            name: "Founders' day".into(),
+        };
+        let holiday = holiday.add(&store, &user(1)).await?;
+        let forged = Holiday {
+            team_id: 2,
+            ..holiday.clone()
+        };
+        assert!(matches!(
+            forged.remove(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        holiday.remove(&store, &user(1)).await?;
+
+        template.remove(&store, &user(1)).await?;
+        assert!(!Store::<TemplatePosition>::exists(&store, &Filter::new()).await?);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_apply_template() -> Result<()> {
+        let store = setup().await?;
+        let template = save(&store, 1).await?;
+        let week = next_week();
+        assert!(matches!(
+            template
+                .preview(&store, &user(1), NaiveDate::from_ymd(2022, 11, 14))
+                .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // User 2 already works Monday lunchtime in the new week.
+        let local = |d: NaiveDate, h| London.from_local_datetime(&d.and_hms(h, 0, 0)).unwrap();
+        let lunch = store
+            .insert(&Position {
+                team_id: 1,
+                starts_at: local(week, 12).with_timezone(&Utc),
+                ends_at: local(week, 13).with_timezone(&Utc),
+                ..Default::default()
+            })
+            .await?;
+        store
+            .create(&ScheduledPosition {
+                position_id: lunch.id,
+                user_id: 2,
+                ..Default::default()
+            })
+            .await?;
+
+        assert!(matches!(
+            template.preview(&store, &user(2), week).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let dry = template.preview(&store, &user(1), week).await?;
+        assert_eq!(2, dry.positions.len());
+        assert_eq!(0, dry.positions[0].position.id);
+        assert_eq!(2, dry.positions[0].conflicts[0].user_id);
+        assert_eq!(3, dry.positions[1].assignments[0].user_id);
+        assert!(dry.positions[1].position.adults_only);
+        assert_eq!(3, Store::<Position>::count(&store, &Filter::new()).await?);
+
+        // Tuesday is a holiday, so the night shift isn't copied.
+        let holiday = Holiday {
+            id: 0,
+            team_id: 1,
+            date: week + Duration::days(1),
+            name: "Founders' day".into(),
+        };
+        holiday.add(&store, &user(1)).await?;
+
+        assert!(matches!(
+            template.apply(&store, &user(2), week).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let copied = template.apply(&store, &user(1), week).await?;
+        assert_eq!(1, copied.holidays.len());
+        assert_eq!(1, copied.positions.len());
+        let day = &copied.positions[0];
+        assert_ne!(0, day.position.id);
+        assert_eq!(
+            NaiveTime::from_hms(9, 0, 0),
+            day.position.starts_at.with_timezone(&London).time()
+        );
+        assert!(day.assignments.is_empty());
+        assert_eq!(1, day.conflicts.len());
+        assert_eq!(4, Store::<Position>::count(&store, &Filter::new()).await?);
+
+        // Applying it again leaves the week alone.
+        let again = template.apply(&store, &user(1), week).await?;
+        assert!(again.positions.is_empty());
+        assert_eq!(vec![day.position.clone()], again.existing);
+        assert_eq!(4, Store::<Position>::count(&store, &Filter::new()).await?);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams")]
+        async fn test_create_template(pool) -> Result<()> {
+            let template = ScheduleTemplate {
+                id: 0,
+                team_id: 1,
+                name: "Usual".into(),
+            };
+            let template = template.insert(&pool).await?;
+            let tp = TemplatePosition {
+                template_id: template.id,
+                name: "Day".into(),
+                start_offset: 9 * 60,
+                end_offset: 17 * 60,
+                min_headcount: 1,
+                max_headcount: 2,
+                ..Default::default()
+            };
+            let tp = tp.insert(&pool).await?;
+            assert_eq!(tp, TemplatePosition::get(&pool, tp.id).await?);
+            assert!(template.clone().create(&pool).await.is_err());
+
+            let backwards = TemplatePosition {
+                end_offset: 8 * 60,
+                ..tp
+            };
+            assert!(backwards.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::{
+    AssignmentStatus, ScheduleError, ScheduleStore, ScheduledPosition,
+};
+use super::store::{Store, Transactional};
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone::to_instant;
+
+const NOT_MANAGER: &str = "only managers of the team may publish its schedule";
+
+/// A published schedule: a numbered snapshot of the team's assignments to positions starting
+/// from `starts_on` through `ends_on`. The live `ScheduledPosition`s are the draft managers
+/// edit, and everyone else only sees what was last published. Versions are never changed once
+/// published, which the database enforces.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[unique(team_id, starts_on, ends_on, number)]
+pub struct ScheduleVersion {
+    #[primary_key]
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    /// Counts up from 1 for each team and period.
+    pub number: i64,
+    pub published_at: DateTime<Utc>,
+    #[references(User)]
+    pub published_by: i64,
+    /// The version this one restored, if it was published by a rollback.
+    #[references(ScheduleVersion)]
+    pub rollback_of: Option<i64>,
+}
+
+/// An assignment as it was published. `position_id` and `slot_id` aren't references, so
+/// positions can still be deleted after they've been published.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct VersionAssignment {
+    #[primary_key]
+    pub id: i64,
+    #[references(ScheduleVersion)]
+    pub version_id: i64,
+    pub position_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub slot_id: Option<i64>,
+}
+
+/// Who works where, as compared between versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Assigned {
+    pub position_id: i64,
+    pub user_id: i64,
+    pub slot_id: Option<i64>,
+}
+
+impl From<&ScheduledPosition> for Assigned {
+    fn from(sp: &ScheduledPosition) -> Self {
+        Assigned {
+            position_id: sp.position_id,
+            user_id: sp.user_id,
+            slot_id: sp.slot_id,
+        }
+    }
+}
+
+impl From<&VersionAssignment> for Assigned {
+    fn from(va: &VersionAssignment) -> Self {
+        Assigned {
+            position_id: va.position_id,
+            user_id: va.user_id,
+            slot_id: va.slot_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Move {
+    pub from: Assigned,
+    pub to: Assigned,
+}
+
+/// How one schedule differs from another. Someone taken off one place and put on another is a
+/// move rather than a removal and an addition.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    pub added: Vec<Assigned>,
+    pub removed: Vec<Assigned>,
+    pub moved: Vec<Move>,
+}
+
+impl Diff {
+    pub fn between(old: &[Assigned], new: &[Assigned]) -> Self {
+        let mut removed: Vec<Assigned> = old.iter().filter(|a| !new.contains(a)).copied().collect();
+        let mut added: Vec<Assigned> = new.iter().filter(|a| !old.contains(a)).copied().collect();
+        removed.sort();
+        added.sort();
+
+        let mut moved = Vec::new();
+        removed.retain(|from| {
+            // Prefer a move to another slot of the same position.
+            let to = added
+                .iter()
+                .position(|to| to.user_id == from.user_id && to.position_id == from.position_id)
+                .or_else(|| added.iter().position(|to| to.user_id == from.user_id));
+            match to {
+                Some(i) => {
+                    moved.push(Move {
+                        from: *from,
+                        to: added.remove(i),
+                    });
+                    false
+                }
+                None => true,
+            }
+        });
+
+        Diff {
+            added,
+            removed,
+            moved,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// The result of a rollback. Assignments which can no longer be made, because their position
+/// was deleted or the person can't work it any more, are skipped.
+#[derive(Debug, Clone, Serialize)]
+pub struct RolledBack {
+    pub version: ScheduleVersion,
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Skipped {
+    pub assignment: Assigned,
+    pub error: String,
+}
+
+impl ScheduleVersion {
+    /// Publishes the team's draft for the period on behalf of `by`, who must manage the team.
+    #[allow(dead_code)]
+    pub async fn publish<S>(
+        store: &S,
+        by: &User,
+        team_id: i64,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<VersionAssignment>
+            + Store<Team>
+            + Store<TeamMember>
+            + Store<Position>
+            + Store<ScheduledPosition>
+            + Transactional,
+    {
+        TeamMember::require_manager(store, team_id, by, NOT_MANAGER).await?;
+        if ends_on < starts_on {
+            return Err(ScheduleError::Invalid(
+                "schedule periods can't end before they start",
+            ));
+        }
+        let draft = draft(store, team_id, starts_on, ends_on).await?;
+        Ok(Self::snapshot(store, by, team_id, starts_on, ends_on, &draft, None).await?)
+    }
+
+    /// Saves `assignments` as the period's next version, in one transaction so that a failure
+    /// can't leave a partial version behind for good.
+    async fn snapshot<S>(
+        store: &S,
+        by: &User,
+        team_id: i64,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+        assignments: &[Assigned],
+        rollback_of: Option<i64>,
+    ) -> Result<Self, sqlx::Error>
+    where
+        S: Store<Self> + Store<VersionAssignment> + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let number = match Self::latest(store, team_id, starts_on, ends_on).await? {
+            Some(latest) => latest.number + 1,
+            None => 1,
+        };
+        let version = store
+            .insert(&ScheduleVersion {
+                id: 0,
+                team_id,
+                starts_on,
+                ends_on,
+                number,
+                published_at: Utc::now(),
+                published_by: by.id,
+                rollback_of,
+            })
+            .await?;
+        for a in assignments {
+            store
+                .create(&VersionAssignment {
+                    id: 0,
+                    version_id: version.id,
+                    position_id: a.position_id,
+                    user_id: a.user_id,
+                    slot_id: a.slot_id,
+                })
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(version)
+    }
+
+    /// The period's most recently published version, if it's been published.
+    pub async fn latest<S: Store<Self>>(
+        store: &S,
+        team_id: i64,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let versions: Vec<Self> = store
+            .find(
+                &Filter::new()
+                    .eq("team_id", team_id)
+                    .eq("starts_on", starts_on)
+                    .eq("ends_on", ends_on),
+            )
+            .await?;
+        Ok(versions.into_iter().max_by_key(|v| v.number))
+    }
+
+    pub async fn assignments<S: Store<VersionAssignment>>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<Assigned>, sqlx::Error> {
+        let assignments: Vec<VersionAssignment> =
+            store.find(&Filter::new().eq("version_id", self.id)).await?;
+        Ok(assignments.iter().map(Assigned::from).collect())
+    }
+
+    /// What changed from this version to `other`.
+    #[allow(dead_code)]
+    pub async fn diff<S: Store<VersionAssignment>>(
+        &self,
+        store: &S,
+        other: &ScheduleVersion,
+    ) -> Result<Diff, sqlx::Error> {
+        Ok(Diff::between(
+            &self.assignments(store).await?,
+            &other.assignments(store).await?,
+        ))
+    }
+
+    /// What publishing the period's draft would change.
+    #[allow(dead_code)]
+    pub async fn unpublished<S>(
+        store: &S,
+        team_id: i64,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+    ) -> Result<Diff, sqlx::Error>
+    where
+        S: Store<Self>
+            + Store<VersionAssignment>
+            + Store<Team>
+            + Store<Position>
+            + Store<ScheduledPosition>,
+    {
+        let published = match Self::latest(store, team_id, starts_on, ends_on).await? {
+            Some(latest) => latest.assignments(store).await?,
+            None => Vec::new(),
+        };
+        let draft = draft(store, team_id, starts_on, ends_on).await?;
+        Ok(Diff::between(&published, &draft))
+    }
+
+    /// Restores this version's assignments to the draft and publishes them again as a new
+    /// version, on behalf of `by`, who must manage the team. Assignments not in the version are
+    /// cancelled, and restored ones go through the usual scheduling checks. Either all of it
+    /// happens or, on an error, none of it does.
+    #[allow(dead_code)]
+    pub async fn rollback<S>(&self, store: &S, by: &User) -> Result<RolledBack, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<VersionAssignment> + Store<TeamMember>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        // Only trust the stored version, not whatever team or period the caller passed in.
+        let version: Self = store.get(self.id).await?;
+        TeamMember::require_manager(store, version.team_id, by, NOT_MANAGER).await?;
+        let target = version.assignments(store).await?;
+
+        // Cancel what's going first, so people moving between positions don't clash with
+        // themselves. Cancelling rather than deleting keeps the assignments' history.
+        let mut kept = Vec::new();
+        for sp in
+            draft_assignments(store, version.team_id, version.starts_on, version.ends_on).await?
+        {
+            let assigned = Assigned::from(&sp);
+            if target.contains(&assigned) {
+                kept.push(assigned);
+            } else {
+                sp.set_status(store, by, AssignmentStatus::Cancelled)
+                    .await?;
+            }
+        }
+
+        let missing: Vec<Assigned> = target.into_iter().filter(|a| !kept.contains(a)).collect();
+        let mut skipped = Vec::new();
+        for a in &missing {
+            if !Store::<Position>::exists(store, &Filter::new().eq("id", a.position_id)).await? {
+                skipped.push(Skipped {
+                    assignment: *a,
+                    error: "position has been deleted".into(),
+                });
+                continue;
+            }
+            let sp = ScheduledPosition {
+                position_id: a.position_id,
+                user_id: a.user_id,
+                slot_id: a.slot_id,
+                ..Default::default()
+            };
+            match sp.schedule(store, by).await {
+                Ok(_) => kept.push(*a),
+                Err(ScheduleError::Database(e)) => return Err(e.into()),
+                Err(e) => skipped.push(Skipped {
+                    assignment: *a,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        let version = Self::snapshot(
+            store,
+            by,
+            version.team_id,
+            version.starts_on,
+            version.ends_on,
+            &kept,
+            Some(version.id),
+        )
+        .await?;
+        tx.commit().await?;
+
+        Ok(RolledBack { version, skipped })
+    }
+}
+
+/// The team's assignments from `from` through `to` as `viewer` may see them. Managers and admins
+/// see the draft. Everyone else sees, for each position, the latest version published for a
+/// period covering its date, and nothing for positions which haven't been published.
+#[allow(dead_code)]
+pub async fn visible<S>(
+    store: &S,
+    viewer: &User,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<Assigned>, sqlx::Error>
+where
+    S: Store<ScheduleVersion>
+        + Store<VersionAssignment>
+        + Store<Team>
+        + Store<TeamMember>
+        + Store<Position>
+        + Store<ScheduledPosition>,
+{
+    if viewer.admin || TeamMember::is_manager(store, team_id, viewer.id).await? {
+        return draft(store, team_id, from, to).await;
+    }
+
+    let team: Team = store.get(team_id).await?;
+    let tz = team.tz()?;
+    let mut versions: Vec<ScheduleVersion> = store
+        .find(
+            &Filter::new()
+                .eq("team_id", team_id)
+                .le("starts_on", to)
+                .ge("ends_on", from),
+        )
+        .await?;
+    versions.sort_by_key(|v| std::cmp::Reverse((v.published_at, v.id)));
+
+    let mut visible = Vec::new();
+    for position in positions(store, team_id, from, to).await? {
+        let date = position.starts_at.with_timezone(&tz).date_naive();
+        let version = versions
+            .iter()
+            .find(|v| v.starts_on <= date && date <= v.ends_on);
+        if let Some(version) = version {
+            let published: Vec<VersionAssignment> = store
+                .find(
+                    &Filter::new()
+                        .eq("version_id", version.id)
+                        .eq("position_id", position.id),
+                )
+                .await?;
+            visible.extend(published.iter().map(Assigned::from));
+        }
+    }
+
+    Ok(visible)
+}
+
+/// Whether a schedule covering the position's date has been published for its team, so people
+/// other than its managers may see it.
+pub async fn is_published<S>(store: &S, position: &Position) -> Result<bool, sqlx::Error>
+where
+    S: Store<ScheduleVersion> + Store<Team>,
+{
+    let team: Team = store.get(position.team_id).await?;
+    let date = position.starts_at.with_timezone(&team.tz()?).date_naive();
+    Store::<ScheduleVersion>::exists(
+        store,
+        &Filter::new()
+            .eq("team_id", position.team_id)
+            .le("starts_on", date)
+            .ge("ends_on", date),
+    )
+    .await
+}
+
+/// The live assignments to the team's positions starting from `from` through `to`.
+async fn draft<S>(
+    store: &S,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<Assigned>, sqlx::Error>
+where
+    S: Store<Team> + Store<Position> + Store<ScheduledPosition>,
+{
+    let assignments = draft_assignments(store, team_id, from, to).await?;
+    Ok(assignments.iter().map(Assigned::from).collect())
+}
+
+async fn draft_assignments<S>(
+    store: &S,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<ScheduledPosition>, sqlx::Error>
+where
+    S: Store<Team> + Store<Position> + Store<ScheduledPosition>,
+{
+    let mut assignments = Vec::new();
+    for position in positions(store, team_id, from, to).await? {
+        let filled: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::active().eq("position_id", position.id))
+            .await?;
+        assignments.extend(filled);
+    }
+    Ok(assignments)
+}
+
+/// The team's positions starting from `from` through `to` in its time zone.
+async fn positions<S>(
+    store: &S,
+    team_id: i64,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Vec<Position>, sqlx::Error>
+where
+    S: Store<Team> + Store<Position>,
+{
+    let team: Team = store.get(team_id).await?;
+    let tz = team.tz()?;
+    store
+        .find(
+            &Filter::new()
+                .eq("team_id", team_id)
+                .ge("starts_at", to_instant(tz, from.and_hms(0, 0, 0)))
+                .lt(
+                    "starts_at",
+                    to_instant(tz, (to + Duration::days(1)).and_hms(0, 0, 0)),
+                ),
+        )
+        .await
+}
+
+#[cfg(test)]
+mod schedule_version_tests {
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::schedule_version::{
+        visible, Assigned, Move, ScheduleVersion, VersionAssignment,
+    };
+    use crate::models::scheduled_position::{AssignmentStatus, ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2022, 11, d)
+    }
+
+    fn at(position_id: i64, user_id: i64) -> Assigned {
+        Assigned {
+            position_id,
+            user_id,
+            slot_id: None,
+        }
+    }
+
+    /// User 1 manages team 1, which users 2 and 3 work on. Position 1 is on Monday 7 Nov and
+    /// position 2 on the Tuesday, and user 2 works Monday.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, true), (1, 2, false), (1, 3, false)]).await?;
+        for d in [7, 8] {
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: Utc.ymd(2022, 11, d).and_hms(9, 0, 0),
+                    ends_at: Utc.ymd(2022, 11, d).and_hms(17, 0, 0),
+                    max_headcount: 2,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        assign(&store, 1, 2).await?;
+        Ok(store)
+    }
+
+    async fn assign(store: &MemoryStore, position_id: i64, user_id: i64) -> Result<()> {
+        let sp = ScheduledPosition {
+            position_id,
+            user_id,
+            ..Default::default()
+        };
+        sp.schedule(store, &user(1)).await?;
+        Ok(())
+    }
+
+    async fn publish(store: &MemoryStore, by: i64) -> Result<ScheduleVersion, ScheduleError> {
+        ScheduleVersion::publish(store, &user(by), 1, date(7), date(13)).await
+    }
+
+    #[actix_web::test]
+    async fn test_publish() -> Result<()> {
+        let store = setup().await?;
+        let seen = |id| {
+            let store = &store;
+            async move { visible(store, &user(id), 1, date(7), date(13)).await }
+        };
+        assert!(seen(2).await?.is_empty());
+        assert!(matches!(
+            publish(&store, 2).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let v1 = publish(&store, 1).await?;
+        assert_eq!(1, v1.number);
+        assert_eq!(vec![at(1, 2)], seen(2).await?);
+
+        // Draft changes stay hidden until they're published.
+        let sp: Vec<ScheduledPosition> = store.find(&Filter::new()).await?;
+        sp[0]
+            .set_status(&store, &user(1), AssignmentStatus::Cancelled)
+            .await?;
+        assign(&store, 2, 2).await?;
+        assign(&store, 1, 3).await?;
+        assert_eq!(vec![at(1, 2)], seen(3).await?);
+        assert_eq!(vec![at(1, 3), at(2, 2)], seen(1).await?);
+
+        let changes = ScheduleVersion::unpublished(&store, 1, date(7), date(13)).await?;
+        assert_eq!(vec![at(1, 3)], changes.added);
+        assert_eq!(
+            vec![Move {
+                from: at(1, 2),
+                to: at(2, 2)
+            }],
+            changes.moved
+        );
+
+        let v2 = publish(&store, 1).await?;
+        assert_eq!(2, v2.number);
+        assert_eq!(changes, v1.diff(&store, &v2).await?);
+        assert_eq!(vec![at(1, 3), at(2, 2)], seen(3).await?);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_rollback() -> Result<()> {
+        let store = setup().await?;
+        let v1 = publish(&store, 1).await?;
+        assign(&store, 2, 2).await?;
+        assign(&store, 2, 3).await?;
+        publish(&store, 1).await?;
+
+        assert!(matches!(
+            v1.rollback(&store, &user(3)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        // The stored version decides what's rolled back, not what the caller passes in.
+        let forged = ScheduleVersion {
+            starts_on: date(20),
+            ends_on: date(26),
+            ..v1.clone()
+        };
+        let rolled_back = forged.rollback(&store, &user(1)).await?;
+        assert!(rolled_back.skipped.is_empty());
+        assert_eq!(3, rolled_back.version.number);
+        assert_eq!(Some(v1.id), rolled_back.version.rollback_of);
+        assert_eq!(
+            (date(7), date(13)),
+            (rolled_back.version.starts_on, rolled_back.version.ends_on)
+        );
+
+        let sp: Vec<ScheduledPosition> = store.find(&ScheduledPosition::active()).await?;
+        assert_eq!(
+            vec![at(1, 2)],
+            sp.iter().map(Assigned::from).collect::<Vec<_>>()
+        );
+        assert!(ScheduleVersion::unpublished(&store, 1, date(7), date(13))
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions")]
+        async fn test_versions_are_immutable(pool) -> Result<()> {
+            let version = ScheduleVersion {
+                id: 0,
+                team_id: 1,
+                starts_on: date(7),
+                ends_on: date(13),
+                number: 1,
+                published_at: Utc::now(),
+                published_by: 1,
+                rollback_of: None,
+            };
+            let version = version.insert(&pool).await?;
+            let va = VersionAssignment {
+                id: 0,
+                version_id: version.id,
+                position_id: 1,
+                user_id: 1,
+                slot_id: None,
+            };
+            let va = va.insert(&pool).await?;
+            assert_eq!(va, VersionAssignment::get(&pool, va.id).await?);
+
+            let renumbered = ScheduleVersion {
+                number: 2,
+                ..version.clone()
+            };
+            assert!(renumbered.update(&pool).await.is_err());
+            assert!(va.delete(&pool).await.is_err());
+            assert!(version.delete(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
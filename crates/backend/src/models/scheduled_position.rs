@@ -1,72 +1,1332 @@
-use super::resource::Resource;
+use std::fmt;
+
+use super::attribute::{AttributeType, UserAttribute};
+use super::availability::{self, Availability, AvailabilityWindow, UnavailablePeriod};
+use super::labor_rule::{self, LaborRule, RuleViolation};
+use super::position_constraint::{self, PositionConstraint, Violation};
+use super::position_slot::PositionSlot;
+use super::positions::Position;
+use super::qualification::{self, PositionQualification, Qualification, UserQualification};
+use super::resource::{text_enum, Filter, Resource};
+use super::store::{Store, Transactional};
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::time_off::TimeOffRequest;
+use super::user::User;
+use super::waitlist::{self, WaitlistSpot};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sqlx::FromRow;
 
+/// The constraint stopping a user being assigned to overlapping positions.
+const NO_OVERLAP: &str = "scheduled_positions_no_overlap";
+
+/// The constraint stopping a position or slot going past its maximum headcount.
+const HEADCOUNT: &str = "scheduled_positions_headcount";
+
+const NOT_MANAGER: &str = "only managers of the position's team may assign it";
+const NOT_TEAM_MANAGER: &str = "only managers of the team may see its draft schedule";
+
+text_enum! {
+    /// Assignments start out proposed or assigned. Their users confirm or decline them, and
+    /// managers accept proposals, cancel assignments, and mark them completed or a no-show once
+    /// the shift has started. Declined and cancelled assignments are kept for their history but
+    /// no longer hold a place.
+    #[derive(Default)]
+    pub enum AssignmentStatus {
+        Proposed => "proposed",
+        #[default]
+        Assigned => "assigned",
+        Confirmed => "confirmed",
+        Declined => "declined",
+        Cancelled => "cancelled",
+        Completed => "completed",
+        NoShow => "no_show",
+    }
+}
+
+impl AssignmentStatus {
+    /// Whether the assignment still counts towards headcounts and double-booking.
+    pub fn holds_place(&self) -> bool {
+        !matches!(
+            self,
+            AssignmentStatus::Declined | AssignmentStatus::Cancelled
+        )
+    }
+
+    /// Whether the shift is still to be worked.
+    pub fn is_open(&self) -> bool {
+        matches!(
+            self,
+            AssignmentStatus::Proposed | AssignmentStatus::Assigned | AssignmentStatus::Confirmed
+        )
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
 pub struct ScheduledPosition {
     #[primary_key]
-    id: i64,
-    position_id: i64,
-    user_id: i64,
+    pub id: i64,
+    #[references(Position)]
+    pub position_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    /// Set by an admin to let this assignment overlap the user's others.
+    pub allow_overlap: bool,
+    /// The slot filled, required when the position has slots.
+    #[references(PositionSlot)]
+    pub slot_id: Option<i64>,
+    /// Set when the user's time off was approved after they were assigned.
+    pub needs_reassignment: bool,
+    pub status: AssignmentStatus,
+}
+
+/// One step in an assignment's lifecycle: who moved it to `status`, and when.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct AssignmentEvent {
+    #[primary_key]
+    pub id: i64,
+    #[references(ScheduledPosition)]
+    pub assignment_id: i64,
+    #[references(User)]
+    pub actor_id: i64,
+    pub status: AssignmentStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Everything scheduling an assignment reads or writes, so callers needn't list it all, and a
+/// transaction to make the writes in.
+pub trait ScheduleStore:
+    Store<ScheduledPosition>
+    + Store<Team>
+    + Store<User>
+    + Store<Position>
+    + Store<PositionSlot>
+    + Store<PositionConstraint>
+    + Store<AttributeType>
+    + Store<UserAttribute>
+    + Store<Qualification>
+    + Store<UserQualification>
+    + Store<PositionQualification>
+    + Store<AvailabilityWindow>
+    + Store<UnavailablePeriod>
+    + Store<TimeOffRequest>
+    + Store<LaborRule>
+    + Store<AssignmentEvent>
+    + Store<WaitlistSpot>
+    + Store<TeamMember>
+    + Transactional
+{
+}
+
+impl<S> ScheduleStore for S where
+    S: Store<ScheduledPosition>
+        + Store<Team>
+        + Store<User>
+        + Store<Position>
+        + Store<PositionSlot>
+        + Store<PositionConstraint>
+        + Store<AttributeType>
+        + Store<UserAttribute>
+        + Store<Qualification>
+        + Store<UserQualification>
+        + Store<PositionQualification>
+        + Store<AvailabilityWindow>
+        + Store<UnavailablePeriod>
+        + Store<TimeOffRequest>
+        + Store<LaborRule>
+        + Store<AssignmentEvent>
+        + Store<WaitlistSpot>
+        + Store<TeamMember>
+        + Transactional
+{
+}
+
+/// A saved assignment, along with anything the scheduler should know about it.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Scheduled {
+    pub assignment: ScheduledPosition,
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Warning {
+    /// The position falls outside the user's weekly availability.
+    OutsideAvailability,
+    /// The assignment breaks working-time rules the team only flags, rather than blocks.
+    LaborRules,
+}
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    /// The user is already assigned to these overlapping positions.
+    Conflict(Vec<ScheduledPosition>),
+    /// The user has marked themselves unavailable for these periods.
+    Unavailable(Vec<UnavailablePeriod>),
+    /// The user has this approved time off.
+    OnLeave(Vec<TimeOffRequest>),
+    /// The acting user isn't allowed to do this, for the given reason.
+    Forbidden(&'static str),
+    /// The position, or the slot being filled, already has its maximum headcount.
+    Full,
+    /// The slot is missing, or isn't one of the position's.
+    InvalidSlot,
+    /// The assignment breaks these constraints on the position.
+    Constraints(Vec<Violation>),
+    /// The user doesn't hold these qualifications, or they expire before the position ends.
+    Unqualified(Vec<Qualification>),
+    /// The assignment breaks these working-time rules of the position's team.
+    LaborRules(Vec<RuleViolation>),
+    /// The request doesn't make sense, for the given reason.
+    Invalid(&'static str),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Conflict(c) => {
+                write!(
+                    f,
+                    "user is already assigned to {} overlapping positions",
+                    c.len()
+                )
+            }
+            ScheduleError::Unavailable(_) => write!(f, "user is unavailable at that time"),
+            ScheduleError::OnLeave(_) => write!(f, "user has time off at that time"),
+            ScheduleError::Forbidden(reason) => write!(f, "{}", reason),
+            ScheduleError::Full => write!(f, "position is already fully staffed"),
+            ScheduleError::InvalidSlot => {
+                write!(f, "assignment must fill one of the position's slots")
+            }
+            ScheduleError::Constraints(v) => {
+                write!(
+                    f,
+                    "assignment breaks {} of the position's constraints",
+                    v.len()
+                )
+            }
+            ScheduleError::Unqualified(_) => write!(f, "user lacks required qualifications"),
+            ScheduleError::LaborRules(v) => {
+                write!(
+                    f,
+                    "assignment breaks {} of the team's working-time rules",
+                    v.len()
+                )
+            }
+            ScheduleError::Invalid(reason) => write!(f, "{}", reason),
+            ScheduleError::Database(e) if is_serialization_failure(e) => {
+                write!(f, "a concurrent change got there first; try again")
+            }
+            ScheduleError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+impl From<sqlx::Error> for ScheduleError {
+    fn from(e: sqlx::Error) -> Self {
+        ScheduleError::Database(e)
+    }
+}
+
+impl ResponseError for ScheduleError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ScheduleError::Conflict(_) => StatusCode::CONFLICT,
+            ScheduleError::Unavailable(_) => StatusCode::CONFLICT,
+            ScheduleError::OnLeave(_) => StatusCode::CONFLICT,
+            ScheduleError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ScheduleError::Full => StatusCode::CONFLICT,
+            ScheduleError::InvalidSlot => StatusCode::UNPROCESSABLE_ENTITY,
+            ScheduleError::Constraints(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ScheduleError::Unqualified(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ScheduleError::LaborRules(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ScheduleError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ScheduleError::Database(e) if is_serialization_failure(e) => StatusCode::CONFLICT,
+            ScheduleError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = json!({ "error": self.to_string() });
+        match self {
+            ScheduleError::Conflict(c) => body["conflicts"] = json!(c),
+            ScheduleError::Unavailable(u) => body["unavailable"] = json!(u),
+            ScheduleError::OnLeave(t) => body["time_off"] = json!(t),
+            ScheduleError::Constraints(v) => body["violations"] = json!(v),
+            ScheduleError::Unqualified(q) => body["qualifications"] = json!(q),
+            ScheduleError::LaborRules(v) => body["labor_violations"] = json!(v),
+            _ => {}
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+/// Whether Postgres aborted a serializable transaction because a concurrent one conflicted with
+/// it. Nothing was written, so the client can simply send the request again.
+fn is_serialization_failure(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .is_some_and(|code| code == "40001")
+}
+
+impl ScheduledPosition {
+    /// Saves this assignment on behalf of `by`. Fails with `ScheduleError::Conflict` if the user
+    /// is already assigned to an overlapping position, unless `allow_overlap` is set, which only
+    /// admins may do. The assignment must fill a slot if the position has any, and neither the
+    /// slot nor the position may already be full.
+    ///
+    /// Assignments during a period the user has marked unavailable are refused. Assignments
+    /// outside their weekly availability go ahead with a warning. The user must meet the
+    /// position's constraints, hold its required qualifications and keep to the team's
+    /// working-time rules. New assignments are either proposed or assigned, and their history
+    /// starts with who created them.
+    ///
+    /// `by` must manage the position's team, and the user must be one of its members.
+    pub async fn schedule<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Scheduled, ScheduleError> {
+        let position: Position = store.get(self.position_id).await?;
+        TeamMember::require_manager(store, position.team_id, by, NOT_MANAGER).await?;
+        self.place(store, by).await
+    }
+
+    /// Saves this assignment with every check `schedule` makes except who `by` is, for flows
+    /// like claiming an open shift which have already decided the user may take the place.
+    pub(super) async fn place<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Scheduled, ScheduleError> {
+        if !matches!(
+            self.status,
+            AssignmentStatus::Proposed | AssignmentStatus::Assigned
+        ) {
+            return Err(ScheduleError::Invalid(
+                "new assignments must be proposed or assigned",
+            ));
+        }
+
+        let tx = store.begin().await?;
+        let warnings = self.check(&tx, by).await?;
+        let assignment = match tx.insert(self).await {
+            Ok(assignment) => assignment,
+            Err(e) => {
+                // A failed statement spoils a Postgres transaction, so explain it outside.
+                drop(tx);
+                return Err(self.save_error(store, e).await);
+            }
+        };
+        assignment.record(&tx, by).await?;
+        tx.commit().await?;
+        Ok(Scheduled {
+            assignment,
+            warnings,
+        })
+    }
+
+    /// Saves changes to this existing assignment, like moving it to another position, slot or
+    /// person, with the same checks as `schedule`. Only open assignments can be moved, and their
+    /// status only changes through `transition`. A place given up is offered to the position's
+    /// waitlist. `by` must manage the team of the position it's moved from and the one it's
+    /// moved to.
+    #[allow(dead_code)]
+    pub async fn reschedule<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Scheduled, ScheduleError> {
+        let tx = store.begin().await?;
+        let existing: ScheduledPosition = tx.get(self.id).await?;
+        for position_id in [existing.position_id, self.position_id] {
+            let position: Position = tx.get(position_id).await?;
+            TeamMember::require_manager(&tx, position.team_id, by, NOT_MANAGER).await?;
+        }
+        if !existing.status.is_open() {
+            return Err(ScheduleError::Invalid("only open assignments can be moved"));
+        }
+        if self.status != existing.status {
+            return Err(ScheduleError::Invalid(
+                "assignments change status through their transitions",
+            ));
+        }
+        let warnings = self.check(&tx, by).await?;
+        if let Err(e) = tx.update(self).await {
+            drop(tx);
+            return Err(self.save_error(store, e).await);
+        }
+        if (existing.position_id, existing.slot_id) != (self.position_id, self.slot_id) {
+            waitlist::offer_next(&tx, existing.position_id).await?;
+        }
+        tx.commit().await?;
+        Ok(Scheduled {
+            assignment: self.clone(),
+            warnings,
+        })
+    }
+
+    /// Makes every check `place` would, without saving anything.
+    pub(super) async fn check<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Vec<Warning>, ScheduleError> {
+        if self.allow_overlap && !by.admin {
+            return Err(ScheduleError::Forbidden(
+                "only admins may allow overlapping assignments",
+            ));
+        }
+
+        self.check_fit(store).await
+    }
+
+    /// The checks `check` makes that don't depend on who is asking: whether the position has
+    /// room and the user can work it.
+    pub(super) async fn check_fit<S: ScheduleStore>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<Warning>, ScheduleError> {
+        let position: Position = store.get(self.position_id).await?;
+        let member = Store::<TeamMember>::exists(
+            store,
+            &Filter::new()
+                .eq("team_id", position.team_id)
+                .eq("user_id", self.user_id),
+        )
+        .await?;
+        if !member {
+            return Err(ScheduleError::Invalid(
+                "only members of the position's team can be assigned to it",
+            ));
+        }
+
+        self.check_headcount(store).await?;
+
+        let violations = position_constraint::evaluate(store, self).await?;
+        if !violations.is_empty() {
+            return Err(ScheduleError::Constraints(violations));
+        }
+
+        let unqualified = qualification::missing(store, self.user_id, &position).await?;
+        if !unqualified.is_empty() {
+            return Err(ScheduleError::Unqualified(unqualified));
+        }
+
+        let mut warnings = Vec::new();
+        match availability::check(store, self.user_id, &position).await? {
+            Availability::Available => {}
+            Availability::OutsideWindows => warnings.push(Warning::OutsideAvailability),
+            Availability::Unavailable(periods) => return Err(ScheduleError::Unavailable(periods)),
+            Availability::OnLeave(time_off) => return Err(ScheduleError::OnLeave(time_off)),
+        }
+
+        if !self.allow_overlap {
+            let conflicts = self.conflicts(store).await?;
+            if !conflicts.is_empty() {
+                return Err(ScheduleError::Conflict(conflicts));
+            }
+        }
+
+        let (blocking, flagged): (Vec<_>, Vec<_>) = labor_rule::evaluate(store, self)
+            .await?
+            .into_iter()
+            .partition(|v| v.blocking);
+        if !blocking.is_empty() {
+            return Err(ScheduleError::LaborRules(blocking));
+        }
+        if !flagged.is_empty() {
+            warnings.push(Warning::LaborRules);
+        }
+
+        Ok(warnings)
+    }
+
+    async fn save_error<S>(&self, store: &S, e: sqlx::Error) -> ScheduleError
+    where
+        S: Store<Self> + Store<Position>,
+    {
+        if violates(&e, NO_OVERLAP) {
+            // Lost a race with another assignment; report it like any other conflict. Inside a
+            // caller's transaction the failed write has spoilt it, so the conflicts can't be
+            // looked up.
+            match self.conflicts(store).await {
+                Ok(conflicts) => ScheduleError::Conflict(conflicts),
+                Err(_) => ScheduleError::Conflict(Vec::new()),
+            }
+        } else if violates(&e, HEADCOUNT) {
+            // Or with another assignment for the last place.
+            ScheduleError::Full
+        } else {
+            e.into()
+        }
+    }
+
+    /// The user's other assignments which overlap this one, including those an admin allowed to
+    /// overlap, since that permission doesn't extend to this assignment.
+    pub async fn conflicts<S>(&self, store: &S) -> Result<Vec<Self>, sqlx::Error>
+    where
+        S: Store<Self> + Store<Position>,
+    {
+        let position: Position = store.get(self.position_id).await?;
+        let assignments: Vec<Self> = store
+            .find(&Self::active().eq("user_id", self.user_id).ne("id", self.id))
+            .await?;
+
+        let mut conflicts = Vec::new();
+        for sp in assignments {
+            let other: Position = store.get(sp.position_id).await?;
+            if position.overlaps(&other) {
+                conflicts.push(sp);
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    /// Checks the position, and any slot filled, have a place free. Places offered from the
+    /// waitlist are held for whoever was offered them.
+    async fn check_headcount<S>(&self, store: &S) -> Result<(), ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<PositionSlot> + Store<WaitlistSpot>,
+    {
+        let position: Position = store.get(self.position_id).await?;
+        let slots: Vec<PositionSlot> = store
+            .find(&Filter::new().eq("position_id", self.position_id))
+            .await?;
+        let offers: Vec<WaitlistSpot> = waitlist::live_offers(store, self.position_id)
+            .await?
+            .into_iter()
+            .filter(|s| s.user_id != self.user_id)
+            .collect();
+
+        if let Some(slot_id) = self.slot_id {
+            let slot = slots
+                .iter()
+                .find(|s| s.id == slot_id)
+                .ok_or(ScheduleError::InvalidSlot)?;
+            let filled = Store::<Self>::count(
+                store,
+                &Self::active().eq("slot_id", slot_id).ne("id", self.id),
+            )
+            .await?;
+            let on_offer = offers.iter().filter(|s| s.slot_id == Some(slot_id)).count() as i64;
+            if filled + on_offer >= slot.max_headcount {
+                return Err(ScheduleError::Full);
+            }
+        } else if !slots.is_empty() {
+            return Err(ScheduleError::InvalidSlot);
+        }
+
+        let filled = Store::<Self>::count(
+            store,
+            &Self::active()
+                .eq("position_id", self.position_id)
+                .ne("id", self.id),
+        )
+        .await?;
+        if filled + offers.len() as i64 >= position.max_headcount {
+            return Err(ScheduleError::Full);
+        }
+
+        Ok(())
+    }
+
+    /// A filter for assignments still holding their place, leaving out declined and cancelled
+    /// ones.
+    pub fn active() -> Filter {
+        Filter::new()
+            .ne("status", AssignmentStatus::Declined)
+            .ne("status", AssignmentStatus::Cancelled)
+    }
+
+    /// A filter for assignments counting towards hours: assigned, confirmed or completed ones.
+    /// Proposals haven't been agreed to, and no-shows weren't worked.
+    pub fn worked() -> Filter {
+        Self::active()
+            .ne("status", AssignmentStatus::Proposed)
+            .ne("status", AssignmentStatus::NoShow)
+    }
+
+    /// Confirms the shift on behalf of `by`, who must be the one assigned.
+    #[allow(dead_code)]
+    pub async fn confirm<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore,
+    {
+        self.transition(store, by, AssignmentStatus::Confirmed)
+            .await
+    }
+
+    /// Declines the shift on behalf of `by`, who must be the one assigned, freeing their place.
+    #[allow(dead_code)]
+    pub async fn decline<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore,
+    {
+        self.transition(store, by, AssignmentStatus::Declined).await
+    }
+
+    /// Moves the assignment to `to` on behalf of `by`, recording who did it and when. Users
+    /// confirm their assigned shifts, and decline proposed or assigned ones. Managers of the
+    /// position's team accept proposals, cancel open assignments, and mark them completed or a
+    /// no-show once the shift has started. Declined and cancelled places are offered to the
+    /// position's waitlist. The change, its event and any offer are made together or not at all.
+    pub async fn transition<S>(
+        &self,
+        store: &S,
+        by: &User,
+        to: AssignmentStatus,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore,
+    {
+        use AssignmentStatus::*;
+
+        let tx = store.begin().await?;
+        let store = &tx;
+        let assignment: Self = store.get(self.id).await?;
+        let allowed = match to {
+            Proposed => false,
+            Assigned => assignment.status == Proposed,
+            Confirmed => assignment.status == Assigned,
+            Declined => matches!(assignment.status, Proposed | Assigned),
+            Cancelled => assignment.status.is_open(),
+            Completed | NoShow => matches!(assignment.status, Assigned | Confirmed),
+        };
+        if !allowed {
+            return Err(ScheduleError::Invalid(
+                "assignment can't move to that status from its current one",
+            ));
+        }
+
+        let position: Position = store.get(assignment.position_id).await?;
+        if matches!(to, Confirmed | Declined) {
+            if assignment.user_id != by.id {
+                return Err(ScheduleError::Forbidden(
+                    "only the assigned user may confirm or decline a shift",
+                ));
+            }
+        } else {
+            TeamMember::require_manager(
+                store,
+                position.team_id,
+                by,
+                "only managers of the team may change an assignment's status",
+            )
+            .await?;
+        }
+        if matches!(to, Completed | NoShow) && Utc::now() < position.starts_at {
+            return Err(ScheduleError::Invalid(
+                "shifts can't be completed before they start",
+            ));
+        }
+
+        let assignment = assignment.set_status(store, by, to).await?;
+        if !to.holds_place() {
+            waitlist::offer_next(store, assignment.position_id).await?;
+        }
+        tx.commit().await?;
+        Ok(assignment)
+    }
+
+    /// Takes the assignment off the schedule on behalf of `by`, who must manage the position's
+    /// team, offering the freed place to the position's waitlist. The assignment is cancelled
+    /// rather than deleted, whatever its status, so its history and anything pointing at it are
+    /// kept.
+    #[allow(dead_code)]
+    pub async fn remove<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let assignment: Self = store.get(self.id).await?;
+        let position: Position = store.get(assignment.position_id).await?;
+        TeamMember::require_manager(
+            store,
+            position.team_id,
+            by,
+            "only managers of the team may remove an assignment",
+        )
+        .await?;
+        if !assignment.status.holds_place() {
+            return Err(ScheduleError::Invalid(
+                "assignment has already been taken off the schedule",
+            ));
+        }
+
+        let assignment = assignment
+            .set_status(store, by, AssignmentStatus::Cancelled)
+            .await?;
+        waitlist::offer_next(store, assignment.position_id).await?;
+        tx.commit().await?;
+        Ok(assignment)
+    }
+
+    /// Moves the assignment to `status` on behalf of `by` and records it, without checking the
+    /// change is allowed. Callers make this part of a transaction, so the update and its event
+    /// are saved together.
+    pub(super) async fn set_status<S>(
+        &self,
+        store: &S,
+        by: &User,
+        status: AssignmentStatus,
+    ) -> Result<Self, sqlx::Error>
+    where
+        S: Store<Self> + Store<AssignmentEvent>,
+    {
+        let assignment = ScheduledPosition {
+            status,
+            ..self.clone()
+        };
+        store.update(&assignment).await?;
+        assignment.record(store, by).await?;
+        Ok(assignment)
+    }
+
+    /// Records that `by` moved the assignment to its current status.
+    pub(super) async fn record<S: Store<AssignmentEvent>>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<(), sqlx::Error> {
+        store
+            .create(&AssignmentEvent {
+                id: 0,
+                assignment_id: self.id,
+                actor_id: by.id,
+                status: self.status,
+                created_at: Utc::now(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// The assignment's changes of status, oldest first.
+    #[allow(dead_code)]
+    pub async fn history<S: Store<AssignmentEvent>>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<AssignmentEvent>, sqlx::Error> {
+        let mut events: Vec<AssignmentEvent> = store
+            .find(&Filter::new().eq("assignment_id", self.id))
+            .await?;
+        events.sort_by_key(|e| (e.created_at, e.id));
+        Ok(events)
+    }
+
+    /// The team's assignments to positions starting between `from` and `to`, and only those
+    /// with `status` if given. Managers chase unconfirmed shifts with `AssignmentStatus::Assigned`.
+    /// These are the draft, so `by` must manage the team; everyone else reads the schedule through
+    /// `schedule_version::visible`.
+    #[allow(dead_code)]
+    pub async fn for_team<S>(
+        store: &S,
+        by: &User,
+        team_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        status: Option<AssignmentStatus>,
+    ) -> Result<Vec<Self>, ScheduleError>
+    where
+        S: Store<Self> + Store<Position> + Store<TeamMember>,
+    {
+        TeamMember::require_manager(store, team_id, by, NOT_TEAM_MANAGER).await?;
+        let positions: Vec<Position> = store
+            .find(
+                &Filter::new()
+                    .eq("team_id", team_id)
+                    .ge("starts_at", from)
+                    .lt("starts_at", to),
+            )
+            .await?;
+
+        let mut assignments = Vec::new();
+        for position in positions {
+            let mut filter = Filter::new().eq("position_id", position.id);
+            if let Some(status) = status {
+                filter = filter.eq("status", status);
+            }
+            let found: Vec<Self> = store.find(&filter).await?;
+            assignments.extend(found);
+        }
+
+        Ok(assignments)
+    }
+}
+
+fn violates(e: &sqlx::Error, constraint: &str) -> bool {
+    e.as_database_error()
+        .into_iter()
+        .any(|e| e.message().contains(constraint))
 }
 
 #[cfg(test)]
 mod scheduled_position_tests {
+    use crate::models::availability::{AvailabilityWindow, UnavailablePeriod};
+    use crate::models::db_test;
+    use crate::models::position_slot::PositionSlot;
+    use crate::models::positions::Position;
     use crate::models::resource::Resource;
-    use crate::models::scheduled_position::ScheduledPosition;
+    use crate::models::scheduled_position::{
+        AssignmentStatus, ScheduleError, ScheduledPosition, Warning,
+    };
+    use crate::models::store::{MemoryStore, PoolStore, Store};
+    use crate::models::team::Team;
+    use crate::models::team_member::TeamMember;
+    use crate::models::test_support::{people, user};
+    use crate::models::user::User;
+    use actix_web::{body::to_bytes, http::StatusCode, ResponseError};
     use anyhow::Result;
-    use sqlx::PgPool;
+    use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
 
-    #[sqlx::test]
-    async fn test_get_all_scheduled_positions(pool: PgPool) -> Result<()> {
-        let res = ScheduledPosition::get_all(&pool).await?;
-        assert_eq!(0, res.len());
+    fn at(h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 1, 1).and_hms(h, 0, 0)
+    }
 
-        Ok(())
+    fn position(starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> Position {
+        Position {
+            team_id: 1,
+            starts_at,
+            ends_at,
+            ..Default::default()
+        }
     }
 
-    #[sqlx::test(fixtures("users", "teams", "positions", "scheduled_positions"))]
-    async fn test_get_scheduled_positions(pool: PgPool) -> Result<()> {
-        let sp = ScheduledPosition::get(&pool, 1).await?;
-        assert_eq!(sp.position_id, 1);
+    fn assign(position_id: i64, allow_overlap: bool) -> ScheduledPosition {
+        ScheduledPosition {
+            id: 0,
+            position_id,
+            user_id: 1,
+            allow_overlap,
+            slot_id: None,
+            needs_reassignment: false,
+            status: AssignmentStatus::Assigned,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_schedule_conflicts() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 1, &[(1, 1, true)]).await?;
+        store.create(&position(at(9), at(17))).await?;
+        store.create(&position(at(12), at(20))).await?;
+        store.create(&position(at(17), at(22))).await?;
+        store.create(&position(at(20), at(22))).await?;
+
+        let manager = user(1);
+        let mut admin = user(1);
+        admin.admin = true;
+        let first = assign(1, false)
+            .schedule(&store, &manager)
+            .await?
+            .assignment;
+
+        match assign(2, false).schedule(&store, &manager).await {
+            Err(ScheduleError::Conflict(c)) => assert_eq!(vec![first], c),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(matches!(
+            assign(2, true).schedule(&store, &manager).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let second = assign(2, true).schedule(&store, &admin).await?.assignment;
+
+        // The override was for the second shift only, so it still blocks later ones.
+        match assign(3, false).schedule(&store, &manager).await {
+            Err(ScheduleError::Conflict(c)) => assert_eq!(vec![second], c),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        // Back to back shifts don't overlap.
+        assign(4, false).schedule(&store, &manager).await?;
 
         Ok(())
     }
 
-    #[sqlx::test(fixtures("users", "teams", "positions", "scheduled_positions"))]
-    async fn test_delete_scheduled_positions(pool: PgPool) -> Result<()> {
-        let sp = ScheduledPosition::get(&pool, 1).await?;
-        let res = sp.delete(&pool).await?;
-        assert_eq!(1, res.rows_affected());
+    #[actix_web::test]
+    async fn test_schedule_headcount() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(
+            &store,
+            4,
+            &[(1, 1, true), (1, 2, false), (1, 3, false), (1, 4, false)],
+        )
+        .await?;
+        let pair = Position {
+            max_headcount: 2,
+            ..position(at(9), at(17))
+        };
+        store.create(&pair).await?;
+        store.create(&position(at(9), at(17))).await?;
+        let supervisor = PositionSlot {
+            position_id: 2,
+            role: "supervisor".into(),
+            ..Default::default()
+        };
+        let supervisor = store.insert(&supervisor).await?;
+
+        let by = user(1);
+        let worker = |user_id, position_id, slot_id| ScheduledPosition {
+            user_id,
+            position_id,
+            slot_id,
+            ..Default::default()
+        };
+        worker(1, 1, None).schedule(&store, &by).await?;
+        worker(2, 1, None).schedule(&store, &by).await?;
+        assert!(matches!(
+            worker(3, 1, None).schedule(&store, &by).await,
+            Err(ScheduleError::Full)
+        ));
 
-        let sp_deleted = ScheduledPosition::get(&pool, 1).await;
-        assert!(sp_deleted.is_err());
+        assert!(matches!(
+            worker(3, 2, None).schedule(&store, &by).await,
+            Err(ScheduleError::InvalidSlot)
+        ));
+        worker(3, 2, Some(supervisor.id))
+            .schedule(&store, &by)
+            .await?;
+        assert!(matches!(
+            worker(4, 2, Some(supervisor.id))
+                .schedule(&store, &by)
+                .await,
+            Err(ScheduleError::Full)
+        ));
 
         Ok(())
     }
 
-    #[sqlx::test(fixtures("users", "teams", "positions"))]
-    async fn test_create_scheduled_positions(pool: PgPool) -> Result<()> {
-        let sp = ScheduledPosition {
-            id: 0,
-            position_id: 1,
+    #[actix_web::test]
+    async fn test_schedule_availability() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 1, &[(1, 1, true)]).await?;
+        store.create(&position(at(9), at(12))).await?;
+        store.create(&position(at(13), at(17))).await?;
+        store.create(&position(at(18), at(20))).await?;
+
+        // 1 Jan 2022 is a Saturday. They can work 08:00-17:00 but not in the evening.
+        let saturdays = AvailabilityWindow {
+            user_id: 1,
+            weekday: 5,
+            start_time: NaiveTime::from_hms(8, 0, 0),
+            end_time: NaiveTime::from_hms(17, 0, 0),
+            ..Default::default()
+        };
+        store.create(&saturdays).await?;
+        let errand = UnavailablePeriod {
             user_id: 1,
+            starts_at: at(16),
+            ends_at: at(17),
+            ..Default::default()
         };
-        let res = sp.create(&pool).await?;
-        assert_eq!(1, res.rows_affected());
+        store.create(&errand).await?;
+
+        let by = user(1);
+        let scheduled = assign(1, false).schedule(&store, &by).await?;
+        assert!(scheduled.warnings.is_empty());
+
+        match assign(2, false).schedule(&store, &by).await {
+            Err(ScheduleError::Unavailable(u)) => assert_eq!(at(16), u[0].starts_at),
+            other => panic!("expected unavailable, got {:?}", other),
+        }
+
+        let scheduled = assign(3, false).schedule(&store, &by).await?;
+        assert_eq!(vec![Warning::OutsideAvailability], scheduled.warnings);
 
         Ok(())
     }
 
-    #[sqlx::test(fixtures("users", "teams", "positions", "scheduled_positions"))]
-    async fn test_update_scheduled_positions(pool: PgPool) -> Result<()> {
-        let mut sp = ScheduledPosition::get(&pool, 1).await?;
-        sp.user_id = 2;
-        sp.update(&pool).await?;
+    #[actix_web::test]
+    async fn test_conflict_response() -> Result<()> {
+        let err = ScheduleError::Conflict(vec![assign(1, false)]);
+        let res = err.error_response();
+        assert_eq!(StatusCode::CONFLICT, res.status());
 
-        let sp_updated = ScheduledPosition::get(&pool, 1).await?;
-        assert_eq!(2, sp_updated.user_id);
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(1, json["conflicts"][0]["position_id"]);
 
         Ok(())
     }
+
+    /// Users 1 and 2 work on team 1, which user 3 manages, with a past position needing one
+    /// person and another far in the future.
+    async fn lifecycle_setup() -> Result<(MemoryStore, Vec<User>)> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, false), (1, 2, false), (1, 3, true)]).await?;
+        let users = (1..=3).map(user).collect();
+        store.create(&position(at(9), at(17))).await?;
+        store
+            .create(&position(
+                Utc.ymd(2999, 1, 1).and_hms(9, 0, 0),
+                Utc.ymd(2999, 1, 1).and_hms(17, 0, 0),
+            ))
+            .await?;
+        Ok((store, users))
+    }
+
+    #[actix_web::test]
+    async fn test_schedule_permissions() -> Result<()> {
+        let (store, users) = lifecycle_setup().await?;
+        let (worker, manager) = (&users[0], &users[2]);
+        store.create(&user(4)).await?;
+
+        // Only managers assign, and only their team's members.
+        assert!(matches!(
+            assign(2, false).schedule(&store, worker).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assert!(matches!(
+            ScheduledPosition {
+                user_id: 4,
+                ..assign(2, false)
+            }
+            .schedule(&store, manager)
+            .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // Admins needn't be on the team.
+        let mut admin = user(4);
+        admin.admin = true;
+        let mut assignment = assign(2, false).schedule(&store, &admin).await?.assignment;
+
+        // Only managers may hand the shift to someone else, too.
+        assignment.user_id = 2;
+        assert!(matches!(
+            assignment.reschedule(&store, worker).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assignment.reschedule(&store, manager).await?;
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_lifecycle() -> Result<()> {
+        use AssignmentStatus::*;
+        let (store, users) = lifecycle_setup().await?;
+        let (worker, other, manager) = (&users[0], &users[1], &users[2]);
+
+        let proposed = ScheduledPosition {
+            status: Proposed,
+            ..assign(1, false)
+        }
+        .schedule(&store, manager)
+        .await?
+        .assignment;
+        assert!(matches!(
+            proposed.confirm(&store, worker).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(matches!(
+            proposed.transition(&store, worker, Assigned).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let assigned = proposed.transition(&store, manager, Assigned).await?;
+        assert!(matches!(
+            assigned.confirm(&store, other).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        assigned.confirm(&store, worker).await?;
+        let done = assigned.transition(&store, manager, Completed).await?;
+        assert_eq!(Completed, done.status);
+
+        let events: Vec<(AssignmentStatus, i64)> = done
+            .history(&store)
+            .await?
+            .iter()
+            .map(|e| (e.status, e.actor_id))
+            .collect();
+        assert_eq!(
+            vec![(Proposed, 3), (Assigned, 3), (Confirmed, 1), (Completed, 3)],
+            events
+        );
+
+        // Shifts can't be marked done before they start, nor created in a later status.
+        let future = assign(2, false).schedule(&store, manager).await?.assignment;
+        assert!(matches!(
+            future.transition(&store, manager, NoShow).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(matches!(
+            ScheduledPosition {
+                status: Confirmed,
+                ..assign(2, false)
+            }
+            .schedule(&store, manager)
+            .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_decline_frees_place() -> Result<()> {
+        let (store, users) = lifecycle_setup().await?;
+        let (worker, other, manager) = (&users[0], &users[1], &users[2]);
+
+        let declined = assign(1, false)
+            .schedule(&store, manager)
+            .await?
+            .assignment
+            .decline(&store, worker)
+            .await?;
+        assert!(matches!(
+            declined.decline(&store, worker).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(matches!(
+            declined.reschedule(&store, manager).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // The place, and the worker's time, are free again.
+        let replacement = ScheduledPosition {
+            user_id: other.id,
+            ..assign(1, false)
+        }
+        .schedule(&store, manager)
+        .await?
+        .assignment;
+        assert!(declined.conflicts(&store).await?.is_empty());
+
+        let (from, to) = (at(0), at(23));
+        assert!(matches!(
+            ScheduledPosition::for_team(&store, worker, 1, from, to, None).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let unconfirmed = ScheduledPosition::for_team(
+            &store,
+            manager,
+            1,
+            from,
+            to,
+            Some(AssignmentStatus::Assigned),
+        )
+        .await?;
+        assert_eq!(vec![replacement], unconfirmed);
+        assert_eq!(
+            2,
+            ScheduledPosition::for_team(&store, manager, 1, from, to, None)
+                .await?
+                .len()
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_remove() -> Result<()> {
+        let (store, users) = lifecycle_setup().await?;
+        let (worker, manager) = (&users[0], &users[2]);
+
+        let assignment = assign(2, false).schedule(&store, manager).await?.assignment;
+        assert!(matches!(
+            assignment.remove(&store, worker).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let removed = assignment.remove(&store, manager).await?;
+        assert_eq!(AssignmentStatus::Cancelled, removed.status);
+        assert!(matches!(
+            removed.remove(&store, manager).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // Its history is kept, ending with who removed it.
+        let events: Vec<(AssignmentStatus, i64)> = removed
+            .history(&store)
+            .await?
+            .iter()
+            .map(|e| (e.status, e.actor_id))
+            .collect();
+        assert_eq!(
+            vec![
+                (AssignmentStatus::Assigned, 3),
+                (AssignmentStatus::Cancelled, 3)
+            ],
+            events
+        );
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_declined_holds_no_place(pool) -> Result<()> {
+            // User 1 works pos1, which needs one person, until they decline it.
+            let mut sp = ScheduledPosition::get(&pool, 1).await?;
+            sp.status = AssignmentStatus::Declined;
+            sp.update(&pool).await?;
+
+            ScheduledPosition {
+                user_id: 2,
+                ..assign(1, false)
+            }
+            .create(&pool)
+            .await?;
+            let clash = position(at(10), at(11)).insert(&pool).await?;
+            assign(clash.id, false).create(&pool).await?;
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_no_double_booking(pool) -> Result<()> {
+            // User 1 already works pos1, 09:15-10:45, and manages its team.
+            TeamMember {
+                id: 0,
+                team_id: 1,
+                user_id: 1,
+                manager: true,
+            }
+            .create(&pool)
+            .await?;
+            let clash = position(at(10), at(11)).insert(&pool).await?;
+            assert!(assign(clash.id, false).create(&pool).await.is_err());
+            assert!(matches!(
+                assign(clash.id, false)
+                    .schedule(&PoolStore::from(pool.clone()), &user(1))
+                    .await,
+                Err(ScheduleError::Conflict(c)) if c.len() == 1
+            ));
+            assign(clash.id, true).create(&pool).await?;
+
+            // The override doesn't let later assignments overlap the clash, but the assignment
+            // it overlaps can still change status.
+            let late = position(at(10) + Duration::minutes(50), at(12))
+                .insert(&pool)
+                .await?;
+            assert!(assign(late.id, false).create(&pool).await.is_err());
+            let mut sp = ScheduledPosition::get(&pool, 1).await?;
+            sp.status = AssignmentStatus::Confirmed;
+            sp.update(&pool).await?;
+
+            // Moving a position into an existing assignment's time is caught too.
+            let mut later = position(at(12), at(13)).insert(&pool).await?;
+            assign(later.id, false).create(&pool).await?;
+            later.starts_at = at(10);
+            assert!(later.update(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_no_moving_onto_override(pool) -> Result<()> {
+            // User 1 works pos1, 09:15-10:45, and is allowed to overlap it with the clash.
+            let clash = position(at(10), at(11)).insert(&pool).await?;
+            assign(clash.id, true).create(&pool).await?;
+            let mut later = position(at(12), at(13)).insert(&pool).await?;
+            assign(later.id, false).create(&pool).await?;
+
+            // Moving the later position onto the clash alone is still a conflict.
+            later.starts_at = at(10) + Duration::minutes(50);
+            assert!(later.update(&pool).await.is_err());
+            later.starts_at = at(11);
+            later.update(&pool).await?;
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_headcount_enforced(pool) -> Result<()> {
+            // pos1 needs one person and user 1 already works it.
+            let extra = ScheduledPosition {
+                user_id: 2,
+                ..assign(1, true)
+            };
+            assert!(extra.create(&pool).await.is_err());
+
+            let mut pos1: Position = Store::get(&pool, 1).await?;
+            pos1.max_headcount = 2;
+            pos1.update(&pool).await?;
+            extra.create(&pool).await?;
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        async fn test_get_all_scheduled_positions(pool) -> Result<()> {
+            let res = ScheduledPosition::get_all(&pool).await?;
+            assert_eq!(0, res.len());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_get_scheduled_positions(pool) -> Result<()> {
+            let sp = ScheduledPosition::get(&pool, 1).await?;
+            assert_eq!(sp.position_id, 1);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_delete_scheduled_positions(pool) -> Result<()> {
+            let sp = ScheduledPosition::get(&pool, 1).await?;
+            let res = sp.delete(&pool).await?;
+            assert_eq!(1, res.rows_affected());
+
+            let sp_deleted = ScheduledPosition::get(&pool, 1).await;
+            assert!(sp_deleted.is_err());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions")]
+        async fn test_create_scheduled_positions(pool) -> Result<()> {
+            let sp = ScheduledPosition {
+                id: 0,
+                position_id: 1,
+                user_id: 1,
+                allow_overlap: false,
+                slot_id: None,
+                needs_reassignment: false,
+                status: AssignmentStatus::Assigned,
+            };
+            let res = sp.create(&pool).await?;
+            assert_eq!(1, res.rows_affected());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions", "scheduled_positions")]
+        async fn test_update_scheduled_positions(pool) -> Result<()> {
+            let mut sp = ScheduledPosition::get(&pool, 1).await?;
+            sp.user_id = 2;
+            sp.update(&pool).await?;
+
+            let sp_updated = ScheduledPosition::get(&pool, 1).await?;
+            assert_eq!(2, sp_updated.user_id);
+
+            Ok(())
+        }
+    }
 }
@@ -0,0 +1,935 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::{
+    AssignmentStatus, ScheduleError, ScheduleStore, ScheduledPosition,
+};
+use super::store::{Store, Transactional};
+use super::team::Team;
+use super::team_member::TeamMember;
+use super::user::User;
+
+text_enum! {
+    #[derive(Default)]
+    pub enum TradeKind {
+        /// Anyone eligible may take the shift.
+        #[default]
+        GiveAway => "give_away",
+        /// Whoever takes the shift gives one of theirs in return.
+        Swap => "swap",
+    }
+}
+
+text_enum! {
+    /// Trades start out open. Once a teammate takes one it completes, or waits pending a
+    /// manager's approval if the team asks for it. A swap is first proposed to the user who
+    /// offered it, who accepts or rejects the shift given in return. Managers can reverse
+    /// completed trades.
+    #[derive(Default)]
+    pub enum TradeStatus {
+        #[default]
+        Open => "open",
+        Proposed => "proposed",
+        Pending => "pending",
+        Completed => "completed",
+        Declined => "declined",
+        Cancelled => "cancelled",
+        Reversed => "reversed",
+    }
+}
+
+impl TradeStatus {
+    /// Whether the trade is still on offer, or waiting for an answer or approval.
+    fn is_unsettled(&self) -> bool {
+        matches!(
+            self,
+            TradeStatus::Open | TradeStatus::Proposed | TradeStatus::Pending
+        )
+    }
+}
+
+/// A user offering one of their assignments to their teammates.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct ShiftTrade {
+    #[primary_key]
+    pub id: i64,
+    #[references(ScheduledPosition)]
+    pub assignment_id: i64,
+    #[references(User)]
+    pub offered_by: i64,
+    pub kind: TradeKind,
+    #[references(User)]
+    pub taken_by: Option<i64>,
+    /// The assignment given in return for a swap.
+    #[references(ScheduledPosition)]
+    pub counter_assignment_id: Option<i64>,
+    pub status: TradeStatus,
+}
+
+/// One step in a trade's history: who moved it to `status`, and when.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct ShiftTradeEvent {
+    #[primary_key]
+    pub id: i64,
+    #[references(ShiftTrade)]
+    pub trade_id: i64,
+    #[references(User)]
+    pub actor_id: i64,
+    pub status: TradeStatus,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShiftTrade {
+    /// Puts the assignment up for trade on behalf of `by`, who must hold it.
+    #[allow(dead_code)]
+    pub async fn offer<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<ShiftTradeEvent> + Store<ScheduledPosition> + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let assignment: ScheduledPosition = store.get(self.assignment_id).await?;
+        if assignment.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "users may only trade their own shifts",
+            ));
+        }
+        if !assignment.status.is_open() {
+            return Err(ScheduleError::Invalid("only open shifts can be traded"));
+        }
+        let trades: Vec<Self> = store
+            .find(&Filter::new().eq("assignment_id", self.assignment_id))
+            .await?;
+        if trades.iter().any(|t| t.status.is_unsettled()) {
+            return Err(ScheduleError::Invalid("shift is already on offer"));
+        }
+
+        let trade = store
+            .insert(&ShiftTrade {
+                offered_by: by.id,
+                taken_by: None,
+                counter_assignment_id: None,
+                status: TradeStatus::Open,
+                ..self.clone()
+            })
+            .await?;
+        trade.record(store, by).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Open trades on `user_id`'s teams which they didn't offer themselves.
+    #[allow(dead_code)]
+    pub async fn open_for<S>(store: &S, user_id: i64) -> Result<Vec<Self>, sqlx::Error>
+    where
+        S: Store<Self> + Store<TeamMember> + Store<ScheduledPosition> + Store<Position>,
+    {
+        let teams: Vec<i64> =
+            Store::<TeamMember>::find(store, &Filter::new().eq("user_id", user_id))
+                .await?
+                .iter()
+                .map(|m| m.team_id)
+                .collect();
+        let open: Vec<Self> = store
+            .find(
+                &Filter::new()
+                    .eq("status", TradeStatus::Open)
+                    .ne("offered_by", user_id),
+            )
+            .await?;
+
+        let mut trades = Vec::new();
+        for trade in open {
+            if teams.contains(&trade.team_id(store).await?) {
+                trades.push(trade);
+            }
+        }
+
+        Ok(trades)
+    }
+
+    /// Takes the shift on behalf of `by`, giving `counter_assignment_id` in return for a swap.
+    /// `by` must be on the shift's team. A swap then waits for the user who offered the shift to
+    /// `accept` the one given in return. Each shift must pass the checks `schedule` makes for
+    /// its new holder when the trade settles, which for trades needing approval is when a
+    /// manager approves.
+    #[allow(dead_code)]
+    pub async fn take<S>(
+        &self,
+        store: &S,
+        by: &User,
+        counter_assignment_id: Option<i64>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftTradeEvent> + Store<TeamMember>,
+    {
+        // The eligibility checks and the exchange run in one transaction, so the shifts can't
+        // change hands in between and a failure part way leaves both where they were.
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let mut trade: Self = store.get(self.id).await?;
+        if trade.status != TradeStatus::Open {
+            return Err(ScheduleError::Invalid("shift is no longer on offer"));
+        }
+        if trade.offered_by == by.id {
+            return Err(ScheduleError::Invalid("users can't take their own shifts"));
+        }
+        match (trade.kind, counter_assignment_id) {
+            (TradeKind::GiveAway, Some(_)) => {
+                return Err(ScheduleError::Invalid(
+                    "give-aways don't take a shift in return",
+                ))
+            }
+            (TradeKind::Swap, None) => {
+                return Err(ScheduleError::Invalid("swaps need a shift in return"))
+            }
+            _ => {}
+        }
+
+        trade.taken_by = Some(by.id);
+        trade.counter_assignment_id = counter_assignment_id;
+        trade.check_eligible(store).await?;
+
+        let trade = match trade.kind {
+            TradeKind::Swap => trade.set_status(store, by, TradeStatus::Proposed).await?,
+            TradeKind::GiveAway => trade.settle(store, by).await?,
+        };
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Accepts the shift proposed in return for a swap, on behalf of `by`, who must have offered
+    /// the trade. The trade then settles as if the shift had just been taken.
+    #[allow(dead_code)]
+    pub async fn accept<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftTradeEvent>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let trade = self.proposal_for(store, by).await?;
+        trade.check_eligible(store).await?;
+        let trade = trade.settle(store, by).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Turns down the shift proposed in return for a swap, on behalf of `by`, who must have
+    /// offered the trade. The trade opens again for other teammates.
+    #[allow(dead_code)]
+    pub async fn reject<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<ShiftTradeEvent> + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let mut trade = self.proposal_for(store, by).await?;
+        trade.taken_by = None;
+        trade.counter_assignment_id = None;
+        let trade = trade.set_status(store, by, TradeStatus::Open).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Approves a pending trade and exchanges the shifts. `by` must manage the shift's team and
+    /// not be part of the trade.
+    #[allow(dead_code)]
+    pub async fn approve<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftTradeEvent> + Store<TeamMember>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let trade = self.decide(store, by).await?;
+        trade.check_eligible(store).await?;
+        trade
+            .exchange(store, by, trade.taker()?, trade.offered_by)
+            .await?;
+        let trade = trade.set_status(store, by, TradeStatus::Completed).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    #[allow(dead_code)]
+    pub async fn decline<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<ShiftTradeEvent>
+            + Store<ScheduledPosition>
+            + Store<Position>
+            + Store<TeamMember>
+            + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let trade = self.decide(store, by).await?;
+        let trade = trade.set_status(store, by, TradeStatus::Declined).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Withdraws a trade which hasn't settled yet. Only the user who offered it may cancel it.
+    #[allow(dead_code)]
+    pub async fn cancel<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<ShiftTradeEvent> + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let trade: Self = store.get(self.id).await?;
+        if trade.offered_by != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only the user who offered a shift may cancel the trade",
+            ));
+        }
+        if !trade.status.is_unsettled() {
+            return Err(ScheduleError::Invalid("trade has already been settled"));
+        }
+
+        let trade = trade.set_status(store, by, TradeStatus::Cancelled).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Hands the shifts of a completed trade back. `by` must manage the shift's team.
+    #[allow(dead_code)]
+    pub async fn reverse<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftTradeEvent> + Store<TeamMember>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+
+        let trade: Self = store.get(self.id).await?;
+        if trade.status != TradeStatus::Completed {
+            return Err(ScheduleError::Invalid(
+                "only completed trades can be reversed",
+            ));
+        }
+        trade.check_manager(store, by).await?;
+
+        let taker = trade.taker()?;
+        let offered: ScheduledPosition = store.get(trade.assignment_id).await?;
+        let counter_holder = match trade.counter_assignment_id {
+            Some(id) => Store::<ScheduledPosition>::get(store, id).await?.user_id,
+            None => trade.offered_by,
+        };
+        if offered.user_id != taker || counter_holder != trade.offered_by {
+            return Err(ScheduleError::Invalid(
+                "shifts have changed hands since the trade",
+            ));
+        }
+
+        trade.exchange(store, by, trade.offered_by, taker).await?;
+        let trade = trade.set_status(store, by, TradeStatus::Reversed).await?;
+        tx.commit().await?;
+
+        Ok(trade)
+    }
+
+    /// Every step of this trade, oldest first.
+    #[allow(dead_code)]
+    pub async fn history<S: Store<ShiftTradeEvent>>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<ShiftTradeEvent>, sqlx::Error> {
+        store.find(&Filter::new().eq("trade_id", self.id)).await
+    }
+
+    async fn team_id<S>(&self, store: &S) -> Result<i64, sqlx::Error>
+    where
+        S: Store<ScheduledPosition> + Store<Position>,
+    {
+        let assignment: ScheduledPosition = store.get(self.assignment_id).await?;
+        let position: Position = store.get(assignment.position_id).await?;
+        Ok(position.team_id)
+    }
+
+    /// Completes the taken trade, or leaves it pending if the team wants trades approved.
+    async fn settle<S>(self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<Self> + Store<ShiftTradeEvent>,
+    {
+        let team: Team = store.get(self.team_id(store).await?).await?;
+        if team.trades_need_approval {
+            return self.set_status(store, by, TradeStatus::Pending).await;
+        }
+        self.exchange(store, by, self.taker()?, self.offered_by)
+            .await?;
+        self.set_status(store, by, TradeStatus::Completed).await
+    }
+
+    /// The trade, if `by` offered it and it's waiting for them to answer a proposed swap.
+    async fn proposal_for<S: Store<Self>>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        let trade: Self = store.get(self.id).await?;
+        if trade.offered_by != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only the user who offered a shift may answer a swap for it",
+            ));
+        }
+        if trade.status != TradeStatus::Proposed {
+            return Err(ScheduleError::Invalid("no swap has been proposed"));
+        }
+        Ok(trade)
+    }
+
+    fn taker(&self) -> Result<i64, ScheduleError> {
+        self.taken_by
+            .ok_or(ScheduleError::Invalid("nobody has taken the shift"))
+    }
+
+    /// Checks the taker may take the shift: it and any counter-shift must still be open and held
+    /// by the users trading them, and both must be on the same team as the taker.
+    async fn check_eligible<S>(&self, store: &S) -> Result<(), ScheduleError>
+    where
+        S: Store<ScheduledPosition> + Store<Position> + Store<TeamMember>,
+    {
+        let taker = self.taker()?;
+        let offered: ScheduledPosition = store.get(self.assignment_id).await?;
+        if offered.user_id != self.offered_by {
+            return Err(ScheduleError::Invalid(
+                "shift has changed hands since it was offered",
+            ));
+        }
+        if !offered.status.is_open() {
+            return Err(ScheduleError::Invalid("only open shifts can be traded"));
+        }
+        let position: Position = store.get(offered.position_id).await?;
+        let teammate = Store::<TeamMember>::exists(
+            store,
+            &Filter::new()
+                .eq("team_id", position.team_id)
+                .eq("user_id", taker),
+        )
+        .await?;
+        if !teammate {
+            return Err(ScheduleError::Forbidden(
+                "only members of the shift's team may take it",
+            ));
+        }
+
+        if let Some(id) = self.counter_assignment_id {
+            let counter: ScheduledPosition = store.get(id).await?;
+            if counter.user_id != taker {
+                return Err(ScheduleError::Forbidden(
+                    "users may only trade their own shifts",
+                ));
+            }
+            if !counter.status.is_open() {
+                return Err(ScheduleError::Invalid("only open shifts can be traded"));
+            }
+            let counter_position: Position = store.get(counter.position_id).await?;
+            if counter_position.team_id != position.team_id {
+                return Err(ScheduleError::Invalid(
+                    "swapped shifts must be on the same team",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives the offered shift to `to`, and any counter-shift to `counter_to`, on behalf of `by`.
+    /// Each shift then gets every check `ScheduledPosition::schedule` makes for its new holder,
+    /// so callers run this in a transaction and drop it if that fails.
+    ///
+    /// Double-booking is checked before anything is written, as the database would otherwise
+    /// refuse the write first. Each user gives up one shift as they gain the other, so that one
+    /// can't conflict. The offered shift allows overlaps until the counter-shift has moved, so
+    /// the users never briefly hold overlapping shifts mid-swap. An admin's permission to
+    /// overlap was for the old holder, so it doesn't move with the shift. Each shift moved gets
+    /// an event in its history, recording who handed it over and the status it's left in.
+    async fn exchange<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+        to: i64,
+        counter_to: i64,
+    ) -> Result<(), ScheduleError> {
+        let moved = |sp: ScheduledPosition, user_id| ScheduledPosition {
+            user_id,
+            allow_overlap: false,
+            needs_reassignment: false,
+            status: unconfirmed(sp.status),
+            ..sp
+        };
+        let offered = moved(store.get(self.assignment_id).await?, to);
+        let counter = match self.counter_assignment_id {
+            Some(id) => Some(moved(store.get(id).await?, counter_to)),
+            None => None,
+        };
+
+        let given_up = counter.as_ref().map(|c| c.id);
+        let mut conflicts: Vec<ScheduledPosition> = offered
+            .conflicts(store)
+            .await?
+            .into_iter()
+            .filter(|c| Some(c.id) != given_up)
+            .collect();
+        if let Some(counter) = &counter {
+            let theirs = counter.conflicts(store).await?;
+            conflicts.extend(theirs.into_iter().filter(|c| c.id != offered.id));
+        }
+        if !conflicts.is_empty() {
+            return Err(ScheduleError::Conflict(conflicts));
+        }
+
+        match &counter {
+            Some(counter) => {
+                store
+                    .update(&ScheduledPosition {
+                        allow_overlap: true,
+                        ..offered.clone()
+                    })
+                    .await?;
+                store.update(counter).await?;
+                store.update(&offered).await?;
+            }
+            None => {
+                store.update(&offered).await?;
+            }
+        }
+
+        offered.check(store, by).await?;
+        offered.record(store, by).await?;
+        if let Some(counter) = counter {
+            counter.check(store, by).await?;
+            counter.record(store, by).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn decide<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<ScheduledPosition> + Store<Position> + Store<TeamMember>,
+    {
+        let trade: Self = store.get(self.id).await?;
+        if trade.status != TradeStatus::Pending {
+            return Err(ScheduleError::Invalid("trade isn't waiting for approval"));
+        }
+        if trade.offered_by == by.id || trade.taken_by == Some(by.id) {
+            return Err(ScheduleError::Forbidden(
+                "managers can't approve their own trades",
+            ));
+        }
+        trade.check_manager(store, by).await?;
+
+        Ok(trade)
+    }
+
+    async fn check_manager<S>(&self, store: &S, by: &User) -> Result<(), ScheduleError>
+    where
+        S: Store<ScheduledPosition> + Store<Position> + Store<TeamMember>,
+    {
+        let team_id = self.team_id(store).await?;
+        TeamMember::require_manager(
+            store,
+            team_id,
+            by,
+            "only managers of the shift's team may do this",
+        )
+        .await
+    }
+
+    async fn set_status<S>(
+        &self,
+        store: &S,
+        by: &User,
+        status: TradeStatus,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<ShiftTradeEvent>,
+    {
+        let mut trade = self.clone();
+        trade.status = status;
+        store.update(&trade).await?;
+        trade.record(store, by).await?;
+
+        Ok(trade)
+    }
+
+    async fn record<S: Store<ShiftTradeEvent>>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<(), sqlx::Error> {
+        store
+            .create(&ShiftTradeEvent {
+                id: 0,
+                trade_id: self.id,
+                actor_id: by.id,
+                status: self.status,
+                created_at: Utc::now(),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// A shift changing hands needs confirming again by its new holder.
+fn unconfirmed(status: AssignmentStatus) -> AssignmentStatus {
+    match status {
+        AssignmentStatus::Confirmed => AssignmentStatus::Assigned,
+        status => status,
+    }
+}
+
+#[cfg(test)]
+mod shift_trade_tests {
+    use crate::models::availability::UnavailablePeriod;
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{AssignmentStatus, ScheduleError, ScheduledPosition};
+    use crate::models::shift_trade::{ShiftTrade, TradeKind, TradeStatus};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use anyhow::Result;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    /// Users 1 and 2 work on team 1, which user 3 manages. User 4 is on team 2. User 1 works
+    /// shift 1 on the 7th and user 2 works shift 2 on the 8th.
+    async fn setup(trades_need_approval: bool) -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store
+            .create(&Team {
+                trades_need_approval,
+                ..Default::default()
+            })
+            .await?;
+        store.create(&Team::default()).await?;
+        people(
+            &store,
+            4,
+            &[(1, 1, false), (1, 2, false), (1, 3, true), (2, 4, false)],
+        )
+        .await?;
+        for d in [7, 8] {
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: at(d, 9),
+                    ends_at: at(d, 17),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        for (position_id, user_id) in [(1, 1), (2, 2)] {
+            store
+                .create(&ScheduledPosition {
+                    position_id,
+                    user_id,
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        Ok(store)
+    }
+
+    fn offer(assignment_id: i64, kind: TradeKind) -> ShiftTrade {
+        ShiftTrade {
+            assignment_id,
+            kind,
+            ..Default::default()
+        }
+    }
+
+    async fn holder(store: &MemoryStore, assignment_id: i64) -> Result<i64> {
+        let sp: ScheduledPosition = store.get(assignment_id).await?;
+        Ok(sp.user_id)
+    }
+
+    #[actix_web::test]
+    async fn test_give_away() -> Result<()> {
+        let store = setup(false).await?;
+        // An admin let user 1 overlap this shift, which shouldn't pass to whoever takes it.
+        let mut shift: ScheduledPosition = store.get(1).await?;
+        shift.allow_overlap = true;
+        store.update(&shift).await?;
+
+        assert!(matches!(
+            offer(1, TradeKind::GiveAway).offer(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let trade = offer(1, TradeKind::GiveAway)
+            .offer(&store, &user(1))
+            .await?;
+        assert!(offer(1, TradeKind::GiveAway)
+            .offer(&store, &user(1))
+            .await
+            .is_err());
+
+        assert_eq!(vec![trade.clone()], ShiftTrade::open_for(&store, 2).await?);
+        assert!(ShiftTrade::open_for(&store, 4).await?.is_empty());
+        assert!(matches!(
+            trade.take(&store, &user(4), None).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+
+        // User 1 had confirmed the shift, which user 2 must do again.
+        let shift: ScheduledPosition = store.get(1).await?;
+        shift.confirm(&store, &user(1)).await?;
+
+        let trade = trade.take(&store, &user(2), None).await?;
+        assert_eq!(TradeStatus::Completed, trade.status);
+        let shift: ScheduledPosition = store.get(1).await?;
+        assert_eq!(2, shift.user_id);
+        assert_eq!(AssignmentStatus::Assigned, shift.status);
+        assert!(!shift.allow_overlap);
+
+        let trade = trade.reverse(&store, &user(3)).await?;
+        assert_eq!(TradeStatus::Reversed, trade.status);
+        assert_eq!(1, holder(&store, 1).await?);
+        let history: Vec<(i64, TradeStatus)> = trade
+            .history(&store)
+            .await?
+            .iter()
+            .map(|e| (e.actor_id, e.status))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, TradeStatus::Open),
+                (2, TradeStatus::Completed),
+                (3, TradeStatus::Reversed),
+            ],
+            history
+        );
+
+        // The shift's own history shows each change of hands.
+        let moves: Vec<(i64, AssignmentStatus)> = shift
+            .history(&store)
+            .await?
+            .iter()
+            .map(|e| (e.actor_id, e.status))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, AssignmentStatus::Confirmed),
+                (2, AssignmentStatus::Assigned),
+                (3, AssignmentStatus::Assigned),
+            ],
+            moves
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_swap_with_approval() -> Result<()> {
+        let store = setup(true).await?;
+        let trade = offer(1, TradeKind::Swap).offer(&store, &user(1)).await?;
+        assert!(matches!(
+            trade.take(&store, &user(2), None).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        // User 1 agrees to the shift offered in return before a manager looks at it.
+        let trade = trade.take(&store, &user(2), Some(2)).await?;
+        assert_eq!(TradeStatus::Proposed, trade.status);
+        assert!(matches!(
+            trade.accept(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let trade = trade.accept(&store, &user(1)).await?;
+        assert_eq!(TradeStatus::Pending, trade.status);
+        assert_eq!(1, holder(&store, 1).await?);
+
+        assert!(matches!(
+            trade.approve(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let trade = trade.approve(&store, &user(3)).await?;
+        assert_eq!(TradeStatus::Completed, trade.status);
+        assert_eq!((2, 1), (holder(&store, 1).await?, holder(&store, 2).await?));
+        assert!(
+            !Store::<ScheduledPosition>::get(&store, 1)
+                .await?
+                .allow_overlap
+        );
+
+        trade.reverse(&store, &user(3)).await?;
+        assert_eq!((1, 2), (holder(&store, 1).await?, holder(&store, 2).await?));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_take_conflicting_shift() -> Result<()> {
+        let store = setup(false).await?;
+        // User 2 is already working the evening of the 7th.
+        store
+            .create(&Position {
+                team_id: 1,
+                starts_at: at(7, 16),
+                ends_at: at(7, 22),
+                ..Default::default()
+            })
+            .await?;
+        let evening = ScheduledPosition {
+            position_id: 3,
+            user_id: 2,
+            ..Default::default()
+        };
+        let evening = store.insert(&evening).await?;
+
+        let trade = offer(1, TradeKind::GiveAway)
+            .offer(&store, &user(1))
+            .await?;
+        match trade.take(&store, &user(2), None).await {
+            Err(ScheduleError::Conflict(c)) => assert_eq!(vec![evening.clone()], c),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+
+        // Swapping the evening shift for it is fine, as user 2 gives it up.
+        trade.cancel(&store, &user(1)).await?;
+        let trade = offer(1, TradeKind::Swap).offer(&store, &user(1)).await?;
+        let trade = trade
+            .take(&store, &user(2), Some(evening.id))
+            .await?
+            .accept(&store, &user(1))
+            .await?;
+        assert_eq!(TradeStatus::Completed, trade.status);
+        assert_eq!(
+            (2, 1),
+            (holder(&store, 1).await?, holder(&store, evening.id).await?)
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_take_closed_shift() -> Result<()> {
+        let store = setup(false).await?;
+        let trade = offer(1, TradeKind::GiveAway)
+            .offer(&store, &user(1))
+            .await?;
+
+        // The manager cancels the shift while it's on offer, so nobody can take it.
+        let shift: ScheduledPosition = store.get(1).await?;
+        shift
+            .transition(&store, &user(3), AssignmentStatus::Cancelled)
+            .await?;
+        assert!(matches!(
+            trade.take(&store, &user(2), None).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert_eq!(1, holder(&store, 1).await?);
+
+        // Nor can a cancelled shift be given in return for a swap.
+        let shift: ScheduledPosition = store.get(2).await?;
+        shift
+            .transition(&store, &user(3), AssignmentStatus::Cancelled)
+            .await?;
+        store
+            .create(&Position {
+                team_id: 1,
+                starts_at: at(9, 9),
+                ends_at: at(9, 17),
+                ..Default::default()
+            })
+            .await?;
+        let third = store
+            .insert(&ScheduledPosition {
+                position_id: 3,
+                user_id: 1,
+                ..Default::default()
+            })
+            .await?;
+        let trade = offer(third.id, TradeKind::Swap)
+            .offer(&store, &user(1))
+            .await?;
+        assert!(matches!(
+            trade.take(&store, &user(2), Some(2)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_take_unschedulable_shift() -> Result<()> {
+        let store = setup(false).await?;
+        // User 1 can't work the 8th, so can't have shift 2 in return.
+        store
+            .create(&UnavailablePeriod {
+                id: 0,
+                user_id: 1,
+                starts_at: at(8, 0),
+                ends_at: at(9, 0),
+                reason: None,
+            })
+            .await?;
+
+        let trade = offer(1, TradeKind::Swap)
+            .offer(&store, &user(1))
+            .await?
+            .take(&store, &user(2), Some(2))
+            .await?;
+        assert!(matches!(
+            trade.accept(&store, &user(1)).await,
+            Err(ScheduleError::Unavailable(_))
+        ));
+        assert_eq!((1, 2), (holder(&store, 1).await?, holder(&store, 2).await?));
+        let trade: ShiftTrade = store.get(trade.id).await?;
+        assert_eq!(TradeStatus::Proposed, trade.status);
+
+        // Turning it down opens the trade to others again.
+        let trade = trade.reject(&store, &user(1)).await?;
+        assert_eq!(
+            (TradeStatus::Open, None, None),
+            (trade.status, trade.taken_by, trade.counter_assignment_id)
+        );
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("teams", "users", "positions", "scheduled_positions")]
+        async fn test_create_shift_trade(pool) -> Result<()> {
+            let trade = ShiftTrade {
+                offered_by: 1,
+                ..offer(1, TradeKind::GiveAway)
+            };
+            let trade = trade.insert(&pool).await?;
+            assert_eq!(trade, ShiftTrade::get(&pool, trade.id).await?);
+
+            // Only swaps take a shift in return.
+            let counter = ShiftTrade {
+                counter_assignment_id: Some(2),
+                ..trade
+            };
+            assert!(counter.create(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,951 @@
+use std::{
+    any::Any,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    error::Error as StdError,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use futures::{lock::Mutex as AsyncMutex, TryStreamExt};
+use sqlx::{error::DatabaseError, Database, PgPool, Pool, Postgres, Sqlite, SqlitePool};
+
+use super::resource::{Filter, Record, Resource, Value};
+use super::Db;
+
+/// Data access for a single resource type. Business logic written against `Store` runs
+/// unchanged on Postgres or SQLite in production and on a `MemoryStore` in unit tests.
+#[async_trait]
+pub trait Store<R: Record>: Send + Sync {
+    async fn create(&self, record: &R) -> Result<u64, sqlx::Error>;
+
+    /// Like `create`, but returns the stored record including its generated id.
+    async fn insert(&self, record: &R) -> Result<R, sqlx::Error>;
+
+    async fn get(&self, id: i64) -> Result<R, sqlx::Error>;
+
+    async fn find(&self, filter: &Filter) -> Result<Vec<R>, sqlx::Error>;
+
+    async fn update(&self, record: &R) -> Result<u64, sqlx::Error>;
+
+    /// Saves `record` over the row with the same `key` columns, keeping that row's id, or
+    /// inserts it if there isn't one. `key` must be one of the record's unique keys. An upsert
+    /// racing another for the same key updates the row that won, or inside a serializable
+    /// transaction fails as a conflict to retry.
+    async fn upsert(&self, key: &[&'static str], record: &R) -> Result<R, sqlx::Error>;
+
+    async fn delete(&self, record: &R) -> Result<u64, sqlx::Error>;
+
+    async fn count(&self, filter: &Filter) -> Result<i64, sqlx::Error>;
+
+    async fn exists(&self, filter: &Filter) -> Result<bool, sqlx::Error>;
+}
+
+/// A store which can group reads and writes into a transaction, so that either all of the
+/// writes happen or none do. The store for a transaction has the same type as the one it was
+/// opened on, so business logic runs unchanged inside it.
+#[async_trait]
+pub trait Transactional: Sized + Send + Sync {
+    /// Opens a transaction and returns a store which reads and writes through it. Dropping that
+    /// store without calling `commit` rolls the writes back. Beginning on a store which is
+    /// already in a transaction joins it, leaving the commit to whoever opened it.
+    async fn begin(&self) -> Result<Self, sqlx::Error>;
+
+    /// Commits the transaction opened by `begin`. Does nothing for a store which joined an
+    /// outer transaction or isn't in one.
+    async fn commit(self) -> Result<(), sqlx::Error>;
+
+    /// Rolls back the transaction opened by `begin`, like dropping the store but reporting
+    /// failures. A store which joined an outer transaction can't undo only its own writes, so
+    /// this fails for one, and for a store which isn't in a transaction.
+    async fn rollback(self) -> Result<(), sqlx::Error>;
+}
+
+fn cannot_roll_back() -> sqlx::Error {
+    sqlx::Error::Protocol("only the store which began a transaction can roll it back".into())
+}
+
+macro_rules! impl_pool_store {
+    ($pool:ty, $db:ty) => {
+        #[async_trait]
+        impl<R: Resource<$db> + Record> Store<R> for $pool {
+            async fn create(&self, record: &R) -> Result<u64, sqlx::Error> {
+                Ok(record.create(self).await?.rows_affected())
+            }
+
+            async fn insert(&self, record: &R) -> Result<R, sqlx::Error> {
+                record.insert(self).await
+            }
+
+            async fn get(&self, id: i64) -> Result<R, sqlx::Error> {
+                R::stream(self, &Filter::new().eq(R::PRIMARY_KEY, id))
+                    .try_next()
+                    .await?
+                    .ok_or(sqlx::Error::RowNotFound)
+            }
+
+            async fn find(&self, filter: &Filter) -> Result<Vec<R>, sqlx::Error> {
+                R::stream(self, filter).try_collect().await
+            }
+
+            async fn update(&self, record: &R) -> Result<u64, sqlx::Error> {
+                Ok(record.update(self).await?.rows_affected())
+            }
+
+            async fn upsert(&self, key: &[&'static str], record: &R) -> Result<R, sqlx::Error> {
+                record.upsert(self, key).await
+            }
+
+            async fn delete(&self, record: &R) -> Result<u64, sqlx::Error> {
+                Ok(record.delete(self).await?.rows_affected())
+            }
+
+            async fn count(&self, filter: &Filter) -> Result<i64, sqlx::Error> {
+                R::count(self, filter).await
+            }
+
+            async fn exists(&self, filter: &Filter) -> Result<bool, sqlx::Error> {
+                R::exists(self, filter).await
+            }
+        }
+    };
+}
+
+impl_pool_store!(PgPool, Postgres);
+impl_pool_store!(SqlitePool, Sqlite);
+
+/// A pool, or one transaction open on it. Unlike a bare pool this is `Transactional`.
+pub struct PoolStore<DB: Database> {
+    pool: Pool<DB>,
+    /// Taken when the transaction is committed.
+    tx: Option<Arc<AsyncMutex<Option<sqlx::Transaction<'static, DB>>>>>,
+    /// Whether this store opened `tx`, and so is the one to commit it.
+    owner: bool,
+}
+
+impl<DB: Database> From<Pool<DB>> for PoolStore<DB> {
+    fn from(pool: Pool<DB>) -> Self {
+        Self {
+            pool,
+            tx: None,
+            owner: false,
+        }
+    }
+}
+
+impl<DB: Database> PoolStore<DB> {
+    /// The pool itself, for work outside any transaction like streaming exports.
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+}
+
+/// A clone joins the transaction, if any, but never commits it.
+impl<DB: Database> Clone for PoolStore<DB> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            tx: self.tx.clone(),
+            owner: false,
+        }
+    }
+}
+
+impl<DB: Database> fmt::Debug for PoolStore<DB> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolStore")
+            .field("pool", &self.pool)
+            .field("in_transaction", &self.tx.is_some())
+            .finish()
+    }
+}
+
+/// Runs `$body` with `$conn` bound to the open transaction of `$store`, or to its pool outside
+/// a transaction.
+macro_rules! with_conn {
+    ($store:expr, $conn:ident => $body:expr) => {
+        match &$store.tx {
+            None => {
+                let $conn = &$store.pool;
+                $body
+            }
+            Some(tx) => {
+                let mut tx = tx.lock().await;
+                let $conn = tx
+                    .as_mut()
+                    .ok_or_else(|| sqlx::Error::Protocol("transaction already committed".into()))?;
+                // Bound so that temporaries borrowing the transaction are dropped before it.
+                let result = $body;
+                result
+            }
+        }
+    };
+}
+
+macro_rules! impl_pool_store_tx {
+    ($db:ty $(, $setup:literal)?) => {
+        #[async_trait]
+        impl<R: Resource<$db> + Record> Store<R> for PoolStore<$db> {
+            async fn create(&self, record: &R) -> Result<u64, sqlx::Error> {
+                with_conn!(self, conn => Ok(record.create(conn).await?.rows_affected()))
+            }
+
+            async fn insert(&self, record: &R) -> Result<R, sqlx::Error> {
+                with_conn!(self, conn => record.insert(conn).await)
+            }
+
+            async fn get(&self, id: i64) -> Result<R, sqlx::Error> {
+                with_conn!(self, conn => R::stream(conn, &Filter::new().eq(R::PRIMARY_KEY, id))
+                    .try_next()
+                    .await?
+                    .ok_or(sqlx::Error::RowNotFound))
+            }
+
+            async fn find(&self, filter: &Filter) -> Result<Vec<R>, sqlx::Error> {
+                with_conn!(self, conn => R::stream(conn, filter).try_collect().await)
+            }
+
+            async fn update(&self, record: &R) -> Result<u64, sqlx::Error> {
+                with_conn!(self, conn => Ok(record.update(conn).await?.rows_affected()))
+            }
+
+            async fn upsert(&self, key: &[&'static str], record: &R) -> Result<R, sqlx::Error> {
+                with_conn!(self, conn => record.upsert(conn, key).await)
+            }
+
+            async fn delete(&self, record: &R) -> Result<u64, sqlx::Error> {
+                with_conn!(self, conn => Ok(record.delete(conn).await?.rows_affected()))
+            }
+
+            async fn count(&self, filter: &Filter) -> Result<i64, sqlx::Error> {
+                with_conn!(self, conn => R::count(conn, filter).await)
+            }
+
+            async fn exists(&self, filter: &Filter) -> Result<bool, sqlx::Error> {
+                with_conn!(self, conn => R::exists(conn, filter).await)
+            }
+        }
+
+        #[async_trait]
+        impl Transactional for PoolStore<$db> {
+            async fn begin(&self) -> Result<Self, sqlx::Error> {
+                if self.tx.is_some() {
+                    return Ok(self.clone());
+                }
+
+                #[allow(unused_mut)]
+                let mut tx = self.pool.begin().await?;
+                $(sqlx::query($setup).execute(&mut tx).await?;)?
+
+                Ok(Self {
+                    pool: self.pool.clone(),
+                    tx: Some(Arc::new(AsyncMutex::new(Some(tx)))),
+                    owner: true,
+                })
+            }
+
+            async fn commit(self) -> Result<(), sqlx::Error> {
+                let tx = match &self.tx {
+                    Some(tx) if self.owner => tx.lock().await.take(),
+                    _ => None,
+                };
+                match tx {
+                    Some(tx) => tx.commit().await,
+                    None => Ok(()),
+                }
+            }
+
+            async fn rollback(self) -> Result<(), sqlx::Error> {
+                let tx = match &self.tx {
+                    Some(tx) if self.owner => tx.lock().await.take(),
+                    _ => None,
+                };
+                match tx {
+                    Some(tx) => tx.rollback().await,
+                    None => Err(cannot_roll_back()),
+                }
+            }
+        }
+    };
+}
+
+// Checks made inside a transaction have to see the same rows as the writes that depend on them.
+// SQLite transactions already behave that way; Postgres needs asking.
+impl_pool_store_tx!(Postgres, "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE");
+impl_pool_store_tx!(Sqlite);
+
+#[async_trait]
+impl<R> Store<R> for Db
+where
+    R: Resource<Postgres> + Resource<Sqlite> + Record,
+{
+    async fn create(&self, record: &R) -> Result<u64, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.create(record).await,
+            Db::Sqlite(pool) => pool.create(record).await,
+        }
+    }
+
+    async fn insert(&self, record: &R) -> Result<R, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.insert(record).await,
+            Db::Sqlite(pool) => pool.insert(record).await,
+        }
+    }
+
+    async fn get(&self, id: i64) -> Result<R, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.get(id).await,
+            Db::Sqlite(pool) => pool.get(id).await,
+        }
+    }
+
+    async fn find(&self, filter: &Filter) -> Result<Vec<R>, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.find(filter).await,
+            Db::Sqlite(pool) => pool.find(filter).await,
+        }
+    }
+
+    async fn update(&self, record: &R) -> Result<u64, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.update(record).await,
+            Db::Sqlite(pool) => pool.update(record).await,
+        }
+    }
+
+    async fn upsert(&self, key: &[&'static str], record: &R) -> Result<R, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.upsert(key, record).await,
+            Db::Sqlite(pool) => pool.upsert(key, record).await,
+        }
+    }
+
+    async fn delete(&self, record: &R) -> Result<u64, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.delete(record).await,
+            Db::Sqlite(pool) => pool.delete(record).await,
+        }
+    }
+
+    async fn count(&self, filter: &Filter) -> Result<i64, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => Store::<R>::count(pool, filter).await,
+            Db::Sqlite(pool) => Store::<R>::count(pool, filter).await,
+        }
+    }
+
+    async fn exists(&self, filter: &Filter) -> Result<bool, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => Store::<R>::exists(pool, filter).await,
+            Db::Sqlite(pool) => Store::<R>::exists(pool, filter).await,
+        }
+    }
+}
+
+#[async_trait]
+impl Transactional for Db {
+    async fn begin(&self) -> Result<Self, sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => Ok(Db::Postgres(pool.begin().await?)),
+            Db::Sqlite(pool) => Ok(Db::Sqlite(pool.begin().await?)),
+        }
+    }
+
+    async fn commit(self) -> Result<(), sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.commit().await,
+            Db::Sqlite(pool) => pool.commit().await,
+        }
+    }
+
+    async fn rollback(self) -> Result<(), sqlx::Error> {
+        match self {
+            Db::Postgres(pool) => pool.rollback().await,
+            Db::Sqlite(pool) => pool.rollback().await,
+        }
+    }
+}
+
+struct Row {
+    record: Box<dyn Any + Send + Sync>,
+    columns: Vec<(&'static str, Option<Value>)>,
+}
+
+impl Row {
+    fn column(&self, name: &str) -> Option<&Value> {
+        self.columns
+            .iter()
+            .find(|(c, _)| *c == name)
+            .and_then(|(_, v)| v.as_ref())
+    }
+}
+
+struct Table {
+    last_id: i64,
+    rows: BTreeMap<i64, Row>,
+    references: &'static [(&'static str, &'static str)],
+}
+
+/// A row to put back when a transaction is rolled back. `None` removes an inserted row.
+struct Undo {
+    table: &'static str,
+    id: i64,
+    row: Option<Row>,
+}
+
+/// An in-memory `Store` which enforces the same primary key, unique and foreign key
+/// constraints as the Postgres schema. Violations are reported as `sqlx::Error::Database`
+/// with the Postgres SQLSTATE code, so callers can't tell the two apart.
+///
+/// Transactions are rolled back from an undo log and aren't isolated: other stores on the same
+/// tables see their writes before the commit.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Arc<Mutex<HashMap<&'static str, Table>>>,
+    undo: Option<Arc<Mutex<Vec<Undo>>>>,
+    /// Whether this store opened the transaction `undo` belongs to.
+    owner: bool,
+}
+
+impl MemoryStore {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record_undo(&self, table: &'static str, id: i64, row: Option<Row>) {
+        if let Some(undo) = &self.undo {
+            let mut undo = undo.lock().expect("memory store lock poisoned");
+            undo.push(Undo { table, id, row });
+        }
+    }
+}
+
+#[async_trait]
+impl Transactional for MemoryStore {
+    async fn begin(&self) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            tables: self.tables.clone(),
+            undo: Some(self.undo.clone().unwrap_or_default()),
+            owner: self.undo.is_none(),
+        })
+    }
+
+    async fn commit(self) -> Result<(), sqlx::Error> {
+        if let (true, Some(undo)) = (self.owner, &self.undo) {
+            undo.lock().expect("memory store lock poisoned").clear();
+        }
+        Ok(())
+    }
+
+    async fn rollback(self) -> Result<(), sqlx::Error> {
+        if self.owner {
+            // Dropping the store undoes its writes.
+            Ok(())
+        } else {
+            Err(cannot_roll_back())
+        }
+    }
+}
+
+/// Rolls back whatever the transaction opened by this store wrote, unless it was committed.
+impl Drop for MemoryStore {
+    fn drop(&mut self) {
+        let undo = match (&self.undo, self.owner) {
+            (Some(undo), true) => undo,
+            _ => return,
+        };
+        let (mut undo, mut tables) = match (undo.lock(), self.tables.lock()) {
+            (Ok(undo), Ok(tables)) => (undo, tables),
+            _ => return,
+        };
+        for Undo { table, id, row } in undo.drain(..).rev() {
+            let rows = &mut tables
+                .get_mut(table)
+                .expect("undone table should exist")
+                .rows;
+            match row {
+                Some(row) => rows.insert(id, row),
+                None => rows.remove(&id),
+            };
+        }
+    }
+}
+
+fn table<'a, R: Record>(tables: &'a mut HashMap<&'static str, Table>) -> &'a mut Table {
+    tables.entry(R::TABLE_NAME).or_insert_with(|| Table {
+        last_id: 0,
+        rows: BTreeMap::new(),
+        references: R::REFERENCES,
+    })
+}
+
+/// Checks the unique and foreign key constraints for `row` as if it were written with `id`.
+fn check_constraints<R: Record>(
+    tables: &HashMap<&'static str, Table>,
+    id: i64,
+    row: &Row,
+) -> Result<(), sqlx::Error> {
+    for key in R::UNIQUE {
+        // Like the databases, keys with a NULL in them never clash.
+        let values: Option<Vec<&Value>> = key.iter().map(|column| row.column(column)).collect();
+        let values = match values {
+            Some(values) => values,
+            None => continue,
+        };
+        let duplicate = tables.get(R::TABLE_NAME).into_iter().any(|t| {
+            t.rows.iter().any(|(other_id, other)| {
+                *other_id != id
+                    && key
+                        .iter()
+                        .zip(&values)
+                        .all(|(column, value)| other.column(column) == Some(*value))
+            })
+        });
+        if duplicate {
+            return Err(MemoryError::unique_violation(R::TABLE_NAME, key));
+        }
+    }
+
+    for (column, referenced) in R::REFERENCES {
+        let referenced_id = match row.column(column) {
+            Some(Value::Int(v)) => *v,
+            _ => continue,
+        };
+        let found = tables
+            .get(referenced)
+            .into_iter()
+            .any(|t| t.rows.contains_key(&referenced_id));
+        if !found {
+            return Err(MemoryError::foreign_key_violation(
+                R::TABLE_NAME,
+                column,
+                format!(
+                    "insert or update on table \"{}\" violates foreign key constraint \"{}_{}_fkey\"",
+                    R::TABLE_NAME,
+                    R::TABLE_NAME,
+                    column
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl<R: Record> Store<R> for MemoryStore {
+    async fn create(&self, record: &R) -> Result<u64, sqlx::Error> {
+        self.insert(record).await?;
+        Ok(1)
+    }
+
+    async fn insert(&self, record: &R) -> Result<R, sqlx::Error> {
+        let mut tables = self.tables.lock().expect("memory store lock poisoned");
+        let id = table::<R>(&mut tables).last_id + 1;
+
+        let mut record = record.clone();
+        record.set_id(id);
+        let row = Row {
+            columns: record.columns(),
+            record: Box::new(record.clone()),
+        };
+        check_constraints::<R>(&tables, id, &row)?;
+
+        let table = table::<R>(&mut tables);
+        table.last_id = id;
+        table.rows.insert(id, row);
+        drop(tables);
+        self.record_undo(R::TABLE_NAME, id, None);
+
+        Ok(record)
+    }
+
+    async fn get(&self, id: i64) -> Result<R, sqlx::Error> {
+        self.find(&Filter::new().eq(R::PRIMARY_KEY, id))
+            .await?
+            .pop()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn find(&self, filter: &Filter) -> Result<Vec<R>, sqlx::Error> {
+        let mut tables = self.tables.lock().expect("memory store lock poisoned");
+
+        Ok(table::<R>(&mut tables)
+            .rows
+            .values()
+            .filter(|row| filter.matches(&row.columns))
+            .map(|row| {
+                row.record
+                    .downcast_ref::<R>()
+                    .expect("table should only hold rows of one type")
+                    .clone()
+            })
+            .collect())
+    }
+
+    async fn update(&self, record: &R) -> Result<u64, sqlx::Error> {
+        let mut tables = self.tables.lock().expect("memory store lock poisoned");
+        let id = record.id();
+        if !table::<R>(&mut tables).rows.contains_key(&id) {
+            return Ok(0);
+        }
+
+        let row = Row {
+            columns: record.columns(),
+            record: Box::new(record.clone()),
+        };
+        check_constraints::<R>(&tables, id, &row)?;
+        let old = table::<R>(&mut tables).rows.insert(id, row);
+        drop(tables);
+        self.record_undo(R::TABLE_NAME, id, old);
+
+        Ok(1)
+    }
+
+    async fn upsert(&self, key: &[&'static str], record: &R) -> Result<R, sqlx::Error> {
+        // Like the databases, keys with a NULL in them never clash, so those always insert.
+        let columns = record.columns();
+        let mut filter = Some(Filter::new());
+        for column in key {
+            let value = columns
+                .iter()
+                .find(|(c, _)| c == column)
+                .and_then(|(_, v)| v.clone());
+            filter = filter.zip(value).map(|(f, value)| f.eq(column, value));
+        }
+        let existing: Option<R> = match filter {
+            Some(filter) => self.find(&filter).await?.into_iter().next(),
+            None => None,
+        };
+
+        let mut record = record.clone();
+        match existing {
+            Some(existing) => {
+                record.set_id(existing.id());
+                self.update(&record).await?;
+                Ok(record)
+            }
+            None => self.insert(&record).await,
+        }
+    }
+
+    async fn delete(&self, record: &R) -> Result<u64, sqlx::Error> {
+        let mut tables = self.tables.lock().expect("memory store lock poisoned");
+        let id = record.id();
+        if !table::<R>(&mut tables).rows.contains_key(&id) {
+            return Ok(0);
+        }
+
+        for (name, table) in tables.iter() {
+            for (column, referenced) in table.references {
+                if *referenced != R::TABLE_NAME {
+                    continue;
+                }
+                if table
+                    .rows
+                    .values()
+                    .any(|row| row.column(column) == Some(&Value::Int(id)))
+                {
+                    return Err(MemoryError::foreign_key_violation(
+                        name,
+                        column,
+                        format!(
+                            "update or delete on table \"{}\" violates foreign key constraint \"{}_{}_fkey\" on table \"{}\"",
+                            R::TABLE_NAME,
+                            name,
+                            column,
+                            name
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let old = table::<R>(&mut tables).rows.remove(&id);
+        drop(tables);
+        self.record_undo(R::TABLE_NAME, id, old);
+
+        Ok(1)
+    }
+
+    async fn count(&self, filter: &Filter) -> Result<i64, sqlx::Error> {
+        let rows: Vec<R> = self.find(filter).await?;
+        Ok(rows.len() as i64)
+    }
+
+    async fn exists(&self, filter: &Filter) -> Result<bool, sqlx::Error> {
+        Ok(Store::<R>::count(self, filter).await? > 0)
+    }
+}
+
+/// A constraint violation raised by `MemoryStore`, shaped like the equivalent Postgres error.
+#[derive(Debug)]
+pub struct MemoryError {
+    message: String,
+    code: &'static str,
+    constraint: String,
+}
+
+impl MemoryError {
+    fn unique_violation(table: &str, key: &[&str]) -> sqlx::Error {
+        let constraint = format!("{}_{}_key", table, key.join("_"));
+        sqlx::Error::Database(Box::new(MemoryError {
+            message: format!(
+                "duplicate key value violates unique constraint \"{}\"",
+                constraint
+            ),
+            code: "23505",
+            constraint,
+        }))
+    }
+
+    fn foreign_key_violation(table: &str, column: &str, message: String) -> sqlx::Error {
+        sqlx::Error::Database(Box::new(MemoryError {
+            message,
+            code: "23503",
+            constraint: format!("{}_{}_fkey", table, column),
+        }))
+    }
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl StdError for MemoryError {}
+
+impl DatabaseError for MemoryError {
+    fn message(&self) -> &str {
+        &self.message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed(self.code))
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+
+    fn constraint(&self) -> Option<&str> {
+        Some(&self.constraint)
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use crate::models::db_test;
+    use crate::models::holiday::Holiday;
+    use crate::models::resource::Filter;
+    use crate::models::store::{MemoryStore, PoolStore, Store, Transactional};
+    use crate::models::team::Team;
+    use crate::models::user::User;
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    fn user(username: &str) -> User {
+        let mut user = User::default();
+        user.username = username.into();
+        user
+    }
+
+    /// Runs against both backends to check that they agree.
+    async fn check_store(store: &impl Store<User>) -> Result<()> {
+        store.create(&user("user1")).await?;
+        let inserted = store.insert(&user("user2")).await?;
+        assert_eq!(2, inserted.id);
+
+        // SQLite reports its own extended result code for a unique violation.
+        let err = store.create(&user("user1")).await.unwrap_err();
+        let code = err.as_database_error().and_then(|e| e.code());
+        assert!(matches!(code.as_deref(), Some("23505") | Some("2067")));
+
+        let mut user2: User = store.get(2).await?;
+        assert_eq!("user2", user2.username);
+
+        user2.username = "user1".into();
+        assert!(store.update(&user2).await.is_err());
+        user2.firstname = Some("Juan".into());
+        user2.username = "user2".into();
+        assert_eq!(1, store.update(&user2).await?);
+
+        let found = store.find(&Filter::new().eq("firstname", "Juan")).await?;
+        assert_eq!(vec![user2.clone()], found);
+        assert_eq!(2, Store::<User>::count(store, &Filter::new()).await?);
+        let one_of = |ids: &[i64]| Filter::new().one_of("id", ids.iter().copied());
+        assert_eq!(1, Store::<User>::count(store, &one_of(&[2, 3])).await?);
+        assert_eq!(0, Store::<User>::count(store, &one_of(&[])).await?);
+
+        assert_eq!(1, store.delete(&user2).await?);
+        assert_eq!(0, store.delete(&user2).await?);
+        assert!(matches!(
+            Store::<User>::get(store, 2).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+
+        Ok(())
+    }
+
+    /// Runs against both backends to check that rolled back writes disappear, and that joined
+    /// transactions commit with the one they joined and can't be rolled back alone.
+    async fn check_transactions<S: Store<User> + Transactional>(store: &S) -> Result<()> {
+        let tx = store.begin().await?;
+        tx.create(&user("user1")).await?;
+        drop(tx);
+        assert_eq!(0, Store::<User>::count(store, &Filter::new()).await?);
+
+        let tx = store.begin().await?;
+        tx.create(&user("user1")).await?;
+        let joined = tx.begin().await?;
+        joined.create(&user("user2")).await?;
+        joined.commit().await?;
+        assert_eq!(2, Store::<User>::count(&tx, &Filter::new()).await?);
+        tx.commit().await?;
+        assert_eq!(2, Store::<User>::count(store, &Filter::new()).await?);
+
+        // Updates and deletes are undone too.
+        let tx = store.begin().await?;
+        let mut user1: User = Store::<User>::find(&tx, &Filter::new().eq("username", "user1"))
+            .await?
+            .remove(0);
+        user1.firstname = Some("Juan".into());
+        tx.update(&user1).await?;
+        let user2: User = Store::<User>::find(&tx, &Filter::new().eq("username", "user2"))
+            .await?
+            .remove(0);
+        tx.delete(&user2).await?;
+        let joined = tx.begin().await?;
+        assert!(joined.rollback().await.is_err());
+        tx.rollback().await?;
+        let users: Vec<User> = store.find(&Filter::new()).await?;
+        assert_eq!(
+            vec![("user1", None), ("user2", None)],
+            users
+                .iter()
+                .map(|u| (u.username.as_str(), u.firstname.as_deref()))
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_memory_store() -> Result<()> {
+        check_store(&MemoryStore::new()).await
+    }
+
+    #[actix_web::test]
+    async fn test_memory_store_transactions() -> Result<()> {
+        check_transactions(&MemoryStore::new()).await
+    }
+
+    #[actix_web::test]
+    async fn test_memory_store_matches_postgres_errors() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&user("user1")).await?;
+
+        let err = store.create(&user("user1")).await.unwrap_err();
+        let db_err = err.as_database_error().expect("should be a database error");
+        assert_eq!("23505", db_err.code().unwrap());
+        assert_eq!(Some("users_username_key"), db_err.constraint());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_memory_store_composite_keys() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&Team::default()).await?;
+        let day = |team_id, d| Holiday {
+            team_id,
+            date: NaiveDate::from_ymd(2022, 12, d),
+            name: "Holiday".into(),
+            ..Default::default()
+        };
+        store.create(&day(1, 25)).await?;
+        store.create(&day(2, 25)).await?;
+        store.create(&day(1, 26)).await?;
+
+        let err = store.create(&day(1, 25)).await.unwrap_err();
+        let db_err = err.as_database_error().expect("should be a database error");
+        assert_eq!(Some("holidays_team_id_date_key"), db_err.constraint());
+
+        // Upserts update the row with the same key.
+        let renamed = store
+            .upsert(
+                &["team_id", "date"],
+                &Holiday {
+                    name: "Christmas".into(),
+                    ..day(1, 25)
+                },
+            )
+            .await?;
+        assert_eq!(1, renamed.id);
+        assert_eq!(3, Store::<Holiday>::count(&store, &Filter::new()).await?);
+
+        Ok(())
+    }
+
+    db_test! {
+        async fn test_pool_store(pool) -> Result<()> {
+            check_store(&pool).await
+        }
+    }
+
+    db_test! {
+        async fn test_pool_store_transactions(pool) -> Result<()> {
+            check_transactions(&PoolStore::from(pool)).await
+        }
+    }
+
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_pool_store_upsert(pool) -> Result<()> {
+            let store = PoolStore::from(pool);
+            let christmas = Holiday {
+                team_id: 1,
+                date: NaiveDate::from_ymd(2022, 12, 25),
+                name: "Holiday".into(),
+                ..Default::default()
+            };
+            let saved = store.insert(&christmas).await?;
+
+            // Upserting over the row doesn't spoil the transaction, as a failed insert would on
+            // Postgres.
+            let tx = store.begin().await?;
+            let renamed = tx
+                .upsert(
+                    &["team_id", "date"],
+                    &Holiday {
+                        name: "Christmas".into(),
+                        ..christmas.clone()
+                    },
+                )
+                .await?;
+            assert_eq!((saved.id, "Christmas"), (renamed.id, renamed.name.as_str()));
+            let boxing_day = Holiday {
+                date: NaiveDate::from_ymd(2022, 12, 26),
+                ..christmas
+            };
+            tx.upsert(&["team_id", "date"], &boxing_day).await?;
+            tx.commit().await?;
+            assert_eq!(2, Store::<Holiday>::count(&store, &Filter::new()).await?);
+
+            Ok(())
+        }
+    }
+}
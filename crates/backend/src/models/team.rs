@@ -1,86 +1,200 @@
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
 use super::resource::Resource;
+use super::scheduled_position::ScheduleError;
+use super::store::Store;
+use super::team_member::TeamMember;
+use super::user::User;
+use crate::timezone;
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
 pub struct Team {
     #[primary_key]
-    id: i64,
-    name: String,
-    description: Option<String>,
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    /// The IANA time zone the team's positions are scheduled in, like `Europe/London`.
+    pub time_zone: String,
+    /// Whether shift trades between members wait for a manager's approval.
+    pub trades_need_approval: bool,
+}
+
+impl Default for Team {
+    fn default() -> Self {
+        Team {
+            id: 0,
+            name: String::new(),
+            description: None,
+            time_zone: "UTC".into(),
+            trades_need_approval: false,
+        }
+    }
+}
+
+impl Team {
+    pub fn tz(&self) -> Result<Tz, sqlx::Error> {
+        timezone::parse(&self.time_zone)
+    }
+
+    /// Creates the team on behalf of `by`, who must be an admin.
+    #[allow(dead_code)]
+    pub async fn add<S: Store<Self>>(&self, store: &S, by: &User) -> Result<Self, ScheduleError> {
+        by.require_admin("only admins may create teams")?;
+        self.check_time_zone()?;
+        Ok(store.insert(self).await?)
+    }
+
+    /// Saves changes to the team on behalf of `by`, who must manage it.
+    #[allow(dead_code)]
+    pub async fn change<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TeamMember>,
+    {
+        TeamMember::require_manager(
+            store,
+            self.id,
+            by,
+            "only managers of the team may change it",
+        )
+        .await?;
+        self.check_time_zone()?;
+        store.update(self).await?;
+        Ok(self.clone())
+    }
+
+    fn check_time_zone(&self) -> Result<(), ScheduleError> {
+        self.tz().map(|_| ()).map_err(|_| {
+            ScheduleError::Invalid("time zone must be an IANA name like Europe/London")
+        })
+    }
 }
 
 #[cfg(test)]
 mod team_tests {
+    use crate::models::db_test;
     use crate::models::resource::Resource;
+    use crate::models::scheduled_position::ScheduleError;
+    use crate::models::store::{MemoryStore, Store};
     use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
     use anyhow::Result;
-    use sqlx::{query, PgPool};
+    use sqlx::query;
 
-    #[sqlx::test()]
-    async fn test_create_team(pool: PgPool) -> Result<()> {
-        let name = "team1".into();
-        let description = Some("This is a team".into());
+    #[actix_web::test]
+    async fn test_time_zone_checked() -> Result<()> {
+        let store = MemoryStore::new();
+        let mut admin = user(1);
+        admin.admin = true;
         let team = Team {
-            name,
-            description,
+            time_zone: "Europe/London".into(),
             ..Default::default()
         };
-        team.create(&pool).await?;
-        let got_team = Team::get(&pool, 1).await?;
-
-        assert_eq!(team.name, got_team.name);
-        assert_eq!(team.description, got_team.description);
+        assert!(matches!(
+            team.add(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let mut team = team.add(&store, &admin).await?;
+        people(&store, 2, &[(1, 2, true)]).await?;
+
+        team.time_zone = "Europe/Lndon".into();
+        assert!(matches!(
+            team.change(&store, &user(2)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert!(matches!(
+            Team {
+                id: 0,
+                ..team.clone()
+            }
+            .add(&store, &admin)
+            .await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        team.time_zone = "Europe/Paris".into();
+        team.change(&store, &user(2)).await?;
+        let saved: Team = store.get(1).await?;
+        assert_eq!(chrono_tz::Europe::Paris, saved.tz()?);
 
         Ok(())
     }
 
-    #[sqlx::test(fixtures("teams"))]
-    async fn test_get_team(pool: PgPool) -> Result<()> {
-        let team = Team::get(&pool, 1).await?;
+    db_test! {
+        async fn test_create_team(pool) -> Result<()> {
+            let name = "team1".into();
+            let description = Some("This is a team".into());
+            let team = Team {
+                name,
+                description,
+                ..Default::default()
+            };
+            team.create(&pool).await?;
+            let got_team = Team::get(&pool, 1).await?;
+
+            assert_eq!(team.name, got_team.name);
+            assert_eq!(team.description, got_team.description);
+            assert_eq!(chrono_tz::UTC, got_team.tz()?);
+
+            Ok(())
+        }
+    }
 
-        assert_eq!("team1", team.name);
-        assert_eq!(Some("this is a good team".into()), team.description);
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_get_team(pool) -> Result<()> {
+            let team = Team::get(&pool, 1).await?;
 
-        Ok(())
+            assert_eq!("team1", team.name);
+            assert_eq!(Some("this is a good team".into()), team.description);
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("teams"))]
-    async fn test_get_all_teams(pool: PgPool) -> Result<()> {
-        let teams = Team::get_all(&pool).await?;
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_get_all_teams(pool) -> Result<()> {
+            let teams = Team::get_all(&pool).await?;
 
-        assert_eq!(3, teams.len());
+            assert_eq!(3, teams.len());
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("teams"))]
-    async fn test_update_team(pool: PgPool) -> Result<()> {
-        let new_name = "teamTwo";
-        let mut team = Team::get(&pool, 1).await?;
-        team.name = new_name.into();
-        team.update(&pool).await?;
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_update_team(pool) -> Result<()> {
+            let new_name = "teamTwo";
+            let mut team = Team::get(&pool, 1).await?;
+            team.name = new_name.into();
+            team.time_zone = "Australia/Sydney".into();
+            team.update(&pool).await?;
 
-        let updated_team = Team::get(&pool, 1).await?;
+            let updated_team = Team::get(&pool, 1).await?;
 
-        assert_eq!(new_name, updated_team.name);
+            assert_eq!(new_name, updated_team.name);
+            assert_eq!(chrono_tz::Australia::Sydney, updated_team.tz()?);
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("teams"))]
-    async fn test_delete_team(pool: PgPool) -> Result<()> {
-        let team = Team::get(&pool, 2).await?;
-        team.delete(&pool).await?;
+    db_test! {
+        #[fixtures("teams")]
+        async fn test_delete_team(pool) -> Result<()> {
+            let team = Team::get(&pool, 2).await?;
+            team.delete(&pool).await?;
 
-        let res = query("SELECT * FROM users WHERE id = $1")
-            .bind(2)
-            .execute(&pool)
-            .await?;
+            let res = query("SELECT * FROM users WHERE id = $1")
+                .bind(2)
+                .execute(&pool)
+                .await?;
 
-        assert_eq!(res.rows_affected(), 0);
+            assert_eq!(res.rows_affected(), 0);
 
-        Ok(())
+            Ok(())
+        }
     }
 }
@@ -1,57 +1,151 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-use super::resource::Resource;
+use super::resource::{Filter, Resource};
+use super::scheduled_position::ScheduleError;
+use super::store::Store;
+use super::team::Team;
+use super::user::User;
 
 #[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
 pub struct TeamMember {
     #[primary_key]
-    id: i64,
-    team_id: i64,
-    user_id: i64,
-    manager: bool,
+    pub id: i64,
+    #[references(Team)]
+    pub team_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub manager: bool,
+}
+
+impl TeamMember {
+    pub async fn is_manager<S: Store<Self>>(
+        store: &S,
+        team_id: i64,
+        user_id: i64,
+    ) -> Result<bool, sqlx::Error> {
+        store
+            .exists(
+                &Filter::new()
+                    .eq("team_id", team_id)
+                    .eq("user_id", user_id)
+                    .eq("manager", true),
+            )
+            .await
+    }
+
+    /// Fails as `Forbidden` with `message` unless `by` manages the team or is an admin.
+    pub async fn require_manager<S: Store<Self>>(
+        store: &S,
+        team_id: i64,
+        by: &User,
+        message: &'static str,
+    ) -> Result<(), ScheduleError> {
+        if by.admin || Self::is_manager(store, team_id, by.id).await? {
+            Ok(())
+        } else {
+            Err(ScheduleError::Forbidden(message))
+        }
+    }
 }
 
 #[cfg(test)]
 mod team_member_tests {
+    use crate::models::db_test;
     use crate::models::resource::Resource;
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
     use crate::models::team_member::TeamMember;
+    use crate::models::user::User;
     use anyhow::Result;
-    use sqlx::PgPool;
 
-    #[sqlx::test(fixtures("team_members"))]
-    async fn test_create_team_member(pool: PgPool) -> Result<()> {
-        let tm1 = TeamMember {
-            id: 0,
-            team_id: 1,
-            user_id: 1,
-            manager: true,
-        };
-        let res = tm1.create(&pool).await?;
-        assert_eq!(1, res.rows_affected());
+    db_test! {
+        #[fixtures("team_members")]
+        async fn test_create_team_member(pool) -> Result<()> {
+            let tm1 = TeamMember {
+                id: 0,
+                team_id: 1,
+                user_id: 1,
+                manager: true,
+            };
+            let res = tm1.create(&pool).await?;
+            assert_eq!(1, res.rows_affected());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("team_members")]
+        async fn test_create_team_member_error(pool) -> Result<()> {
+            let tm1 = TeamMember {
+                id: 0,
+                team_id: 3, // Team 3 doesn't exist. Foreign key error.
+                user_id: 1,
+                manager: true,
+            };
+            let res = tm1.create(&pool).await;
+            assert!(res.is_err());
+
+            let tm2 = TeamMember {
+                id: 0,
+                team_id: 1,
+                user_id: 100, // User 100 doesn't exist. Foreign key error.
+                manager: false,
+            };
+            let res = tm2.create(&pool).await;
+            assert!(res.is_err());
+
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_is_manager() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&User::default()).await?;
+        store
+            .create(&TeamMember {
+                id: 0,
+                team_id: 1,
+                user_id: 1,
+                manager: true,
+            })
+            .await?;
+
+        assert!(TeamMember::is_manager(&store, 1, 1).await?);
+        assert!(!TeamMember::is_manager(&store, 1, 2).await?);
 
         Ok(())
     }
 
-    #[sqlx::test(fixtures("team_members"))]
-    async fn test_create_team_member_error(pool: PgPool) -> Result<()> {
-        let tm1 = TeamMember {
+    #[actix_web::test]
+    async fn test_memory_store_foreign_keys() -> Result<()> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&User::default()).await?;
+
+        let tm = TeamMember {
             id: 0,
             team_id: 3, // Team 3 doesn't exist. Foreign key error.
             user_id: 1,
-            manager: true,
-        };
-        let res = tm1.create(&pool).await;
-        assert!(res.is_err());
-
-        let tm2 = TeamMember {
-            id: 0,
-            team_id: 1,
-            user_id: 100, // User 100 doesn't exist. Foreign key error.
             manager: false,
         };
-        let res = tm2.create(&pool).await;
-        assert!(res.is_err());
+        let err = store.create(&tm).await.unwrap_err();
+        assert_eq!(
+            Some("team_members_team_id_fkey"),
+            err.as_database_error().unwrap().constraint()
+        );
+
+        store.create(&TeamMember { team_id: 1, ..tm }).await?;
+        let team: Team = store.get(1).await?;
+        // Team 1 still has a member. Foreign key error.
+        assert!(store.delete(&team).await.is_err());
+
+        let tm: TeamMember = store.get(1).await?;
+        store.delete(&tm).await?;
+        assert_eq!(1, store.delete(&team).await?);
 
         Ok(())
     }
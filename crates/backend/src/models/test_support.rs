@@ -0,0 +1,34 @@
+//! Fixtures shared by the models' tests.
+
+use anyhow::Result;
+
+use super::store::{MemoryStore, Store};
+use super::team_member::TeamMember;
+use super::user::User;
+
+/// User `id`, as they'd be signed in. Their admin flag and other fields are defaults.
+pub fn user(id: i64) -> User {
+    let mut user = User::default();
+    user.id = id;
+    user.username = format!("user{}", id);
+    user
+}
+
+/// Saves users 1 to `users`, then `members` as `(team_id, user_id, manager)`. The teams have
+/// to exist already.
+pub async fn people(store: &MemoryStore, users: i64, members: &[(i64, i64, bool)]) -> Result<()> {
+    for id in 1..=users {
+        store.create(&user(id)).await?;
+    }
+    for &(team_id, user_id, manager) in members {
+        store
+            .create(&TeamMember {
+                id: 0,
+                team_id,
+                user_id,
+                manager,
+            })
+            .await?;
+    }
+    Ok(())
+}
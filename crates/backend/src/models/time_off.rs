@@ -0,0 +1,474 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::{
+    AssignmentStatus, ScheduleError, ScheduleStore, ScheduledPosition,
+};
+use super::store::{Store, Transactional};
+use super::team_member::TeamMember;
+use super::user::User;
+use super::waitlist::{self, WaitlistSpot};
+
+text_enum! {
+    #[derive(Default)]
+    pub enum TimeOffKind {
+        #[default]
+        Vacation => "vacation",
+        Sick => "sick",
+        Personal => "personal",
+        Other => "other",
+    }
+}
+
+text_enum! {
+    /// Requests start out pending. Managers approve or deny them, and the requester may cancel
+    /// them until they've been denied.
+    #[derive(Default)]
+    pub enum TimeOffStatus {
+        #[default]
+        Pending => "pending",
+        Approved => "approved",
+        Denied => "denied",
+        Cancelled => "cancelled",
+    }
+}
+
+/// A request for time off from `starts_at` until `ends_at`, which can start or end part way
+/// through a day.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct TimeOffRequest {
+    #[primary_key]
+    pub id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    pub kind: TimeOffKind,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub status: TimeOffStatus,
+}
+
+/// One step in a request's history: who moved it to `status`, and when.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct TimeOffEvent {
+    #[primary_key]
+    pub id: i64,
+    #[references(TimeOffRequest)]
+    pub request_id: i64,
+    #[references(User)]
+    pub actor_id: i64,
+    pub status: TimeOffStatus,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl TimeOffRequest {
+    /// Files this request as pending on behalf of `by`, who must be the requester. The request
+    /// and its first event are saved together.
+    #[allow(dead_code)]
+    pub async fn submit<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TimeOffEvent> + Transactional,
+    {
+        if self.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "users may only request time off for themselves",
+            ));
+        }
+        if self.ends_at <= self.starts_at {
+            return Err(ScheduleError::Invalid("time off must end after it starts"));
+        }
+
+        let tx = store.begin().await?;
+        let request = tx
+            .insert(&TimeOffRequest {
+                status: TimeOffStatus::Pending,
+                ..self.clone()
+            })
+            .await?;
+        request.record(&tx, by, None).await?;
+        tx.commit().await?;
+
+        Ok(request)
+    }
+
+    /// Approves the request. `by` must manage one of the requester's teams, and can't approve
+    /// their own requests. The requester's assignments during the time off are flagged for
+    /// reassignment, except where someone is waiting for the position. Those are cancelled and
+    /// their places offered down the waitlist, so cancelling the time off later won't bring
+    /// them back. The decision and its effects on assignments are saved together.
+    #[allow(dead_code)]
+    pub async fn approve<S>(
+        &self,
+        store: &S,
+        by: &User,
+        note: Option<String>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<TimeOffEvent> + Store<TeamMember>,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let request = self
+            .decide(store, by, TimeOffStatus::Approved, note)
+            .await?;
+        for mut sp in request.assignments(store).await? {
+            if WaitlistSpot::anyone_waiting(store, sp.position_id).await? {
+                sp.set_status(store, by, AssignmentStatus::Cancelled)
+                    .await?;
+                waitlist::offer_next(store, sp.position_id).await?;
+            } else if !sp.needs_reassignment {
+                sp.needs_reassignment = true;
+                store.update(&sp).await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(request)
+    }
+
+    #[allow(dead_code)]
+    pub async fn deny<S>(
+        &self,
+        store: &S,
+        by: &User,
+        note: Option<String>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TimeOffEvent> + Store<TeamMember> + Transactional,
+    {
+        let tx = store.begin().await?;
+        let request = self.decide(&tx, by, TimeOffStatus::Denied, note).await?;
+        tx.commit().await?;
+
+        Ok(request)
+    }
+
+    /// Withdraws a pending or approved request. Only the requester may cancel it. Assignments
+    /// flagged because of it are cleared unless other approved time off still covers them.
+    /// Assignments its approval cancelled stay cancelled, as their places may have been taken.
+    /// The cancellation and its effects on assignments are saved together.
+    #[allow(dead_code)]
+    pub async fn cancel<S>(&self, store: &S, by: &User) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self>
+            + Store<TimeOffEvent>
+            + Store<ScheduledPosition>
+            + Store<Position>
+            + Transactional,
+    {
+        let tx = store.begin().await?;
+        let store = &tx;
+        let current: Self = store.get(self.id).await?;
+        if current.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only the requester may cancel time off",
+            ));
+        }
+        if !matches!(
+            current.status,
+            TimeOffStatus::Pending | TimeOffStatus::Approved
+        ) {
+            return Err(ScheduleError::Invalid("time off has already been decided"));
+        }
+
+        let request = current
+            .set_status(store, by, TimeOffStatus::Cancelled, None)
+            .await?;
+        if current.status == TimeOffStatus::Approved {
+            for mut sp in request.assignments(store).await? {
+                let position: Position = store.get(sp.position_id).await?;
+                if sp.needs_reassignment
+                    && Self::approved_during(store, &position, sp.user_id)
+                        .await?
+                        .is_empty()
+                {
+                    sp.needs_reassignment = false;
+                    store.update(&sp).await?;
+                }
+            }
+        }
+        tx.commit().await?;
+
+        Ok(request)
+    }
+
+    /// Every decision made on this request, oldest first.
+    #[allow(dead_code)]
+    pub async fn history<S: Store<TimeOffEvent>>(
+        &self,
+        store: &S,
+    ) -> Result<Vec<TimeOffEvent>, sqlx::Error> {
+        store.find(&Filter::new().eq("request_id", self.id)).await
+    }
+
+    /// The requester's assignments overlapping this time off.
+    pub async fn assignments<S>(&self, store: &S) -> Result<Vec<ScheduledPosition>, sqlx::Error>
+    where
+        S: Store<ScheduledPosition> + Store<Position>,
+    {
+        let all: Vec<ScheduledPosition> = store
+            .find(&ScheduledPosition::active().eq("user_id", self.user_id))
+            .await?;
+        let mut overlapping = Vec::new();
+        for sp in all {
+            let position: Position = store.get(sp.position_id).await?;
+            if position.starts_at < self.ends_at && self.starts_at < position.ends_at {
+                overlapping.push(sp);
+            }
+        }
+
+        Ok(overlapping)
+    }
+
+    /// Approved time off for `user_id` overlapping `position`.
+    pub async fn approved_during<S: Store<Self>>(
+        store: &S,
+        position: &Position,
+        user_id: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        store
+            .find(
+                &Filter::new()
+                    .eq("user_id", user_id)
+                    .eq("status", TimeOffStatus::Approved)
+                    .lt("starts_at", position.ends_at)
+                    .gt("ends_at", position.starts_at),
+            )
+            .await
+    }
+
+    async fn decide<S>(
+        &self,
+        store: &S,
+        by: &User,
+        status: TimeOffStatus,
+        note: Option<String>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TimeOffEvent> + Store<TeamMember>,
+    {
+        let current: Self = store.get(self.id).await?;
+        if current.status != TimeOffStatus::Pending {
+            return Err(ScheduleError::Invalid("time off has already been decided"));
+        }
+        if current.user_id == by.id || !manages(store, by.id, current.user_id).await? {
+            return Err(ScheduleError::Forbidden(
+                "only managers of the requester's teams may decide time off",
+            ));
+        }
+
+        current.set_status(store, by, status, note).await
+    }
+
+    async fn set_status<S>(
+        &self,
+        store: &S,
+        by: &User,
+        status: TimeOffStatus,
+        note: Option<String>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: Store<Self> + Store<TimeOffEvent>,
+    {
+        let mut request = self.clone();
+        request.status = status;
+        store.update(&request).await?;
+        request.record(store, by, note).await?;
+
+        Ok(request)
+    }
+
+    async fn record<S: Store<TimeOffEvent>>(
+        &self,
+        store: &S,
+        by: &User,
+        note: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        store
+            .create(&TimeOffEvent {
+                id: 0,
+                request_id: self.id,
+                actor_id: by.id,
+                status: self.status,
+                note,
+                created_at: Utc::now(),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Whether `manager_id` manages any team `user_id` belongs to.
+async fn manages<S: Store<TeamMember>>(
+    store: &S,
+    manager_id: i64,
+    user_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let memberships = store.find(&Filter::new().eq("user_id", user_id)).await?;
+    for m in memberships {
+        if TeamMember::is_manager(store, m.team_id, manager_id).await? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod time_off_tests {
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::resource::{Filter, Resource};
+    use crate::models::scheduled_position::{ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use crate::models::time_off::{TimeOffKind, TimeOffRequest, TimeOffStatus};
+    use anyhow::Result;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn at(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 11, d).and_hms(h, 0, 0)
+    }
+
+    /// User 1 works on team 1, which user 2 manages. User 3 manages another team.
+    async fn setup() -> Result<MemoryStore> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        store.create(&Team::default()).await?;
+        people(&store, 3, &[(1, 1, false), (1, 2, true), (2, 3, true)]).await?;
+
+        Ok(store)
+    }
+
+    fn request(starts_at: DateTime<Utc>, ends_at: DateTime<Utc>) -> TimeOffRequest {
+        TimeOffRequest {
+            user_id: 1,
+            kind: TimeOffKind::Vacation,
+            starts_at,
+            ends_at,
+            ..Default::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_approval_workflow() -> Result<()> {
+        let store = setup().await?;
+        assert!(matches!(
+            request(at(7, 0), at(9, 0)).submit(&store, &user(2)).await,
+            Err(ScheduleError::Forbidden(_))
+        ));
+        let req = request(at(7, 0), at(9, 0)).submit(&store, &user(1)).await?;
+        assert_eq!(TimeOffStatus::Pending, req.status);
+
+        for by in [1, 3] {
+            assert!(matches!(
+                req.approve(&store, &user(by), None).await,
+                Err(ScheduleError::Forbidden(_))
+            ));
+        }
+        let req = req.approve(&store, &user(2), Some("Enjoy".into())).await?;
+        assert_eq!(TimeOffStatus::Approved, req.status);
+        assert!(matches!(
+            req.deny(&store, &user(2), None).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        let req = req.cancel(&store, &user(1)).await?;
+        let history: Vec<(i64, TimeOffStatus)> = req
+            .history(&store)
+            .await?
+            .iter()
+            .map(|e| (e.actor_id, e.status))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, TimeOffStatus::Pending),
+                (2, TimeOffStatus::Approved),
+                (1, TimeOffStatus::Cancelled),
+            ],
+            history
+        );
+        assert_eq!(Some("Enjoy".into()), req.history(&store).await?[1].note);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_approved_time_off_flags_assignments() -> Result<()> {
+        let store = setup().await?;
+        let position = |starts_at, ends_at| Position {
+            team_id: 1,
+            starts_at,
+            ends_at,
+            ..Default::default()
+        };
+        store.create(&position(at(7, 9), at(7, 17))).await?;
+        store.create(&position(at(10, 9), at(10, 17))).await?;
+        store.create(&position(at(8, 9), at(8, 17))).await?;
+        for position_id in [1, 2] {
+            let sp = ScheduledPosition {
+                position_id,
+                user_id: 1,
+                ..Default::default()
+            };
+            store.create(&sp).await?;
+        }
+
+        // Time off from lunchtime on the 7th until the end of the 8th.
+        let req = request(at(7, 12), at(9, 0))
+            .submit(&store, &user(1))
+            .await?;
+        let req = req.approve(&store, &user(2), None).await?;
+
+        let flagged: Vec<bool> = Store::<ScheduledPosition>::find(&store, &Filter::new())
+            .await?
+            .iter()
+            .map(|sp| sp.needs_reassignment)
+            .collect();
+        assert_eq!(vec![true, false], flagged);
+
+        // New assignments during the time off are refused.
+        let sp = ScheduledPosition {
+            position_id: 3,
+            user_id: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            sp.schedule(&store, &user(2)).await,
+            Err(ScheduleError::OnLeave(_))
+        ));
+
+        req.cancel(&store, &user(1)).await?;
+        let sp: ScheduledPosition = store.get(1).await?;
+        assert!(!sp.needs_reassignment);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users")]
+        async fn test_create_time_off(pool) -> Result<()> {
+            let req = TimeOffRequest {
+                kind: TimeOffKind::Sick,
+                status: TimeOffStatus::Approved,
+                reason: Some("Flu".into()),
+                ..request(at(7, 0), at(8, 0))
+            };
+            let req = req.insert(&pool).await?;
+            assert_eq!(req, TimeOffRequest::get(&pool, req.id).await?);
+
+            let approved =
+                TimeOffRequest::count(&pool, &Filter::new().eq("status", TimeOffStatus::Approved))
+                    .await?;
+            assert_eq!(1, approved);
+
+            Ok(())
+        }
+    }
+}
@@ -2,15 +2,16 @@ use std::env;
 
 use anyhow::Result;
 use chrono::NaiveDate;
+use futures::TryStreamExt;
 use lazy_static::lazy_static;
 use orion::pwhash::{self, hash_password_verify, Password, PasswordHash};
-use pwgen;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgQueryResult, query, query_as, FromRow, PgPool};
+use sqlx::{Database, FromRow, Pool};
 use validator::Validate;
 
-use super::resource::Resource;
+use super::resource::{Filter, Resource, Value};
+use super::scheduled_position::ScheduleError;
 use super::{_default_false, _default_true};
 
 lazy_static! {
@@ -30,6 +31,7 @@ pub struct User {
     #[primary_key]
     pub id: i64,
     #[validate(regex = "USERNAME")]
+    #[unique]
     pub username: String,
     pub lastname: Option<String>,
     pub firstname: Option<String>,
@@ -45,30 +47,36 @@ pub struct User {
 }
 
 impl User {
-    pub async fn get_by_username(pool: &PgPool, username: &str) -> Result<Self, sqlx::Error> {
-        query_as!(
-            Self,
-            r#"
-                SELECT
-                    id,
-                    username,
-                    lastname,
-                    firstname,
-                    email,
-                    password_hash,
-                    date_of_birth,
-                    admin,
-                    active
-                FROM users
-                WHERE username = $1
-            "#,
-            username
-        )
-        .fetch_one(pool)
-        .await
+    /// Fails as `Forbidden` with `message` unless the user is an admin.
+    pub fn require_admin(&self, message: &'static str) -> Result<(), ScheduleError> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(ScheduleError::Forbidden(message))
+        }
     }
 
-    pub async fn authenticate(pool: &PgPool, creds: Credentials) -> Result<Self, actix_web::Error> {
+    pub async fn get_by_username<DB: Database>(
+        pool: &Pool<DB>,
+        username: &str,
+    ) -> Result<Self, sqlx::Error>
+    where
+        Self: Resource<DB>,
+    {
+        Self::stream(pool, &Filter::new().eq("username", username))
+            .try_next()
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    #[allow(dead_code)]
+    pub async fn authenticate<DB: Database>(
+        pool: &Pool<DB>,
+        creds: Credentials,
+    ) -> Result<Self, actix_web::Error>
+    where
+        Self: Resource<DB>,
+    {
         let user = match Self::get_by_username(pool, &creds.username).await {
             Ok(u) => u,
             Err(_) => {
@@ -85,22 +93,24 @@ impl User {
         }
     }
 
-    pub async fn set_password(
+    pub async fn set_password<DB: Database>(
         &mut self,
-        pool: &PgPool,
+        pool: &Pool<DB>,
         password: &str,
-    ) -> Result<PgQueryResult, sqlx::Error> {
+    ) -> Result<DB::QueryResult, sqlx::Error>
+    where
+        Self: Resource<DB>,
+    {
         let pw = Password::from_slice(password.as_bytes())
             .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
         let hash = pwhash::hash_password(&pw, 3, 1 << 16).unwrap();
         self.password_hash = Some(hash.unprotected_as_encoded().to_string());
 
-        query!(
-            "Update users SET password_hash = $1 WHERE username = $2",
-            self.password_hash,
-            self.username
+        Self::update_where(
+            pool,
+            &[("password_hash", Value::of(&self.password_hash))],
+            &Filter::new().eq("username", self.username.as_str()),
         )
-        .execute(pool)
         .await
     }
 
@@ -119,16 +129,19 @@ impl User {
         }
     }
 
-    pub async fn count_admins(pool: &PgPool) -> Result<i64, sqlx::Error> {
-        let count =
-            sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM users WHERE admin = 't'"#)
-                .fetch_one(pool)
-                .await?;
-        Ok(count)
+    pub async fn count_admins<DB: Database>(pool: &Pool<DB>) -> Result<i64, sqlx::Error>
+    where
+        Self: Resource<DB>,
+    {
+        Self::count(pool, &Filter::new().eq("admin", true)).await
     }
 }
 
-pub async fn initialize_admin(pool: &PgPool) -> Result<(), sqlx::Error> {
+#[allow(dead_code)]
+pub async fn initialize_admin<DB: Database>(pool: &Pool<DB>) -> Result<(), sqlx::Error>
+where
+    User: Resource<DB>,
+{
     if User::count_admins(pool).await? >= 1 {
         println!("Admin user exists.");
         return Ok(());
@@ -175,117 +188,157 @@ fn fake_validate() {
 
 #[cfg(test)]
 mod user_tests {
-    use crate::models::resource::Resource;
+    use crate::models::db_test;
+    use crate::models::resource::{Filter, Resource};
     use crate::models::user::{Credentials, User};
     use anyhow::Result;
-    use sqlx::{query, PgPool};
-
-    #[sqlx::test()]
-    async fn test_create_user(pool: PgPool) -> Result<()> {
-        let username = "user1";
-        let email = "user1@email.com";
-        let user = User {
-            username: username.to_string(),
-            email: Some(email.to_string()),
-            admin: true,
-            ..Default::default()
-        };
-        user.create(&pool).await?;
-        let got_user = User::get_by_username(&pool, &user.username).await?;
-
-        assert_eq!(user.username, got_user.username);
-        assert_eq!(user.email, got_user.email);
-        assert_eq!(user.admin, got_user.admin);
-        assert_eq!(user.password_hash, got_user.password_hash);
+    use sqlx::query;
+
+    db_test! {
+        async fn test_create_user(pool) -> Result<()> {
+            let username = "user1";
+            let email = "user1@email.com";
+            let user = User {
+                username: username.to_string(),
+                email: Some(email.to_string()),
+                admin: true,
+                ..Default::default()
+            };
+            user.create(&pool).await?;
+            let got_user = User::get_by_username(&pool, &user.username).await?;
+
+            assert_eq!(user.username, got_user.username);
+            assert_eq!(user.email, got_user.email);
+            assert_eq!(user.admin, got_user.admin);
+            assert_eq!(user.password_hash, got_user.password_hash);
+
+            Ok(())
+        }
+    }
 
-        Ok(())
+    db_test! {
+        #[fixtures("users")]
+        async fn test_get_user(pool) -> Result<()> {
+            let user = User::get_by_username(&pool, "user1").await?;
+
+            assert_eq!("user1", user.username);
+            assert_eq!("User", user.lastname.unwrap());
+            assert_eq!("Juan", user.firstname.unwrap());
+            assert_eq!("user@email.com", user.email.unwrap());
+            assert_eq!(
+                "46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b",
+                user.password_hash.unwrap()
+            );
+            assert!(!user.admin);
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_get_user(pool: PgPool) -> Result<()> {
-        let user = User::get_by_username(&pool, "user1").await?;
-
-        assert_eq!("user1", user.username);
-        assert_eq!("User", user.lastname.unwrap());
-        assert_eq!("Juan", user.firstname.unwrap());
-        assert_eq!("user@email.com", user.email.unwrap());
-        assert_eq!(
-            "46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b",
-            user.password_hash.unwrap()
-        );
-        assert!(!user.admin);
-
-        Ok(())
+    db_test! {
+        #[fixtures("users")]
+        async fn test_update_user(pool) -> Result<()> {
+            let new_firstname = "John";
+            let mut user = User::get_by_username(&pool, "user1").await?;
+            user.firstname = Some(new_firstname.to_string());
+            user.update(&pool).await?;
+
+            let updated_user = User::get_by_username(&pool, "user1").await?;
+
+            assert_eq!(new_firstname, updated_user.firstname.unwrap());
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_update_user(pool: PgPool) -> Result<()> {
-        let new_firstname = "John";
-        let mut user = User::get_by_username(&pool, "user1").await?;
-        user.firstname = Some(new_firstname.to_string());
-        user.update(&pool).await?;
+    db_test! {
+        #[fixtures("users")]
+        async fn test_delete_user(pool) -> Result<()> {
+            let user = User::get_by_username(&pool, "user1").await?;
+            user.delete(&pool).await?;
 
-        let updated_user = User::get_by_username(&pool, "user1").await?;
+            let res = query("SELECT * FROM users WHERE username = $1")
+                .bind(user.username)
+                .execute(&pool)
+                .await?;
 
-        assert_eq!(new_firstname, updated_user.firstname.unwrap());
+            assert_eq!(res.rows_affected(), 0);
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_delete_user(pool: PgPool) -> Result<()> {
-        let user = User::get_by_username(&pool, "user1").await?;
-        user.delete(&pool).await?;
+    db_test! {
+        #[fixtures("users")]
+        async fn test_set_password(pool) -> Result<()> {
+            let mut user = User::get_by_username(&pool, "userNoPass").await?;
+            let password = "itsagoodpass2";
+            user.set_password(&pool, password).await?;
 
-        let res = query("SELECT * FROM users WHERE username = $1")
-            .bind(user.username)
-            .execute(&pool)
-            .await?;
+            user = User::get_by_username(&pool, "userNoPass").await?;
 
-        assert_eq!(res.rows_affected(), 0);
+            assert!(user.password_hash.is_some());
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_set_password(pool: PgPool) -> Result<()> {
-        let mut user = User::get_by_username(&pool, "userNoPass").await?;
-        let password = "itsagoodpass2";
-        user.set_password(&pool, password).await?;
+    db_test! {
+        #[fixtures("users")]
+        async fn test_validate_password(pool) -> Result<()> {
+            let mut user = User::get_by_username(&pool, "userNoPass").await?;
+            let password = "itsagoodpass2";
+            user.set_password(&pool, password).await?;
 
-        user = User::get_by_username(&pool, "userNoPass").await?;
+            user = User::get_by_username(&pool, "userNoPass").await?;
 
-        assert!(user.password_hash.is_some());
+            assert!(user.validate_password(password));
+            assert!(!user.validate_password("itsabadpass3"));
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_validate_password(pool: PgPool) -> Result<()> {
-        let mut user = User::get_by_username(&pool, "userNoPass").await?;
-        let password = "itsagoodpass2";
-        user.set_password(&pool, password).await?;
+    db_test! {
+        #[fixtures("users")]
+        async fn test_count_admins(pool) -> Result<()> {
+            assert_eq!(0, User::count_admins(&pool).await?);
 
-        user = User::get_by_username(&pool, "userNoPass").await?;
+            let mut user = User::get_by_username(&pool, "user1").await?;
+            user.admin = true;
+            user.update(&pool).await?;
 
-        assert!(user.validate_password(password));
-        assert!(!user.validate_password("itsabadpass3"));
+            assert_eq!(1, User::count_admins(&pool).await?);
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_authenticate(pool: PgPool) -> Result<()> {
-        let username = "userCanLogin".to_string();
-        let password = "abc123".to_string();
-        let creds = Credentials {
-            username: username.clone(),
-            password,
-        };
-        let auth_user = User::authenticate(&pool, creds).await.unwrap();
+    db_test! {
+        #[fixtures("users")]
+        async fn test_exists_user(pool) -> Result<()> {
+            assert!(User::exists(&pool, &Filter::new().eq("username", "user1")).await?);
+            assert!(!User::exists(&pool, &Filter::new().eq("username", "nobody")).await?);
+            assert!(User::exists(&pool, &Filter::new().null("date_of_birth")).await?);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[fixtures("users")]
+        async fn test_authenticate(pool) -> Result<()> {
+            let username = "userCanLogin".to_string();
+            let password = "abc123".to_string();
+            let creds = Credentials {
+                username: username.clone(),
+                password,
+            };
+            let auth_user = User::authenticate(&pool, creds).await.unwrap();
 
-        assert_eq!(User::get_by_username(&pool, &username).await?, auth_user);
+            assert_eq!(User::get_by_username(&pool, &username).await?, auth_user);
 
-        Ok(())
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,650 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use super::position_slot::PositionSlot;
+use super::positions::Position;
+use super::resource::{text_enum, Filter, Resource};
+use super::scheduled_position::{ScheduleError, ScheduleStore, ScheduledPosition};
+use super::store::Store;
+use super::team_member::TeamMember;
+use super::user::User;
+
+/// How long someone has to accept a place offered to them, unless the shift starts sooner.
+const OFFER_HOURS: i64 = 24;
+
+text_enum! {
+    /// Spots wait in line until a place frees up, when the first eligible one is offered it.
+    /// The user accepts or declines the offer, and if they do neither by its deadline it expires
+    /// and the place moves down the list.
+    #[derive(Default)]
+    pub enum WaitlistStatus {
+        #[default]
+        Waiting => "waiting",
+        Offered => "offered",
+        Accepted => "accepted",
+        Declined => "declined",
+        Expired => "expired",
+        Withdrawn => "withdrawn",
+    }
+}
+
+/// A user's place in line for a full position, or for one of its slots.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Resource)]
+pub struct WaitlistSpot {
+    #[primary_key]
+    pub id: i64,
+    #[references(Position)]
+    pub position_id: i64,
+    #[references(User)]
+    pub user_id: i64,
+    /// The slot wanted, required when the position has slots.
+    #[references(PositionSlot)]
+    pub slot_id: Option<i64>,
+    pub status: WaitlistStatus,
+    /// When an offer lapses.
+    pub offer_expires_at: Option<DateTime<Utc>>,
+    /// The assignment made once an offer is accepted.
+    #[references(ScheduledPosition)]
+    pub assignment_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WaitlistSpot {
+    /// Joins the line for the position, or `slot_id` within it, on behalf of `by`, who must be
+    /// on the position's team. The position must be full and not yet started.
+    #[allow(dead_code)]
+    pub async fn join<S>(
+        store: &S,
+        by: &User,
+        position_id: i64,
+        slot_id: Option<i64>,
+    ) -> Result<Self, ScheduleError>
+    where
+        S: ScheduleStore + Store<TeamMember>,
+    {
+        let position: Position = store.get(position_id).await?;
+        if position.starts_at <= Utc::now() {
+            return Err(ScheduleError::Invalid("position has already started"));
+        }
+        let teammate = Store::<TeamMember>::exists(
+            store,
+            &Filter::new()
+                .eq("team_id", position.team_id)
+                .eq("user_id", by.id),
+        )
+        .await?;
+        if !teammate {
+            return Err(ScheduleError::Forbidden(
+                "only members of the position's team may join its waitlist",
+            ));
+        }
+        let slots: Vec<PositionSlot> = store
+            .find(&Filter::new().eq("position_id", position_id))
+            .await?;
+        let valid_slot = match slot_id {
+            Some(id) => slots.iter().any(|s| s.id == id),
+            None => slots.is_empty(),
+        };
+        if !valid_slot {
+            return Err(ScheduleError::InvalidSlot);
+        }
+        let offers = live_offers(store, position_id).await?;
+        if has_room(store, &position, slot_id, &offers).await? {
+            return Err(ScheduleError::Invalid(
+                "position has room, so can be assigned straight away",
+            ));
+        }
+        let assigned = Store::<ScheduledPosition>::exists(
+            store,
+            &ScheduledPosition::active()
+                .eq("position_id", position_id)
+                .eq("user_id", by.id),
+        )
+        .await?;
+        if assigned {
+            return Err(ScheduleError::Invalid("user already works this position"));
+        }
+        let in_line = Self::waitlist(store, position_id)
+            .await?
+            .iter()
+            .any(|s| s.user_id == by.id);
+        if in_line {
+            return Err(ScheduleError::Invalid(
+                "user is already on this position's waitlist",
+            ));
+        }
+
+        Ok(store
+            .insert(&WaitlistSpot {
+                id: 0,
+                position_id,
+                user_id: by.id,
+                slot_id,
+                status: WaitlistStatus::Waiting,
+                offer_expires_at: None,
+                assignment_id: None,
+                created_at: Utc::now(),
+            })
+            .await?)
+    }
+
+    /// The people waiting for the position, or holding an offer of it, in the order they
+    /// joined.
+    pub async fn waitlist<S: Store<Self>>(
+        store: &S,
+        position_id: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let mut spots: Vec<Self> = store
+            .find(
+                &Filter::new()
+                    .eq("position_id", position_id)
+                    .ne("status", WaitlistStatus::Accepted)
+                    .ne("status", WaitlistStatus::Declined)
+                    .ne("status", WaitlistStatus::Expired)
+                    .ne("status", WaitlistStatus::Withdrawn),
+            )
+            .await?;
+        spots.sort_by_key(|s| (s.created_at, s.id));
+        Ok(spots)
+    }
+
+    /// Accepts an offered place on behalf of `by`, who must hold the spot, assigning them with
+    /// the usual scheduling checks. Offers past their deadline have expired, and the place goes
+    /// to the next in line. The spot is read in the same transaction as it's answered, so an
+    /// offer expiring meanwhile can't be accepted.
+    #[allow(dead_code)]
+    pub async fn accept<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        let tx = store.begin().await?;
+        let mut spot = self.offer_for(&tx, by).await?;
+        if spot.offer_expires_at.is_some_and(|at| at <= Utc::now()) {
+            offer_next(&tx, spot.position_id).await?;
+            tx.commit().await?;
+            return Err(ScheduleError::Invalid("offer has expired"));
+        }
+
+        let assignment = ScheduledPosition {
+            position_id: spot.position_id,
+            user_id: spot.user_id,
+            slot_id: spot.slot_id,
+            ..Default::default()
+        }
+        .place(&tx, by)
+        .await?
+        .assignment;
+
+        spot.status = WaitlistStatus::Accepted;
+        spot.assignment_id = Some(assignment.id);
+        tx.update(&spot).await?;
+        tx.commit().await?;
+        Ok(spot)
+    }
+
+    /// Turns down an offered place on behalf of `by`, who must hold the spot, and offers it to
+    /// the next in line.
+    #[allow(dead_code)]
+    pub async fn decline<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        let tx = store.begin().await?;
+        let mut spot = self.offer_for(&tx, by).await?;
+        spot.status = WaitlistStatus::Declined;
+        tx.update(&spot).await?;
+        offer_next(&tx, spot.position_id).await?;
+        tx.commit().await?;
+        Ok(spot)
+    }
+
+    /// Leaves the line on behalf of `by`, who must hold the spot. Any place they were offered
+    /// goes to the next in line.
+    #[allow(dead_code)]
+    pub async fn withdraw<S: ScheduleStore>(
+        &self,
+        store: &S,
+        by: &User,
+    ) -> Result<Self, ScheduleError> {
+        let mut spot: Self = store.get(self.id).await?;
+        if spot.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only its holder may withdraw a waitlist spot",
+            ));
+        }
+        if !matches!(
+            spot.status,
+            WaitlistStatus::Waiting | WaitlistStatus::Offered
+        ) {
+            return Err(ScheduleError::Invalid("spot is no longer on the waitlist"));
+        }
+
+        let offered = spot.status == WaitlistStatus::Offered;
+        spot.status = WaitlistStatus::Withdrawn;
+        let tx = store.begin().await?;
+        tx.update(&spot).await?;
+        if offered {
+            offer_next(&tx, spot.position_id).await?;
+        }
+        tx.commit().await?;
+        Ok(spot)
+    }
+
+    /// Whether anyone is still waiting for the position.
+    pub async fn anyone_waiting<S: Store<Self>>(
+        store: &S,
+        position_id: i64,
+    ) -> Result<bool, sqlx::Error> {
+        store
+            .exists(
+                &Filter::new()
+                    .eq("position_id", position_id)
+                    .eq("status", WaitlistStatus::Waiting),
+            )
+            .await
+    }
+
+    /// Lapses every offer past its deadline and offers the places down the line, returning the
+    /// new offers. Run periodically so places don't sit on expired offers.
+    pub async fn expire_offers<S: ScheduleStore>(store: &S) -> Result<Vec<Self>, ScheduleError> {
+        let expired: Vec<Self> = store
+            .find(
+                &Filter::new()
+                    .eq("status", WaitlistStatus::Offered)
+                    .le("offer_expires_at", Utc::now()),
+            )
+            .await?;
+        let mut positions: Vec<i64> = expired.iter().map(|s| s.position_id).collect();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let mut offers = Vec::new();
+        for position_id in positions {
+            let tx = store.begin().await?;
+            offers.extend(offer_next(&tx, position_id).await?);
+            tx.commit().await?;
+        }
+        Ok(offers)
+    }
+
+    async fn offer_for<S: Store<Self>>(&self, store: &S, by: &User) -> Result<Self, ScheduleError> {
+        let spot: Self = store.get(self.id).await?;
+        if spot.user_id != by.id {
+            return Err(ScheduleError::Forbidden(
+                "only its holder may answer a waitlist offer",
+            ));
+        }
+        if spot.status != WaitlistStatus::Offered {
+            return Err(ScheduleError::Invalid("spot hasn't been offered a place"));
+        }
+        Ok(spot)
+    }
+}
+
+/// Expires lapsed offers every `period`, for as long as the server runs, logging any failure
+/// and trying again next time.
+pub async fn expire_offers_every<S: ScheduleStore>(store: S, period: std::time::Duration) {
+    let mut interval = actix_web::rt::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = WaitlistSpot::expire_offers(&store).await {
+            log::error!("expiring waitlist offers failed: {}", e);
+        }
+    }
+}
+
+/// Offers the position's free places to the first eligible people waiting, once any lapsed
+/// offers have expired. Outstanding offers hold their places until they're answered or expire.
+/// Whoever can't currently be assigned, say because of a clash or time off, keeps their spot
+/// for next time. Returns the new offers.
+pub async fn offer_next<S: ScheduleStore>(
+    store: &S,
+    position_id: i64,
+) -> Result<Vec<WaitlistSpot>, ScheduleError> {
+    let now = Utc::now();
+    let position: Position = store.get(position_id).await?;
+    let mut offered = Vec::new();
+    let mut waiting = Vec::new();
+    for mut spot in WaitlistSpot::waitlist(store, position_id).await? {
+        match spot.status {
+            WaitlistStatus::Offered if spot.offer_expires_at.is_some_and(|at| at <= now) => {
+                spot.status = WaitlistStatus::Expired;
+                store.update(&spot).await?;
+            }
+            WaitlistStatus::Offered => offered.push(spot),
+            _ => waiting.push(spot),
+        }
+    }
+    if position.starts_at <= now {
+        return Ok(Vec::new());
+    }
+
+    let mut offers = Vec::new();
+    for mut spot in waiting {
+        if !has_room(store, &position, spot.slot_id, &offered).await? {
+            continue;
+        }
+        let assignment = ScheduledPosition {
+            position_id,
+            user_id: spot.user_id,
+            slot_id: spot.slot_id,
+            ..Default::default()
+        };
+        match assignment.check_fit(store).await {
+            Ok(_) => {}
+            Err(ScheduleError::Database(e)) => return Err(e.into()),
+            Err(_) => continue,
+        }
+
+        spot.status = WaitlistStatus::Offered;
+        spot.offer_expires_at = Some((now + Duration::hours(OFFER_HOURS)).min(position.starts_at));
+        store.update(&spot).await?;
+        offered.push(spot.clone());
+        offers.push(spot);
+    }
+    Ok(offers)
+}
+
+/// Offers of the position's places which are still waiting for an answer and haven't lapsed.
+/// Each holds its place until it's answered or expires.
+pub(super) async fn live_offers<S: Store<WaitlistSpot>>(
+    store: &S,
+    position_id: i64,
+) -> Result<Vec<WaitlistSpot>, sqlx::Error> {
+    store
+        .find(
+            &Filter::new()
+                .eq("position_id", position_id)
+                .eq("status", WaitlistStatus::Offered)
+                .gt("offer_expires_at", Utc::now()),
+        )
+        .await
+}
+
+/// Whether the position, and `slot_id` within it, have a place free once `offered` places are
+/// taken.
+pub(super) async fn has_room<S>(
+    store: &S,
+    position: &Position,
+    slot_id: Option<i64>,
+    offered: &[WaitlistSpot],
+) -> Result<bool, sqlx::Error>
+where
+    S: Store<ScheduledPosition> + Store<PositionSlot>,
+{
+    let staffing = position.staffing(store).await?;
+    if staffing.assigned + offered.len() as i64 >= position.max_headcount {
+        return Ok(false);
+    }
+    let slot_id = match slot_id {
+        Some(id) => id,
+        None => return Ok(true),
+    };
+    let slot: PositionSlot = store.get(slot_id).await?;
+    let assigned = staffing
+        .slots
+        .iter()
+        .find(|s| s.slot_id == slot_id)
+        .map_or(0, |s| s.assigned);
+    let on_offer = offered
+        .iter()
+        .filter(|s| s.slot_id == Some(slot_id))
+        .count() as i64;
+    Ok(assigned + on_offer < slot.max_headcount)
+}
+
+#[cfg(test)]
+mod waitlist_tests {
+    use crate::models::db_test;
+    use crate::models::positions::Position;
+    use crate::models::resource::Resource;
+    use crate::models::scheduled_position::{AssignmentStatus, ScheduleError, ScheduledPosition};
+    use crate::models::store::{MemoryStore, Store};
+    use crate::models::team::Team;
+    use crate::models::test_support::{people, user};
+    use crate::models::time_off::TimeOffRequest;
+    use crate::models::waitlist::{WaitlistSpot, WaitlistStatus};
+    use anyhow::Result;
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    fn in_days(days: i64) -> DateTime<Utc> {
+        Utc::now() + Duration::days(days)
+    }
+
+    /// Users 1, 2 and 4 work on team 1, which user 3 manages. Position 1 is next week, needs
+    /// one person and has user 1, and position 2 is at the same time.
+    async fn setup() -> Result<(MemoryStore, ScheduledPosition)> {
+        let store = MemoryStore::new();
+        store.create(&Team::default()).await?;
+        people(
+            &store,
+            4,
+            &[(1, 1, false), (1, 2, false), (1, 3, true), (1, 4, false)],
+        )
+        .await?;
+        for _ in 0..2 {
+            store
+                .create(&Position {
+                    team_id: 1,
+                    starts_at: in_days(7),
+                    ends_at: in_days(7) + Duration::hours(8),
+                    ..Default::default()
+                })
+                .await?;
+        }
+        let assignment = ScheduledPosition {
+            position_id: 1,
+            user_id: 1,
+            ..Default::default()
+        }
+        .schedule(&store, &user(3))
+        .await?
+        .assignment;
+
+        Ok((store, assignment))
+    }
+
+    async fn status(store: &MemoryStore, spot: &WaitlistSpot) -> Result<WaitlistStatus> {
+        Ok(Store::<WaitlistSpot>::get(store, spot.id).await?.status)
+    }
+
+    #[actix_web::test]
+    async fn test_offers_move_down_the_list() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+        let fourth = WaitlistSpot::join(&store, &user(4), 1, None).await?;
+        for (by, position_id) in [(2, 1), (1, 1), (2, 2)] {
+            assert!(matches!(
+                WaitlistSpot::join(&store, &user(by), position_id, None).await,
+                Err(ScheduleError::Invalid(_))
+            ));
+        }
+
+        // User 1 declining offers their place to user 2, who has a day to take it.
+        assignment.decline(&store, &user(1)).await?;
+        let waitlist = WaitlistSpot::waitlist(&store, 1).await?;
+        assert_eq!(
+            vec![second.id, fourth.id],
+            vec![waitlist[0].id, waitlist[1].id]
+        );
+        assert_eq!(WaitlistStatus::Offered, waitlist[0].status);
+        assert!(waitlist[0]
+            .offer_expires_at
+            .is_some_and(|at| at <= Utc::now() + Duration::hours(24)));
+        assert_eq!(WaitlistStatus::Waiting, waitlist[1].status);
+        assert!(matches!(
+            fourth.accept(&store, &user(4)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        second.decline(&store, &user(2)).await?;
+        let accepted = fourth.accept(&store, &user(4)).await?;
+        assert_eq!(WaitlistStatus::Accepted, accepted.status);
+        let sp: ScheduledPosition = store.get(accepted.assignment_id.unwrap()).await?;
+        assert_eq!((1, 4), (sp.position_id, sp.user_id));
+        assert!(WaitlistSpot::waitlist(&store, 1).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_offer_holds_place() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+        assignment.decline(&store, &user(1)).await?;
+        assert_eq!(WaitlistStatus::Offered, status(&store, &second).await?);
+
+        // The freed place is user 2's while their offer stands, so nobody else can have it.
+        let other = ScheduledPosition {
+            position_id: 1,
+            user_id: 4,
+            ..Default::default()
+        };
+        assert!(matches!(
+            other.schedule(&store, &user(3)).await,
+            Err(ScheduleError::Full)
+        ));
+        second.accept(&store, &user(2)).await?;
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_ineligible_keep_their_spot() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let clash = ScheduledPosition {
+            position_id: 2,
+            user_id: 2,
+            ..Default::default()
+        }
+        .schedule(&store, &user(3))
+        .await?
+        .assignment;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+        let fourth = WaitlistSpot::join(&store, &user(4), 1, None).await?;
+
+        // User 2 is busy, so user 4 is offered the place when user 1 is removed.
+        assignment
+            .transition(&store, &user(3), AssignmentStatus::Cancelled)
+            .await?;
+        assert_eq!(WaitlistStatus::Waiting, status(&store, &second).await?);
+        assert_eq!(WaitlistStatus::Offered, status(&store, &fourth).await?);
+
+        // Once free, user 2 gets it when user 4's offer lapses.
+        clash
+            .transition(&store, &user(3), AssignmentStatus::Cancelled)
+            .await?;
+        let mut lapsed: WaitlistSpot = store.get(fourth.id).await?;
+        lapsed.offer_expires_at = Some(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0));
+        store.update(&lapsed).await?;
+        let offers = WaitlistSpot::expire_offers(&store).await?;
+        assert_eq!(
+            vec![second.id],
+            offers.iter().map(|s| s.id).collect::<Vec<_>>()
+        );
+        assert_eq!(WaitlistStatus::Expired, status(&store, &fourth).await?);
+        assert!(matches!(
+            lapsed.accept(&store, &user(4)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_accepting_lapsed_offer() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+        let fourth = WaitlistSpot::join(&store, &user(4), 1, None).await?;
+        assignment
+            .transition(&store, &user(3), AssignmentStatus::Cancelled)
+            .await?;
+
+        // Answering an offer which lapsed before it was expired passes the place on.
+        let mut lapsed: WaitlistSpot = store.get(second.id).await?;
+        lapsed.offer_expires_at = Some(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0));
+        store.update(&lapsed).await?;
+        assert!(matches!(
+            lapsed.accept(&store, &user(2)).await,
+            Err(ScheduleError::Invalid(_))
+        ));
+        assert_eq!(WaitlistStatus::Expired, status(&store, &second).await?);
+        assert_eq!(WaitlistStatus::Offered, status(&store, &fourth).await?);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_time_off_gives_up_place() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+
+        let request = TimeOffRequest {
+            user_id: 1,
+            starts_at: in_days(6),
+            ends_at: in_days(8),
+            ..Default::default()
+        }
+        .submit(&store, &user(1))
+        .await?
+        .approve(&store, &user(3), None)
+        .await?;
+
+        let cancelled: ScheduledPosition = store.get(assignment.id).await?;
+        assert_eq!(AssignmentStatus::Cancelled, cancelled.status);
+        assert_eq!(WaitlistStatus::Offered, status(&store, &second).await?);
+
+        // Taking the time off back doesn't restore the place, which user 2 may now take.
+        request.cancel(&store, &user(1)).await?;
+        let still: ScheduledPosition = store.get(assignment.id).await?;
+        assert_eq!(AssignmentStatus::Cancelled, still.status);
+
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn test_removal_offers_place() -> Result<()> {
+        let (store, assignment) = setup().await?;
+        let second = WaitlistSpot::join(&store, &user(2), 1, None).await?;
+
+        assignment.remove(&store, &user(3)).await?;
+        assert_eq!(WaitlistStatus::Offered, status(&store, &second).await?);
+        let accepted = second.accept(&store, &user(2)).await?;
+
+        // Removing the place they accepted keeps the spot pointing at it.
+        let removed = Store::<ScheduledPosition>::get(&store, accepted.assignment_id.unwrap())
+            .await?
+            .remove(&store, &user(3))
+            .await?;
+        assert_eq!(AssignmentStatus::Cancelled, removed.status);
+        assert_eq!(WaitlistStatus::Accepted, status(&store, &second).await?);
+
+        Ok(())
+    }
+
+    db_test! {
+        #[fixtures("users", "teams", "positions")]
+        async fn test_create_waitlist_spot(pool) -> Result<()> {
+            let spot = WaitlistSpot {
+                position_id: 1,
+                user_id: 1,
+                created_at: Utc.ymd(2022, 1, 1).and_hms(0, 0, 0),
+                ..Default::default()
+            };
+            let spot = spot.insert(&pool).await?;
+            assert_eq!(spot, WaitlistSpot::get(&pool, spot.id).await?);
+
+            // Offers need a deadline.
+            let offered = WaitlistSpot {
+                status: WaitlistStatus::Offered,
+                ..spot
+            };
+            assert!(offered.update(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+}
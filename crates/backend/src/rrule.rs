@@ -0,0 +1,548 @@
+//! A subset of RFC 5545 recurrence rules, expanded to dates.
+//!
+//! Supported parts are `FREQ` (`DAILY`, `WEEKLY`, `MONTHLY`, `YEARLY`), `INTERVAL`, `COUNT`,
+//! `UNTIL`, `BYDAY` (with ordinals such as `-1FR` for `MONTHLY` and `YEARLY`), `BYMONTHDAY`
+//! (except for `WEEKLY`), `BYMONTH` and `WKST=MO`. Anything else is rejected rather than
+//! silently ignored.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid RRULE: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDate>,
+    /// Weekdays, each with an optional ordinal within the month (`1MO`, `-1FR`).
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+}
+
+impl FromStr for RRule {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+        };
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| ParseError(format!("expected KEY=VALUE, got '{}'", part)))?;
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    freq = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        f => return Err(ParseError(format!("unsupported FREQ '{}'", f))),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = parse_number(key, value)?;
+                    if rule.interval == 0 {
+                        return Err(ParseError("INTERVAL must be at least 1".into()));
+                    }
+                }
+                "COUNT" => rule.count = Some(parse_number(key, value)?),
+                "UNTIL" => rule.until = Some(parse_date(value)?),
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(parse_by_day)
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = value
+                        .split(',')
+                        .map(|v| parse_number(key, v))
+                        .collect::<Result<_, _>>()?;
+                    if rule
+                        .by_month_day
+                        .iter()
+                        .any(|d: &i32| *d == 0 || d.abs() > 31)
+                    {
+                        return Err(ParseError("BYMONTHDAY must be 1..31 or -31..-1".into()));
+                    }
+                }
+                "BYMONTH" => {
+                    rule.by_month = value
+                        .split(',')
+                        .map(|v| parse_number(key, v))
+                        .collect::<Result<_, _>>()?;
+                    if rule.by_month.iter().any(|m| !(1..=12).contains(m)) {
+                        return Err(ParseError("BYMONTH must be 1..12".into()));
+                    }
+                }
+                "WKST" if value.eq_ignore_ascii_case("MO") => {}
+                k => return Err(ParseError(format!("unsupported part '{}'", k))),
+            }
+        }
+
+        rule.freq = freq.ok_or_else(|| ParseError("FREQ is required".into()))?;
+        if rule.count.is_some() && rule.until.is_some() {
+            return Err(ParseError("COUNT and UNTIL can't both be set".into()));
+        }
+        if rule.by_day.iter().any(|(n, _)| n.is_some())
+            && matches!(rule.freq, Frequency::Daily | Frequency::Weekly)
+        {
+            return Err(ParseError(
+                "BYDAY ordinals are only valid for MONTHLY and YEARLY".into(),
+            ));
+        }
+        if !rule.by_month_day.is_empty() && rule.freq == Frequency::Weekly {
+            return Err(ParseError("BYMONTHDAY isn't valid for WEEKLY".into()));
+        }
+        // Only a year, counted in whole, has more than five of any weekday.
+        let by_year = rule.freq == Frequency::Yearly
+            && rule.by_month.is_empty()
+            && rule.by_month_day.is_empty();
+        if rule
+            .by_day
+            .iter()
+            .any(|(n, _)| n.is_some_and(|n| n.abs() > 5))
+            && !by_year
+        {
+            return Err(ParseError(
+                "BYDAY ordinals beyond 5 are only valid for YEARLY without BYMONTH or BYMONTHDAY"
+                    .into(),
+            ));
+        }
+
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for RRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", freq)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%d"))?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|(n, d)| match n {
+                    Some(n) => format!("{}{}", n, weekday_code(*d)),
+                    None => weekday_code(*d).into(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|d| d.to_string()).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(|m| m.to_string()).collect();
+            write!(f, ";BYMONTH={}", months.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl RRule {
+    /// Every occurrence on or after `dtstart` and on or before `end`, in order. `COUNT` is
+    /// counted from `dtstart`, so it is honoured however early `end` cuts the expansion off.
+    pub fn occurrences(&self, dtstart: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+        let end = match self.until {
+            Some(until) if until < end => until,
+            _ => end,
+        };
+
+        let mut dates = Vec::new();
+        let mut period = 0;
+        loop {
+            let (period_start, candidates) = self.period(dtstart, period);
+            if period_start > end {
+                break;
+            }
+
+            for date in candidates {
+                if date < dtstart || date > end {
+                    continue;
+                }
+                if let Some(count) = self.count {
+                    if dates.len() as u32 >= count {
+                        return dates;
+                    }
+                }
+                dates.push(date);
+            }
+
+            period += self.interval as i64;
+        }
+
+        dates
+    }
+
+    /// The first day of the `n`th period after the one containing `dtstart`, and the sorted
+    /// dates within that period which match the rule.
+    fn period(&self, dtstart: NaiveDate, n: i64) -> (NaiveDate, Vec<NaiveDate>) {
+        match self.freq {
+            Frequency::Daily => {
+                let date = dtstart + Duration::days(n);
+                let matches = self.matches_month(date)
+                    && self.matches_month_day(date)
+                    && (self.by_day.is_empty()
+                        || self.by_day.iter().any(|(_, d)| *d == date.weekday()));
+                (date, if matches { vec![date] } else { vec![] })
+            }
+            Frequency::Weekly => {
+                let monday = dtstart
+                    - Duration::days(dtstart.weekday().num_days_from_monday() as i64)
+                    + Duration::weeks(n);
+                let mut dates: Vec<NaiveDate> = if self.by_day.is_empty() {
+                    vec![monday + Duration::days(dtstart.weekday().num_days_from_monday() as i64)]
+                } else {
+                    self.by_day
+                        .iter()
+                        .map(|(_, d)| monday + Duration::days(d.num_days_from_monday() as i64))
+                        .collect()
+                };
+                dates.retain(|d| self.matches_month(*d));
+                dates.sort();
+                dates.dedup();
+                (monday, dates)
+            }
+            Frequency::Monthly => {
+                let months = dtstart.year() as i64 * 12 + dtstart.month0() as i64 + n;
+                let first = NaiveDate::from_ymd(
+                    months.div_euclid(12) as i32,
+                    months.rem_euclid(12) as u32 + 1,
+                    1,
+                );
+                let dates = if self.matches_month(first) {
+                    self.days_in_month(dtstart, first)
+                } else {
+                    vec![]
+                };
+                (first, dates)
+            }
+            Frequency::Yearly => {
+                let year = dtstart.year() + n as i32;
+                let first = NaiveDate::from_ymd(year, 1, 1);
+                // As RFC 5545 has it, BYMONTHDAY or BYDAY alone expand across the whole year,
+                // and BYDAY ordinals then count weeks of the year rather than of the month.
+                let months = if !self.by_month.is_empty() {
+                    self.by_month.clone()
+                } else if !self.by_month_day.is_empty() {
+                    (1..=12).collect()
+                } else if !self.by_day.is_empty() {
+                    return (first, self.days_in_year(first));
+                } else {
+                    vec![dtstart.month()]
+                };
+                let mut dates: Vec<NaiveDate> = months
+                    .iter()
+                    .flat_map(|m| self.days_in_month(dtstart, NaiveDate::from_ymd(year, *m, 1)))
+                    .collect();
+                dates.sort();
+                dates.dedup();
+                (first, dates)
+            }
+        }
+    }
+
+    /// The days in the month starting at `first` selected by `BYMONTHDAY` and `BYDAY`, falling
+    /// back to `dtstart`'s day of the month when neither is set.
+    fn days_in_month(&self, dtstart: NaiveDate, first: NaiveDate) -> Vec<NaiveDate> {
+        let days: Vec<NaiveDate> = (0..days_in_month(first))
+            .map(|d| first + Duration::days(d as i64))
+            .collect();
+
+        let mut dates: Vec<NaiveDate> = days
+            .iter()
+            .copied()
+            .filter(|date| {
+                if self.by_month_day.is_empty() && self.by_day.is_empty() {
+                    return date.day() == dtstart.day();
+                }
+                self.matches_month_day(*date)
+                    && (self.by_day.is_empty()
+                        || self.by_day.iter().any(|(n, d)| {
+                            matches_by_day(*date, *n, *d, date.day(), days.len() as u32)
+                        }))
+            })
+            .collect();
+        dates.sort();
+        dates
+    }
+
+    /// The days in the year starting at `first` selected by `BYDAY`, with ordinals counted
+    /// within the year.
+    fn days_in_year(&self, first: NaiveDate) -> Vec<NaiveDate> {
+        let len = (NaiveDate::from_ymd(first.year() + 1, 1, 1) - first).num_days() as u32;
+        (0..len)
+            .map(|d| first + Duration::days(d as i64))
+            .filter(|date| {
+                self.by_day
+                    .iter()
+                    .any(|(n, d)| matches_by_day(*date, *n, *d, date.ordinal(), len))
+            })
+            .collect()
+    }
+
+    fn matches_month(&self, date: NaiveDate) -> bool {
+        self.by_month.is_empty() || self.by_month.contains(&date.month())
+    }
+
+    fn matches_month_day(&self, date: NaiveDate) -> bool {
+        if self.by_month_day.is_empty() {
+            return true;
+        }
+        let len = days_in_month(date.with_day(1).expect("every month has a first day")) as i32;
+        self.by_month_day
+            .iter()
+            .any(|d| *d == date.day() as i32 || len + d + 1 == date.day() as i32)
+    }
+}
+
+/// Whether `date`, the `nth` day of a month or year `len` days long, is the `n`th `day` in it.
+fn matches_by_day(date: NaiveDate, n: Option<i32>, day: Weekday, nth: u32, len: u32) -> bool {
+    if date.weekday() != day {
+        return false;
+    }
+    match n {
+        None => true,
+        Some(n) if n > 0 => (nth - 1) / 7 + 1 == n as u32,
+        Some(n) => (len - nth) / 7 + 1 == n.unsigned_abs(),
+    }
+}
+
+fn days_in_month(first: NaiveDate) -> u32 {
+    let next = if first.month() == 12 {
+        NaiveDate::from_ymd(first.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd(first.year(), first.month() + 1, 1)
+    };
+    (next - first).num_days() as u32
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, ParseError> {
+    value
+        .parse()
+        .map_err(|_| ParseError(format!("{} must be a number, got '{}'", key, value)))
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, ParseError> {
+    // UNTIL may be a DATE or a DATE-TIME. Positions are whole days, so only the date matters.
+    let date = value.get(..8).unwrap_or(value);
+    NaiveDate::parse_from_str(date, "%Y%m%d")
+        .map_err(|_| ParseError(format!("UNTIL must be YYYYMMDD, got '{}'", value)))
+}
+
+fn parse_by_day(value: &str) -> Result<(Option<i32>, Weekday), ParseError> {
+    let value = value.trim();
+    if value.len() < 2 {
+        return Err(ParseError(format!("invalid BYDAY '{}'", value)));
+    }
+    let (n, code) = value.split_at(value.len() - 2);
+    let day = match code.to_ascii_uppercase().as_str() {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(ParseError(format!("invalid BYDAY '{}'", value))),
+    };
+    let n = if n.is_empty() {
+        None
+    } else {
+        let n: i32 = parse_number("BYDAY", n.trim_start_matches('+'))?;
+        if n == 0 || n.abs() > 53 {
+            return Err(ParseError(format!("invalid BYDAY ordinal '{}'", value)));
+        }
+        Some(n)
+    };
+    Ok((n, day))
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+#[cfg(test)]
+mod rrule_tests {
+    use crate::rrule::RRule;
+    use chrono::NaiveDate;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(y, m, d)
+    }
+
+    #[test]
+    fn test_weekly_by_day() {
+        let rule: RRule = "RRULE:FREQ=WEEKLY;BYDAY=MO,WE".parse().unwrap();
+        let dates = rule.occurrences(date(2022, 11, 2), date(2022, 11, 14));
+
+        assert_eq!(
+            vec![
+                date(2022, 11, 2),
+                date(2022, 11, 7),
+                date(2022, 11, 9),
+                date(2022, 11, 14)
+            ],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_interval_and_count() {
+        let rule: RRule = "FREQ=DAILY;INTERVAL=3;COUNT=3".parse().unwrap();
+        let dates = rule.occurrences(date(2022, 12, 30), date(2023, 12, 31));
+
+        assert_eq!(
+            vec![date(2022, 12, 30), date(2023, 1, 2), date(2023, 1, 5)],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_monthly_last_friday_until() {
+        let rule: RRule = "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20230301T000000Z"
+            .parse()
+            .unwrap();
+        let dates = rule.occurrences(date(2022, 11, 1), date(2023, 12, 31));
+
+        assert_eq!(
+            vec![
+                date(2022, 11, 25),
+                date(2022, 12, 30),
+                date(2023, 1, 27),
+                date(2023, 2, 24)
+            ],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_monthly_skips_short_months() {
+        let rule: RRule = "FREQ=MONTHLY".parse().unwrap();
+        let dates = rule.occurrences(date(2023, 1, 31), date(2023, 5, 31));
+
+        assert_eq!(
+            vec![date(2023, 1, 31), date(2023, 3, 31), date(2023, 5, 31)],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_yearly_by_month() {
+        let rule: RRule = "FREQ=YEARLY;BYMONTH=1,7;BYMONTHDAY=-1".parse().unwrap();
+        let dates = rule.occurrences(date(2022, 1, 1), date(2023, 6, 30));
+
+        assert_eq!(
+            vec![date(2022, 1, 31), date(2022, 7, 31), date(2023, 1, 31)],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_yearly_expands_across_the_year() {
+        let rule: RRule = "FREQ=YEARLY;BYMONTHDAY=1".parse().unwrap();
+        let dates = rule.occurrences(date(2022, 10, 15), date(2023, 2, 28));
+        assert_eq!(
+            vec![
+                date(2022, 11, 1),
+                date(2022, 12, 1),
+                date(2023, 1, 1),
+                date(2023, 2, 1)
+            ],
+            dates
+        );
+
+        // The 20th Monday of the year, and the last Friday of it.
+        let rule: RRule = "FREQ=YEARLY;BYDAY=20MO,-1FR".parse().unwrap();
+        let dates = rule.occurrences(date(2022, 1, 1), date(2023, 12, 31));
+        assert_eq!(
+            vec![
+                date(2022, 5, 16),
+                date(2022, 12, 30),
+                date(2023, 5, 15),
+                date(2023, 12, 29)
+            ],
+            dates
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let s = "FREQ=MONTHLY;INTERVAL=2;COUNT=4;BYDAY=1MO,-1FR";
+        assert_eq!(s, s.parse::<RRule>().unwrap().to_string());
+    }
+
+    #[test]
+    fn test_rejects_unsupported() {
+        assert!("BYDAY=MO".parse::<RRule>().is_err());
+        assert!("FREQ=HOURLY".parse::<RRule>().is_err());
+        assert!("FREQ=WEEKLY;BYSETPOS=1".parse::<RRule>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=1MO".parse::<RRule>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=MO;BYMONTHDAY=1"
+            .parse::<RRule>()
+            .is_err());
+        assert!("FREQ=MONTHLY;BYDAY=6MO".parse::<RRule>().is_err());
+        assert!("FREQ=YEARLY;BYMONTH=3;BYDAY=20MO".parse::<RRule>().is_err());
+        assert!("FREQ=DAILY;COUNT=2;UNTIL=20221231"
+            .parse::<RRule>()
+            .is_err());
+    }
+}
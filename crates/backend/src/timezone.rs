@@ -0,0 +1,91 @@
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// Converts a wall-clock time in `tz` to an instant, resolving DST transitions the way
+/// RFC 5545 does:
+///
+/// - A time skipped by a spring-forward gap uses the offset in effect before the gap, so 02:30 on
+///   a day which jumps from 02:00 to 03:00 becomes 03:30.
+/// - A time repeated by a fall-back overlap resolves to its first occurrence.
+pub fn to_instant(tz: Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.with_timezone(&Utc),
+        LocalResult::None => {
+            let offset = offset_before(tz, local);
+            Utc.from_utc_datetime(&(local - Duration::seconds(offset)))
+        }
+    }
+}
+
+/// The UTC offset in seconds in effect just before the gap containing `local`.
+fn offset_before(tz: Tz, local: NaiveDateTime) -> i64 {
+    let mut before = local;
+    loop {
+        before -= Duration::minutes(15);
+        if let LocalResult::Single(t) | LocalResult::Ambiguous(t, _) =
+            tz.from_local_datetime(&before)
+        {
+            return t.offset().fix().local_minus_utc() as i64;
+        }
+    }
+}
+
+/// The instant `date` begins in `tz`. On a day starting in a DST gap, that's the end of the gap.
+pub fn midnight(tz: Tz, date: NaiveDate) -> DateTime<Utc> {
+    to_instant(tz, date.and_hms(0, 0, 0))
+}
+
+/// The date in `tz` at instant `t`.
+pub fn local_date(tz: Tz, t: DateTime<Utc>) -> NaiveDate {
+    t.with_timezone(&tz).date_naive()
+}
+
+pub fn parse(name: &str) -> Result<Tz, sqlx::Error> {
+    name.parse::<Tz>()
+        .map_err(|e| sqlx::Error::Decode(e.into()))
+}
+
+#[cfg(test)]
+mod timezone_tests {
+    use crate::timezone::{parse, to_instant};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use chrono_tz::America::New_York;
+
+    #[test]
+    fn test_to_instant() {
+        let local = NaiveDate::from_ymd(2022, 7, 1).and_hms(9, 0, 0);
+        assert_eq!(
+            Utc.ymd(2022, 7, 1).and_hms(13, 0, 0),
+            to_instant(New_York, local)
+        );
+    }
+
+    #[test]
+    fn test_spring_forward_gap() {
+        // 2023-03-12 jumps from 02:00 EST to 03:00 EDT.
+        let local = NaiveDate::from_ymd(2023, 3, 12).and_hms(2, 30, 0);
+        let instant = to_instant(New_York, local);
+
+        assert_eq!(Utc.ymd(2023, 3, 12).and_hms(7, 30, 0), instant);
+        assert_eq!(
+            NaiveDate::from_ymd(2023, 3, 12).and_hms(3, 30, 0),
+            instant.with_timezone(&New_York).naive_local()
+        );
+    }
+
+    #[test]
+    fn test_fall_back_repeat() {
+        // 2022-11-06 runs 01:00-02:00 twice, first in EDT then in EST.
+        let local = NaiveDate::from_ymd(2022, 11, 6).and_hms(1, 30, 0);
+        assert_eq!(
+            Utc.ymd(2022, 11, 6).and_hms(5, 30, 0),
+            to_instant(New_York, local)
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(New_York, parse("America/New_York").unwrap());
+        assert!(parse("Mars/Olympus_Mons").is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{self, DataStruct, DeriveInput, Fields, Ident, Type};
+use syn::{
+    self, punctuated::Punctuated, DataStruct, DeriveInput, Fields, Ident, Path, Token, Type,
+};
 
-#[proc_macro_derive(Resource, attributes(primary_key))]
+#[proc_macro_derive(Resource, attributes(primary_key, unique, references))]
 pub fn resource_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).expect("input should be parsable");
 
@@ -13,76 +15,273 @@ fn impl_resource(ast: &DeriveInput) -> TokenStream {
     let name = &ast.ident;
     let table_name = to_snake_case(&ast.ident.to_string());
 
-    let ((primary_key, primary_key_dt), fields) = get_fields(&ast);
+    let ((primary_key, primary_key_dt), fields) = get_fields(ast);
+    let (unique, (ref_fields, ref_types)) = get_constraints(ast);
 
-    let gen = quote! {
-        #[async_trait::async_trait]
-        impl Resource for #name {
-            async fn create(&self, pool: &sqlx::postgres::PgPool) -> Result<sqlx::postgres::PgQueryResult, sqlx::Error> {
-                let mut query: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("INSERT INTO ");
-                query.push(#table_name)
-                    .push(" (")
-                    .push(stringify!(#(#fields),*))
-                    .push(") VALUES (");
+    // One implementation per supported database. The SQL is shared and `QueryBuilder` takes care
+    // of each dialect's bind parameter syntax.
+    let backends: Vec<_> = [quote!(sqlx::Postgres), quote!(sqlx::Sqlite)]
+        .iter()
+        .map(|db| {
+            quote! {
+                #[async_trait::async_trait]
+                impl crate::models::resource::Resource<#db> for #name {
+                    async fn create<'c, A>(&self, conn: A) -> Result<<#db as sqlx::Database>::QueryResult, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("INSERT INTO ");
+                        query.push(#table_name)
+                            .push(" (")
+                            .push(stringify!(#(#fields),*))
+                            .push(") VALUES (");
 
-                let mut sep = query.separated(", ");
-                #(sep.push_bind(self.#fields.clone());)*
+                        let mut sep = query.separated(", ");
+                        #(sep.push_bind(self.#fields.clone());)*
 
-                query.push(")");
+                        query.push(")");
 
-                query.build().execute(pool).await
-            }
+                        let mut conn = conn.acquire().await?;
+                        query.build().execute(&mut *conn).await
+                    }
 
-            async fn get_all(pool: &sqlx::postgres::PgPool) -> Result<Vec<Self>, sqlx::Error> {
-                let mut query: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("SELECT * FROM ");
-                query
-                    .push(#table_name)
-                    .push(" ORDER BY ")
-                    .push(stringify!(#primary_key));
+                    async fn insert<'c, A>(&self, conn: A) -> Result<Self, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("INSERT INTO ");
+                        query.push(#table_name)
+                            .push(" (")
+                            .push(stringify!(#(#fields),*))
+                            .push(") VALUES (");
 
-                query.build_query_as().fetch_all(pool).await
-            }
+                        let mut sep = query.separated(", ");
+                        #(sep.push_bind(self.#fields.clone());)*
+
+                        query.push(") RETURNING *");
+
+                        // Step the statement to completion. SQLite doesn't commit an insert
+                        // until then, so other connections may not see the row yet.
+                        let mut conn = conn.acquire().await?;
+                        query
+                            .build_query_as()
+                            .fetch_all(&mut *conn)
+                            .await?
+                            .pop()
+                            .ok_or(sqlx::Error::RowNotFound)
+                    }
+
+                    async fn upsert<'c, A>(&self, conn: A, key: &[&'static str]) -> Result<Self, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("INSERT INTO ");
+                        query.push(#table_name)
+                            .push(" (")
+                            .push(stringify!(#(#fields),*))
+                            .push(") VALUES (");
+
+                        let mut sep = query.separated(", ");
+                        #(sep.push_bind(self.#fields.clone());)*
+
+                        query.push(") ON CONFLICT (")
+                            .push(key.join(", "))
+                            .push(") DO UPDATE SET ");
+
+                        let mut sep = query.separated(", ");
+                        #(sep.push(concat!(stringify!(#fields), " = excluded.", stringify!(#fields)));)*
+
+                        query.push(" RETURNING *");
+
+                        let mut conn = conn.acquire().await?;
+                        query
+                            .build_query_as()
+                            .fetch_all(&mut *conn)
+                            .await?
+                            .pop()
+                            .ok_or(sqlx::Error::RowNotFound)
+                    }
+
+                    async fn get_all<'c, A>(conn: A) -> Result<Vec<Self>, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("SELECT * FROM ");
+                        query
+                            .push(#table_name)
+                            .push(" ORDER BY ")
+                            .push(stringify!(#primary_key));
+
+                        let mut conn = conn.acquire().await?;
+                        query.build_query_as().fetch_all(&mut *conn).await
+                    }
+
+                    fn stream_all<'c, A>(conn: A) -> futures::stream::BoxStream<'c, Result<Self, sqlx::Error>>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        <Self as crate::models::resource::Resource<#db>>::stream(conn, &crate::models::resource::Filter::new())
+                    }
+
+                    fn stream<'c, A>(conn: A, filter: &crate::models::resource::Filter) -> futures::stream::BoxStream<'c, Result<Self, sqlx::Error>>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let filter = filter.clone();
+
+                        Box::pin(async_stream::try_stream! {
+                            let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("SELECT * FROM ");
+                            query.push(#table_name);
+                            filter.push_where(&mut query);
+                            query
+                                .push(" ORDER BY ")
+                                .push(stringify!(#primary_key));
+
+                            let mut conn = conn.acquire().await?;
+                            let mut rows = query.build_query_as::<Self>().fetch(&mut *conn);
+                            while let Some(row) = futures::TryStreamExt::try_next(&mut rows).await? {
+                                yield row;
+                            }
+                        })
+                    }
+
+                    async fn update<'c, A>(&self, conn: A) -> Result<<#db as sqlx::Database>::QueryResult, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("UPDATE ");
+                        query.push(#table_name)
+                            .push(" SET ");
+
+                        let mut sep = query.separated(", ");
+                        #(sep.push(stringify!(#fields)).push_unseparated(" = ").push_bind_unseparated(self.#fields.clone());)*
+
+                        query.push(" WHERE ")
+                            .push(stringify!(#primary_key))
+                            .push(" = ")
+                            .push_bind(self.#primary_key.clone());
+
+                        let mut conn = conn.acquire().await?;
+                        query.build().execute(&mut *conn).await
+                    }
+
+                    async fn delete<'c, A>(&self, conn: A) -> Result<<#db as sqlx::Database>::QueryResult, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("DELETE FROM ");
+                        query.push(#table_name)
+                            .push(" WHERE ")
+                            .push(stringify!(#primary_key))
+                            .push(" = ")
+                            .push_bind(self.#primary_key);
+
+                        let mut conn = conn.acquire().await?;
+                        query.build().execute(&mut *conn)
+                        .await
+                    }
+
+                    async fn update_where<'c, A>(
+                        conn: A,
+                        columns: &[(&'static str, Option<crate::models::resource::Value>)],
+                        filter: &crate::models::resource::Filter,
+                    ) -> Result<<#db as sqlx::Database>::QueryResult, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("UPDATE ");
+                        query.push(#table_name)
+                            .push(" SET ");
+
+                        for (i, (column, value)) in columns.iter().enumerate() {
+                            if i > 0 {
+                                query.push(", ");
+                            }
+                            query.push(column).push(" = ");
+                            match value {
+                                Some(v) => v.push_bind(&mut query),
+                                None => {
+                                    query.push("NULL");
+                                }
+                            }
+                        }
+                        filter.push_where(&mut query);
+
+                        let mut conn = conn.acquire().await?;
+                        query.build().execute(&mut *conn).await
+                    }
+
+                    async fn count<'c, A>(conn: A, filter: &crate::models::resource::Filter) -> Result<i64, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("SELECT count(*) FROM ");
+                        query.push(#table_name);
+                        filter.push_where(&mut query);
+
+                        let mut conn = conn.acquire().await?;
+                        let (count,): (i64,) = query.build_query_as().fetch_one(&mut *conn).await?;
+                        Ok(count)
+                    }
 
-            async fn update(&self, pool: &sqlx::postgres::PgPool) -> Result<sqlx::postgres::PgQueryResult, sqlx::Error> {
-                let mut query: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("UPDATE ");
-                query.push(#table_name)
-                    .push(" SET ");
+                    async fn exists<'c, A>(conn: A, filter: &crate::models::resource::Filter) -> Result<bool, sqlx::Error>
+                    where
+                        A: sqlx::Acquire<'c, Database = #db> + Send + 'c,
+                    {
+                        let mut query: sqlx::QueryBuilder<#db> = sqlx::QueryBuilder::new("SELECT EXISTS (SELECT 1 FROM ");
+                        query.push(#table_name);
+                        filter.push_where(&mut query);
+                        query.push(")");
 
-                let mut sep = query.separated(", ");
-                #(sep.push(stringify!(#fields)).push_unseparated(" = ").push_bind_unseparated(self.#fields.clone());)*
+                        let mut conn = conn.acquire().await?;
+                        let (exists,): (bool,) = query.build_query_as().fetch_one(&mut *conn).await?;
+                        Ok(exists)
+                    }
 
-                query.push(" WHERE ")
-                    .push(stringify!(#primary_key))
-                    .push(" = ")
-                    .push_bind(self.#primary_key.clone());
+                }
+            }
+        })
+        .collect();
+
+    let gen = quote! {
+        #(#backends)*
+
+        impl crate::models::resource::Record for #name {
+            const TABLE_NAME: &'static str = #table_name;
+            const PRIMARY_KEY: &'static str = stringify!(#primary_key);
+            const UNIQUE: &'static [&'static [&'static str]] = &[#(&[#(stringify!(#unique)),*]),*];
+            const REFERENCES: &'static [(&'static str, &'static str)] = &[
+                #((stringify!(#ref_fields), <#ref_types as crate::models::resource::Record>::TABLE_NAME)),*
+            ];
 
-                query.build().execute(pool).await
+            fn id(&self) -> i64 {
+                self.#primary_key
             }
 
-            async fn delete(&self, pool: &sqlx::postgres::PgPool) -> Result<sqlx::postgres::PgQueryResult, sqlx::Error> {
-                let mut query: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("DELETE FROM ");
-                query.push(#table_name)
-                    .push(" WHERE ")
-                    .push(stringify!(#primary_key))
-                    .push(" = ")
-                    .push_bind(self.#primary_key);
+            fn set_id(&mut self, id: i64) {
+                self.#primary_key = id;
+            }
 
-                query.build().execute(pool)
-                .await
+            fn columns(&self) -> Vec<(&'static str, Option<crate::models::resource::Value>)> {
+                vec![
+                    (stringify!(#primary_key), crate::models::resource::Value::of(&self.#primary_key)),
+                    #((stringify!(#fields), crate::models::resource::Value::of(&self.#fields))),*
+                ]
             }
         }
 
         impl #name {
-            async fn get(pool: &sqlx::postgres::PgPool, identifier: #primary_key_dt) -> Result<Self, sqlx::Error> {
-                let mut query: sqlx::QueryBuilder<sqlx::Postgres> = sqlx::QueryBuilder::new("SELECT * FROM ");
-                query
-                    .push(#table_name)
-                    .push(" WHERE ")
-                    .push(stringify!(#primary_key))
-                    .push(" = ")
-                    .push(identifier.clone());
-
-                query.build_query_as().fetch_one(pool).await
+            async fn get<'c, DB, A>(conn: A, identifier: #primary_key_dt) -> Result<Self, sqlx::Error>
+            where
+                DB: sqlx::Database,
+                A: sqlx::Acquire<'c, Database = DB> + Send + 'c,
+                Self: crate::models::resource::Resource<DB>,
+            {
+                let filter = crate::models::resource::Filter::new().eq(stringify!(#primary_key), identifier);
+
+                futures::TryStreamExt::try_next(&mut <Self as crate::models::resource::Resource<DB>>::stream(conn, &filter))
+                    .await?
+                    .ok_or(sqlx::Error::RowNotFound)
             }
         }
     };
@@ -148,3 +347,49 @@ fn get_fields(ast: &DeriveInput) -> ((Ident, Type), Vec<Ident>) {
         fields,
     )
 }
+
+/// The fields making up a unique key.
+type Key = Vec<Ident>;
+
+/// The unique keys, from `#[unique]` on a field or `#[unique(a, b)]` on the struct for one over
+/// several columns, and the fields marked `#[references(Type)]` with their types.
+fn get_constraints(ast: &DeriveInput) -> (Vec<Key>, (Vec<Ident>, Vec<Path>)) {
+    let mut unique: Vec<Key> = ast
+        .attrs
+        .iter()
+        .filter(|a| a.path.is_ident("unique"))
+        .map(|a| {
+            a.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+                .expect("unique on a struct should list the key's fields")
+                .into_iter()
+                .collect()
+        })
+        .collect();
+    let mut ref_fields = Vec::new();
+    let mut ref_types = Vec::new();
+
+    let data = match &ast.data {
+        syn::Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => panic!("Only structs with named fields can derive Resource"),
+    };
+
+    for d in data {
+        let ident = d.ident.clone().expect("field should be named");
+        for a in &d.attrs {
+            if a.path.is_ident("unique") {
+                unique.push(vec![ident.clone()]);
+            } else if a.path.is_ident("references") {
+                let referenced: Path = a
+                    .parse_args()
+                    .expect("references should name the referenced Resource");
+                ref_fields.push(ident.clone());
+                ref_types.push(referenced);
+            }
+        }
+    }
+
+    (unique, (ref_fields, ref_types))
+}